@@ -0,0 +1,11 @@
+CREATE TABLE IF NOT EXISTS reactions(
+  ID          INTEGER PRIMARY KEY AUTOINCREMENT,
+  -- message ids come from the history file. the channel scopes them
+  -- and is used to clean up and for permission checks
+  channel_id  INTEGER     NOT NULL,
+  message_id  INTEGER     NOT NULL,
+  user_id     INTEGER     NOT NULL,
+  emoji       TEXT        NOT NULL,
+  created_at  TEXT        NOT NULL,
+  UNIQUE(channel_id, message_id, user_id, emoji)
+);
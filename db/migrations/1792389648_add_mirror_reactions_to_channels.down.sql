@@ -0,0 +1 @@
+ALTER TABLE channels DROP COLUMN mirror_reactions;
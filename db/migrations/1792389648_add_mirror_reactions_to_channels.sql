@@ -0,0 +1,2 @@
+-- send a "* nick reacted" line to irc when someone reacts in this channel
+ALTER TABLE channels ADD COLUMN mirror_reactions INTEGER NOT NULL DEFAULT 0;
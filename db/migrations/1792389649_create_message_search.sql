@@ -0,0 +1,10 @@
+-- full text index over all bridged messages
+-- the rowid is the message id used by the history and the websocket protocol
+CREATE VIRTUAL TABLE IF NOT EXISTS message_search USING fts5(
+  content,
+  author,
+  server_id   UNINDEXED,
+  channel_id  UNINDEXED,
+  created_at  UNINDEXED, -- rfc3339 utc so it can be compared as text
+  tokenize = 'unicode61'
+);
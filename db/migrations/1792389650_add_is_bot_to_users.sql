@@ -0,0 +1,2 @@
+-- bot accounts can not log in with a password and only use api tokens
+ALTER TABLE users ADD COLUMN is_bot INTEGER NOT NULL DEFAULT 0;
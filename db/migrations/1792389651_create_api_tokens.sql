@@ -0,0 +1,12 @@
+CREATE TABLE IF NOT EXISTS api_tokens(
+  ID           INTEGER PRIMARY KEY AUTOINCREMENT,
+  user_id      INTEGER     NOT NULL,
+  name         TEXT        NOT NULL,
+  -- hex encoded sha256 of the token
+  -- the token itself is only shown once when it is created
+  token_hash   TEXT UNIQUE NOT NULL,
+  -- comma separated list of read_history, send_messages and manage_webhooks
+  scopes       TEXT        NOT NULL,
+  created_at   TEXT        NOT NULL,
+  last_used_at TEXT
+);
@@ -0,0 +1,11 @@
+CREATE TABLE IF NOT EXISTS subscriptions(
+  ID         INTEGER PRIMARY KEY AUTOINCREMENT,
+  url        TEXT    NOT NULL,
+  -- key of the hmac-sha256 signature sent with every delivery
+  secret     TEXT    NOT NULL,
+  -- comma separated list of message_created, user_joined, mention and moderation
+  events     TEXT    NOT NULL,
+  -- only events of this channel. NULL means all channels
+  channel_id INTEGER,
+  created_at TEXT    NOT NULL
+);
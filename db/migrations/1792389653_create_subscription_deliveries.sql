@@ -0,0 +1,13 @@
+CREATE TABLE IF NOT EXISTS subscription_deliveries(
+  ID              INTEGER PRIMARY KEY AUTOINCREMENT,
+  subscription_id INTEGER NOT NULL,
+  -- shared by all attempts to deliver the same event
+  delivery_id     TEXT    NOT NULL,
+  event           TEXT    NOT NULL,
+  attempt         INTEGER NOT NULL,
+  -- http status of the response. NULL if there was none
+  status_code     INTEGER,
+  error           TEXT,
+  success         INTEGER NOT NULL DEFAULT 0,
+  created_at      TEXT    NOT NULL
+);
@@ -0,0 +1,7 @@
+-- users and servers created by `db-cli seed`. clean only deletes these
+-- and the rows hanging off them so real rows with the same names are safe
+CREATE TABLE IF NOT EXISTS seed_rows(
+  table_name TEXT    NOT NULL,
+  row_id     INTEGER NOT NULL,
+  PRIMARY KEY(table_name, row_id)
+);
@@ -0,0 +1,82 @@
+//! Uniform replies to client events. Every event is answered with an [`Ack`],
+//! through a socket.io acknowledgement or an `ack` frame on the plain websocket.
+//! The codes are documented in docs/websocket-protocol.md
+
+use serde::Serialize;
+use serde_json::Value;
+use thiserror::Error;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    // the plain websocket frame is not valid json or no envelope
+    InvalidFrame,
+    UnknownOp,
+    // the event data does not have the expected shape
+    InvalidPayload,
+    InvalidInput,
+    NotLoggedIn,
+    InvalidToken,
+    WrongCredentials,
+    AccountBlocked,
+    UsernameTaken,
+    PermissionDenied,
+    ServerNotFound,
+    ChannelNotFound,
+    // the session is in a different channel than the event targets
+    WrongChannel,
+    PrivateChannel,
+    MessageNotFound,
+    RateLimited,
+    // an admin muted the account with /mute
+    Muted,
+    // a plugin refused the message
+    Vetoed,
+    IrcSendFailed,
+    Internal,
+}
+
+#[derive(Debug, Clone, Serialize, Error)]
+#[error("{message}")]
+pub struct EventError {
+    pub code: ErrorCode,
+    pub message: String,
+    // seconds until a rate limited event can be sent again
+    #[serde(rename = "retryAfter", skip_serializing_if = "Option::is_none")]
+    pub retry_after: Option<u64>,
+}
+
+impl EventError {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self { code, message: message.into(), retry_after: None }
+    }
+
+    pub fn with_retry_after(mut self, secs: u64) -> Self {
+        self.retry_after = Some(secs);
+        self
+    }
+
+    pub fn not_logged_in() -> Self {
+        Self::new(ErrorCode::NotLoggedIn, "please login to your account")
+    }
+}
+
+pub type EventResult = Result<Value, EventError>;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Ack {
+    pub success: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<EventError>,
+}
+
+impl From<EventResult> for Ack {
+    fn from(res: EventResult) -> Self {
+        match res {
+            Ok(data) => Ack { success: true, data: (!data.is_null()).then_some(data), error: None },
+            Err(e) => Ack { success: false, data: None, error: Some(e) },
+        }
+    }
+}
@@ -0,0 +1,128 @@
+//! Shared pieces of the versioned REST API under /api/v1
+
+use axum::{http::{header, StatusCode}, response::{IntoResponse, Response}, Json};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use thiserror::Error;
+use utoipa::ToSchema;
+
+use crate::ack::{ErrorCode, EventError};
+
+pub const INVALID_SESSION: &str = "invalid session token or not logged in to an account";
+pub const ADMIN_AUTH_REQUIRED: &str = "Authentication is required please set the bearer authorization header.";
+
+#[derive(Debug, Error)]
+pub enum ApiError {
+    #[error("{0}")]
+    BadRequest(String),
+    #[error("{0}")]
+    Unauthorized(String),
+    #[error("{0}")]
+    Forbidden(String),
+    #[error("{0}")]
+    NotFound(String),
+    // retry_after is sent as Retry-After header in seconds
+    #[error("{message}")]
+    TooManyRequests { message: String, retry_after: Option<u64> },
+    #[error("{0}")]
+    Internal(String),
+    // a service the request depends on, like irc, failed
+    #[error("{0}")]
+    BadGateway(String),
+}
+
+impl ApiError {
+    pub fn invalid_session() -> Self {
+        ApiError::Unauthorized(INVALID_SESSION.into())
+    }
+
+    pub fn admin_auth_required() -> Self {
+        ApiError::Unauthorized(ADMIN_AUTH_REQUIRED.into())
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ApiError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ApiError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            ApiError::Forbidden(_) => StatusCode::FORBIDDEN,
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::TooManyRequests { .. } => StatusCode::TOO_MANY_REQUESTS,
+            ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            ApiError::BadGateway(_) => StatusCode::BAD_GATEWAY,
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::BadRequest(_) => "bad_request",
+            ApiError::Unauthorized(_) => "unauthorized",
+            ApiError::Forbidden(_) => "forbidden",
+            ApiError::NotFound(_) => "not_found",
+            ApiError::TooManyRequests { .. } => "too_many_requests",
+            ApiError::Internal(_) => "internal",
+            ApiError::BadGateway(_) => "bad_gateway",
+        }
+    }
+}
+
+/// Errors of the modules shared with the socket events
+impl From<EventError> for ApiError {
+    fn from(e: EventError) -> Self {
+        match e.code {
+            ErrorCode::NotLoggedIn | ErrorCode::InvalidToken | ErrorCode::WrongCredentials => ApiError::Unauthorized(e.message),
+            ErrorCode::AccountBlocked | ErrorCode::PermissionDenied | ErrorCode::PrivateChannel | ErrorCode::WrongChannel
+                | ErrorCode::Muted | ErrorCode::Vetoed => ApiError::Forbidden(e.message),
+            ErrorCode::ServerNotFound | ErrorCode::ChannelNotFound | ErrorCode::MessageNotFound => ApiError::NotFound(e.message),
+            ErrorCode::RateLimited => ApiError::TooManyRequests { message: e.message, retry_after: e.retry_after },
+            ErrorCode::IrcSendFailed => ApiError::BadGateway(e.message),
+            ErrorCode::Internal => ApiError::Internal(e.message),
+            ErrorCode::InvalidFrame | ErrorCode::UnknownOp | ErrorCode::InvalidPayload | ErrorCode::InvalidInput
+                | ErrorCode::UsernameTaken => ApiError::BadRequest(e.message),
+        }
+    }
+}
+
+/// Body of every error response
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ErrorBody {
+    pub error: String,
+    // bad_request, unauthorized, forbidden, not_found, too_many_requests, internal or bad_gateway
+    pub code: String,
+}
+
+/// Body of endpoints that have nothing else to report
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct MessageResponse {
+    pub message: String,
+}
+
+impl MessageResponse {
+    pub fn ok() -> Json<Self> {
+        Json(MessageResponse { message: "OK".into() })
+    }
+}
+
+/// Marks error responses so the unversioned aliases can turn them back into
+/// the old `200 {"error": ...}` shape
+#[derive(Debug, Clone)]
+struct LegacyError(String);
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let body = ErrorBody { error: self.to_string(), code: self.code().into() };
+        let mut res = (self.status(), Json(body)).into_response();
+        if let ApiError::TooManyRequests { retry_after: Some(secs), .. } = &self {
+            res.headers_mut().insert(header::RETRY_AFTER, (*secs).into());
+        }
+        res.extensions_mut().insert(LegacyError(self.to_string()));
+        res
+    }
+}
+
+/// Response mapper for the old unversioned routes
+pub async fn legacy_errors(res: Response) -> Response {
+    match res.extensions().get::<LegacyError>() {
+        Some(LegacyError(message)) => Json(json!({"error": message})).into_response(),
+        None => res,
+    }
+}
@@ -0,0 +1,102 @@
+//! Personal access tokens for bots and external clients. They are sent as
+//! `Authorization: Bearer <token>`. Only the sha256 of a token is stored,
+//! the token itself is shown once when it is created.
+
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use utoipa::ToSchema;
+
+use crate::{
+    ack::{ErrorCode, EventError},
+    models::{api_token::{self, ApiTokenRow}, user::{self, UserRow}},
+    util,
+};
+
+// tells api tokens apart from the admin token in the same header
+pub const TOKEN_PREFIX: &str = "iwt_";
+pub const MAX_TOKENS_PER_USER: usize = 25;
+const MAX_NAME_LEN: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Scope {
+    // messages, search and read markers
+    ReadHistory,
+    // messages and reactions
+    SendMessages,
+    ManageWebhooks,
+}
+
+impl Scope {
+    pub const ALL: [Scope; 3] = [Scope::ReadHistory, Scope::SendMessages, Scope::ManageWebhooks];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Scope::ReadHistory => "read_history",
+            Scope::SendMessages => "send_messages",
+            Scope::ManageWebhooks => "manage_webhooks",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|scope| scope.as_str() == s)
+    }
+}
+
+/// A token as the owner sees it. `token` is only set right after creating it.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ApiTokenInfo {
+    pub id: i64,
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    #[serde(rename = "createdAt")] pub created_at: String,
+    #[serde(rename = "lastUsedAt")] pub last_used_at: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+}
+
+impl From<ApiTokenRow> for ApiTokenInfo {
+    fn from(row: ApiTokenRow) -> Self {
+        Self { id: row.id, scopes: scopes(&row), name: row.name, created_at: row.created_at, last_used_at: row.last_used_at, token: None }
+    }
+}
+
+pub fn hash(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+pub fn scopes(row: &ApiTokenRow) -> Vec<Scope> {
+    row.scopes.split(',').filter_map(Scope::parse).collect()
+}
+
+/// Stores a new token of `user_id`. The returned token can not be recovered later.
+pub fn create(conn: &Connection, user_id: i64, name: &str, scopes: &[Scope]) -> Result<ApiTokenInfo, EventError> {
+    let name = name.trim();
+    if name.is_empty() || name.len() > MAX_NAME_LEN {
+        return Err(EventError::new(ErrorCode::InvalidInput, format!("the name has to be between 1 and {MAX_NAME_LEN} characters long")));
+    }
+    if scopes.is_empty() { return Err(EventError::new(ErrorCode::InvalidInput, "a token needs at least one scope")); }
+    if api_token::for_user(conn, user_id).len() >= MAX_TOKENS_PER_USER {
+        return Err(EventError::new(ErrorCode::InvalidInput, format!("an account can have at most {MAX_TOKENS_PER_USER} tokens")));
+    }
+    let names: Vec<&str> = Scope::ALL.into_iter().filter(|s| scopes.contains(s)).map(Scope::as_str).collect();
+    let token = format!("{TOKEN_PREFIX}{}", util::generate_token(40));
+    let id = api_token::insert(conn, user_id, name, &hash(&token), &names.join(","))
+        .map_err(|e| EventError::new(ErrorCode::Internal, format!("failed to store the token: {e}")))?;
+    let row = api_token::find(conn, id).ok_or_else(|| EventError::new(ErrorCode::Internal, "failed to store the token"))?;
+    Ok(ApiTokenInfo { token: Some(token), ..row.into() })
+}
+
+/// The account of `token` if the token grants `scope`. Records the use.
+pub fn authenticate(conn: &Connection, token: &str, scope: Scope) -> Result<UserRow, EventError> {
+    let invalid = || EventError::new(ErrorCode::InvalidToken, "invalid api token");
+    let row = api_token::find_by_hash(conn, &hash(token)).ok_or_else(invalid)?;
+    let user = user::find(conn, row.user_id).ok_or_else(invalid)?;
+    if user.blocked() { return Err(EventError::new(ErrorCode::AccountBlocked, "this account is blocked")); }
+    if !scopes(&row).contains(&scope) {
+        return Err(EventError::new(ErrorCode::PermissionDenied, format!("the api token is missing the {} scope", scope.as_str())));
+    }
+    let _ = api_token::touch(conn, row.id);
+    Ok(user)
+}
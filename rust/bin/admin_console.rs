@@ -0,0 +1,472 @@
+use std::process::ExitCode;
+
+use anyhow::{anyhow, bail, Context, Result};
+use irc_websockets::{
+    cli::CliArgs,
+    config::Config,
+    db,
+    api_tokens::{self, Scope},
+    models::{api_token, channel, friend, server, subscription, subscription_delivery, user, webhook},
+    subscriptions::{self, EventType},
+    util,
+};
+use rusqlite::{types::ValueRef, Connection, OpenFlags};
+use rustyline::{error::ReadlineError, history::History, DefaultEditor};
+
+const HELP: &str = "\
+commands:
+  user <id|name>                        show a user
+  users [search]                        list users
+  block <id|name>                       block a user
+  unblock <id|name>                     unblock a user
+  bot create <name>                     create a bot account
+  bot <id|name> <on|off>                flag a user as bot
+  tokens <id|name>                      list the api tokens of a user
+  token create <id|name> <name> <scopes> create an api token. scopes are comma separated
+  token revoke <id>                     delete an api token
+  channels [server]                     list channels
+  channel <id>                          show a channel
+  channel set <id> <field> <value...>   edit a channel
+  servers                               list servers
+  server <id>                           show a server
+  server set <id> <field> <value...>    edit a server
+  webhooks [channel id]                 list webhooks
+  webhook rotate <id>                   give a webhook a new token
+  subscriptions                         list outgoing event subscriptions
+  subscription create <url> <secret> <events> [channel id]
+                                        subscribe a url. events are comma separated
+  subscription delete <id>              delete a subscription and its delivery log
+  deliveries <subscription id>          show the last delivery attempts
+  sql <statement>                       run raw sql
+  begin | commit | rollback             transaction control
+  status                                show transaction state
+  history                               show command history
+  help | exit";
+
+const CHANNEL_FIELDS: &str = "name, description, discord_server, discord_channel, irc_channel, irc_server_ip, irc_server_name, owner_id, is_private, mirror_reactions";
+const SERVER_FIELDS: &str = "name, discord_name, irc_name, irc_ip, icon_url, banner_url, owner_id";
+
+struct Console {
+    conn: Connection,
+    read_only: bool,
+    // every change happens in a transaction that has to be committed by hand
+    in_transaction: bool,
+    // exit with uncommitted changes has to be confirmed by a second exit
+    exit_warned: bool,
+}
+
+enum Next {
+    Continue,
+    Exit,
+}
+
+fn parse_flag(value: &str) -> Result<i64> {
+    match value {
+        "0" | "false" | "off" | "no" => Ok(0),
+        "1" | "true" | "on" | "yes" => Ok(1),
+        _ => bail!("expected 0 or 1 but got '{}'", value),
+    }
+}
+
+fn parse_id(value: Option<&str>) -> Result<i64> {
+    let value = value.ok_or_else(|| anyhow!("missing id"))?;
+    value.parse().with_context(|| format!("invalid id '{}'", value))
+}
+
+fn print_user(u: &user::UserRow, conn: &Connection) {
+    println!("id={} username={} admin={} blocked={} bot={} token={}", u.id, u.username, u.admin(), u.blocked(), u.bot(), if u.token.is_some() { "set" } else { "none" });
+    println!("  register_ip={} login_ip={} created_at={} updated_at={}", u.register_ip, u.login_ip, u.created_at, u.updated_at);
+    println!("  friends={} api_tokens={}", friend::for_user(conn, u.id).len(), api_token::for_user(conn, u.id).len());
+}
+
+fn print_api_token(t: &api_token::ApiTokenRow) {
+    println!("id={} user_id={} name={} scopes={} created_at={} last_used_at={}", t.id, t.user_id, t.name, t.scopes, t.created_at, t.last_used_at.as_deref().unwrap_or("never"));
+}
+
+fn print_subscription(s: &subscription::SubscriptionRow) {
+    let channel = s.channel_id.map_or("all".to_string(), |id| id.to_string());
+    println!("id={} url={} events={} channel_id={} created_at={}", s.id, s.url, s.events, channel, s.created_at);
+}
+
+fn print_delivery(d: &subscription_delivery::DeliveryRow) {
+    let status = d.status_code.map_or("none".to_string(), |c| c.to_string());
+    println!("id={} delivery={} event={} attempt={} success={} status={} error={} at={}", d.id, d.delivery_id, d.event, d.attempt, d.success == 1, status, d.error.as_deref().unwrap_or("none"), d.created_at);
+}
+
+fn print_channel(c: &channel::ChannelRow) {
+    println!(
+        "id={} server_id={} name={} discord={}#{} irc={}#{} ({}) private={} mirror_reactions={} owner_id={}",
+        c.id, c.server_id, c.name, c.discord_server, c.discord_channel, c.irc_server_name, c.irc_channel, c.irc_server_ip, c.is_private, c.mirror_reactions, c.owner_id
+    );
+    if !c.description.is_empty() { println!("  {}", c.description); }
+}
+
+fn print_server(s: &server::ServerRow) {
+    println!("id={} name={} discord_name={} irc={} ({}) owner_id={}", s.id, s.name, s.discord_name, s.irc_name, s.irc_ip, s.owner_id);
+    println!("  icon_url={} banner_url={}", s.icon_url, s.banner_url);
+}
+
+fn print_webhook(w: &webhook::WebhookRow) {
+    println!("id={} name={} server_id={} channel_id={} owner_id={} last_use_ip={} token={}", w.id, w.name, w.server_id, w.channel_id, w.owner_id, w.last_use_ip, w.token);
+}
+
+fn format_value(value: ValueRef) -> String {
+    match value {
+        ValueRef::Null => "NULL".into(),
+        ValueRef::Integer(i) => i.to_string(),
+        ValueRef::Real(f) => f.to_string(),
+        ValueRef::Text(t) => String::from_utf8_lossy(t).to_string(),
+        ValueRef::Blob(b) => format!("<{} bytes>", b.len()),
+    }
+}
+
+impl Console {
+    fn prompt(&self) -> &'static str {
+        match (self.read_only, self.in_transaction) {
+            (true, _) => "(read-only)> ",
+            (false, true) => "(uncommitted)> ",
+            (false, false) => "> ",
+        }
+    }
+
+    /// Opens the transaction before the first change
+    fn begin_write(&mut self) -> Result<()> {
+        if self.read_only { bail!("the console is in read-only mode"); }
+        if !self.in_transaction {
+            self.conn.execute_batch("BEGIN")?;
+            self.in_transaction = true;
+            println!("[*] started a transaction. nothing is saved until you type commit");
+        }
+        Ok(())
+    }
+
+    fn find_user(&self, key: Option<&str>) -> Result<user::UserRow> {
+        let key = key.ok_or_else(|| anyhow!("missing user id or name"))?;
+        let found = match key.parse::<i64>() {
+            Ok(id) => user::find(&self.conn, id),
+            Err(_) => user::find_by_username(&self.conn, key),
+        };
+        found.ok_or_else(|| anyhow!("user '{}' not found", key))
+    }
+
+    fn set_blocked(&mut self, key: Option<&str>, blocked: bool) -> Result<()> {
+        let mut u = self.find_user(key)?;
+        self.begin_write()?;
+        u.is_blocked = blocked as i64;
+        user::update(&self.conn, &u)?;
+        println!("[*] {} '{}'", if blocked { "blocked" } else { "unblocked" }, u.username);
+        Ok(())
+    }
+
+    fn create_bot(&mut self, name: &str) -> Result<()> {
+        if user::is_username_taken(&self.conn, name) { bail!("the username '{}' is already taken", name); }
+        self.begin_write()?;
+        // nobody knows the password. bots log in with api tokens
+        let id = user::insert(&self.conn, name, &util::generate_token(64), "127.0.0.1")?;
+        let mut u = user::find(&self.conn, id).ok_or_else(|| anyhow!("failed to create bot '{}'", name))?;
+        u.is_bot = 1;
+        user::update(&self.conn, &u)?;
+        print_user(&u, &self.conn);
+        Ok(())
+    }
+
+    fn set_bot(&mut self, key: &str, value: &str) -> Result<()> {
+        let mut u = self.find_user(Some(key))?;
+        u.is_bot = parse_flag(value)?;
+        self.begin_write()?;
+        user::update(&self.conn, &u)?;
+        println!("[*] '{}' is {}a bot", u.username, if u.bot() { "" } else { "not " });
+        Ok(())
+    }
+
+    fn create_api_token(&mut self, key: &str, name: &str, scopes: &str) -> Result<()> {
+        let u = self.find_user(Some(key))?;
+        let scopes = scopes.split(',')
+            .map(|s| Scope::parse(s.trim()).ok_or_else(|| anyhow!("unknown scope '{}'. scopes: read_history, send_messages, manage_webhooks", s)))
+            .collect::<Result<Vec<_>>>()?;
+        self.begin_write()?;
+        let token = api_tokens::create(&self.conn, u.id, name, &scopes)?;
+        println!("[*] created api token id={} for '{}'", token.id, u.username);
+        println!("[*] {}", token.token.unwrap_or_default());
+        println!("[*] it is not shown again");
+        Ok(())
+    }
+
+    fn revoke_api_token(&mut self, id: Option<&str>) -> Result<()> {
+        let id = parse_id(id)?;
+        let t = api_token::find(&self.conn, id).ok_or_else(|| anyhow!("api token {} not found", id))?;
+        self.begin_write()?;
+        api_token::delete(&self.conn, t.id)?;
+        println!("[*] revoked api token '{}' of user_id={}", t.name, t.user_id);
+        Ok(())
+    }
+
+    fn create_subscription(&mut self, url: &str, secret: &str, events: &str, channel_id: Option<&str>) -> Result<()> {
+        let events = events.split(',')
+            .map(|e| EventType::parse(e.trim()).ok_or_else(|| anyhow!("unknown event '{}'. events: message_created, user_joined, mention, moderation", e)))
+            .collect::<Result<Vec<_>>>()?;
+        let channel_id = channel_id.map(|id| parse_id(Some(id))).transpose()?;
+        self.begin_write()?;
+        let s = subscriptions::create(&self.conn, url, secret, &events, channel_id)?;
+        println!("[*] created subscription id={} for {}", s.id, s.url);
+        Ok(())
+    }
+
+    fn delete_subscription(&mut self, id: Option<&str>) -> Result<()> {
+        let id = parse_id(id)?;
+        let s = subscription::find(&self.conn, id).ok_or_else(|| anyhow!("subscription {} not found", id))?;
+        self.begin_write()?;
+        subscription::delete(&self.conn, s.id)?;
+        println!("[*] deleted subscription id={} of {}", s.id, s.url);
+        Ok(())
+    }
+
+    fn set_channel(&mut self, args: &[&str]) -> Result<()> {
+        let id = parse_id(args.first().copied())?;
+        let (Some(field), true) = (args.get(1), args.len() > 2) else { bail!("usage: channel set <id> <field> <value...>"); };
+        let value = args[2..].join(" ");
+        let mut c = channel::find(&self.conn, id).ok_or_else(|| anyhow!("channel {} not found", id))?;
+        match *field {
+            "name" => c.name = value,
+            "description" => c.description = value,
+            "discord_server" => c.discord_server = value,
+            "discord_channel" => c.discord_channel = value,
+            "irc_channel" => c.irc_channel = value,
+            "irc_server_ip" => c.irc_server_ip = value,
+            "irc_server_name" => c.irc_server_name = value,
+            "owner_id" => c.owner_id = parse_id(Some(&value))?,
+            "is_private" => c.is_private = parse_flag(&value)?,
+            "mirror_reactions" => c.mirror_reactions = parse_flag(&value)?,
+            _ => bail!("unknown field '{}'. fields: {}", field, CHANNEL_FIELDS),
+        }
+        self.begin_write()?;
+        channel::update(&self.conn, &c)?;
+        print_channel(&c);
+        Ok(())
+    }
+
+    fn set_server(&mut self, args: &[&str]) -> Result<()> {
+        let id = parse_id(args.first().copied())?;
+        let (Some(field), true) = (args.get(1), args.len() > 2) else { bail!("usage: server set <id> <field> <value...>"); };
+        let value = args[2..].join(" ");
+        let mut s = server::find(&self.conn, id).ok_or_else(|| anyhow!("server {} not found", id))?;
+        match *field {
+            "name" => s.name = value,
+            "discord_name" => s.discord_name = value,
+            "irc_name" => s.irc_name = value,
+            "irc_ip" => s.irc_ip = value,
+            "icon_url" => s.icon_url = value,
+            "banner_url" => s.banner_url = value,
+            "owner_id" => s.owner_id = parse_id(Some(&value))?,
+            _ => bail!("unknown field '{}'. fields: {}", field, SERVER_FIELDS),
+        }
+        self.begin_write()?;
+        server::update(&self.conn, &s)?;
+        print_server(&s);
+        Ok(())
+    }
+
+    fn rotate_webhook(&mut self, id: Option<&str>) -> Result<()> {
+        let id = parse_id(id)?;
+        let mut w = webhook::find(&self.conn, id).ok_or_else(|| anyhow!("webhook {} not found", id))?;
+        self.begin_write()?;
+        w.token = util::generate_webhook_token();
+        webhook::update(&self.conn, &w)?;
+        print_webhook(&w);
+        Ok(())
+    }
+
+    fn sql(&mut self, statement: &str) -> Result<()> {
+        let first = statement.split_whitespace().next().unwrap_or("").to_lowercase();
+        if matches!(first.as_str(), "begin" | "commit" | "end" | "rollback" | "savepoint" | "release") {
+            bail!("use the begin, commit and rollback commands to control transactions");
+        }
+        let read_only = self.conn.prepare(statement)?.readonly();
+        if !read_only { self.begin_write()?; }
+        let mut st = self.conn.prepare(statement)?;
+        if !read_only {
+            let changed = st.execute([])?;
+            println!("[*] {} rows changed", changed);
+            return Ok(());
+        }
+        let names: Vec<String> = st.column_names().iter().map(|n| n.to_string()).collect();
+        println!("{}", names.join(" | "));
+        let mut rows = st.query([])?;
+        let mut count = 0;
+        while let Some(row) = rows.next()? {
+            let values: Vec<String> = (0..names.len()).map(|i| row.get_ref(i).map(format_value).unwrap_or_default()).collect();
+            println!("{}", values.join(" | "));
+            count += 1;
+        }
+        println!("[*] {} rows", count);
+        Ok(())
+    }
+
+    fn commit(&mut self) -> Result<()> {
+        if !self.in_transaction { println!("[*] nothing to commit"); return Ok(()); }
+        self.conn.execute_batch("COMMIT")?;
+        self.in_transaction = false;
+        println!("[*] committed");
+        Ok(())
+    }
+
+    fn rollback(&mut self) -> Result<()> {
+        if !self.in_transaction { println!("[*] nothing to roll back"); return Ok(()); }
+        self.conn.execute_batch("ROLLBACK")?;
+        self.in_transaction = false;
+        println!("[*] rolled back");
+        Ok(())
+    }
+
+    fn run(&mut self, line: &str, history: &dyn History) -> Result<Next> {
+        let line = line.trim();
+        let args: Vec<&str> = line.split_whitespace().collect();
+        let Some(&cmd) = args.first() else { return Ok(Next::Continue); };
+        if cmd != "exit" && cmd != "quit" { self.exit_warned = false; }
+        let rest = &args[1..];
+        match (cmd, rest) {
+            ("help", _) => println!("{}", HELP),
+            ("exit" | "quit", _) => {
+                if self.in_transaction && !self.exit_warned {
+                    self.exit_warned = true;
+                    println!("[!] there are uncommitted changes. type commit to save them or exit again to discard them");
+                    return Ok(Next::Continue);
+                }
+                return Ok(Next::Exit);
+            }
+            ("user", [key]) => print_user(&self.find_user(Some(key))?, &self.conn),
+            ("users", _) => {
+                let search = rest.first().map(|s| s.to_lowercase());
+                for u in user::all(&self.conn).iter().filter(|u| search.as_ref().is_none_or(|s| u.username.to_lowercase().contains(s))) {
+                    println!("id={} username={} admin={} blocked={} bot={}", u.id, u.username, u.admin(), u.blocked(), u.bot());
+                }
+            }
+            ("block", [key]) => self.set_blocked(Some(key), true)?,
+            ("unblock", [key]) => self.set_blocked(Some(key), false)?,
+            ("bot", ["create", name]) => self.create_bot(name)?,
+            ("bot", [key, value]) => self.set_bot(key, value)?,
+            ("tokens", [key]) => api_token::for_user(&self.conn, self.find_user(Some(key))?.id).iter().for_each(print_api_token),
+            ("token", ["create", key, name, scopes]) => self.create_api_token(key, name, scopes)?,
+            ("token", ["revoke", id]) => self.revoke_api_token(Some(id))?,
+            ("channels", _) => {
+                for c in channel::all(&self.conn).iter().filter(|c| rest.first().is_none_or(|s| c.discord_server == *s)) {
+                    print_channel(c);
+                }
+            }
+            ("channel", ["set", args @ ..]) => self.set_channel(args)?,
+            ("channel", [id]) => {
+                let c = channel::find(&self.conn, parse_id(Some(id))?).ok_or_else(|| anyhow!("channel {} not found", id))?;
+                print_channel(&c);
+                let members: i64 = self.conn.query_row("SELECT COUNT(*) FROM channel_members WHERE channel_id = ?1", [c.id], |row| row.get(0))?;
+                println!("  members={}", members);
+            }
+            ("servers", _) => server::all(&self.conn).iter().for_each(print_server),
+            ("server", ["set", args @ ..]) => self.set_server(args)?,
+            ("server", [id]) => print_server(&server::find(&self.conn, parse_id(Some(id))?).ok_or_else(|| anyhow!("server {} not found", id))?),
+            ("webhooks", []) => webhook::all(&self.conn).iter().for_each(print_webhook),
+            ("webhooks", [id]) => webhook::where_eq(&self.conn, "channel_id", parse_id(Some(id))?).iter().for_each(print_webhook),
+            ("webhook", ["rotate", id]) => self.rotate_webhook(Some(id))?,
+            ("subscriptions", []) => subscription::all(&self.conn).iter().for_each(print_subscription),
+            ("subscription", ["create", url, secret, events]) => self.create_subscription(url, secret, events, None)?,
+            ("subscription", ["create", url, secret, events, channel_id]) => self.create_subscription(url, secret, events, Some(channel_id))?,
+            ("subscription", ["delete", id]) => self.delete_subscription(Some(id))?,
+            ("deliveries", [id]) => subscription_delivery::for_subscription(&self.conn, parse_id(Some(id))?, None, 20).iter().for_each(print_delivery),
+            ("sql", _) => {
+                let statement = line[3..].trim();
+                if statement.is_empty() { bail!("usage: sql <statement>"); }
+                self.sql(statement)?;
+            }
+            ("begin", []) => self.begin_write()?,
+            ("commit", []) => self.commit()?,
+            ("rollback", []) => self.rollback()?,
+            ("status", []) => println!(
+                "[*] {}{}",
+                if self.read_only { "read-only. " } else { "" },
+                if self.in_transaction { "there are uncommitted changes" } else { "no open transaction" }
+            ),
+            ("history", []) => {
+                for i in 0..history.len() {
+                    if let Ok(Some(entry)) = history.get(i, rustyline::history::SearchDirection::Forward) {
+                        println!("{:>4}  {}", i + 1, entry.entry);
+                    }
+                }
+            }
+            _ => bail!("unknown command or wrong arguments. type help"),
+        }
+        Ok(Next::Continue)
+    }
+}
+
+fn open(cli: &CliArgs, read_only: bool) -> Result<Connection> {
+    let config = Config::load_unvalidated(cli)?;
+    let conn = if read_only {
+        Connection::open_with_flags(&config.db_path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+            .with_context(|| format!("failed to open database '{}'", config.db_path.display()))?
+    } else {
+        db::open(&config.db_path)?
+    };
+    if let Ok(pending) = db::pending_migrations(&conn, &db::default_migrations_dir()) {
+        if !pending.is_empty() {
+            println!("[!] there are {} pending migrations. run db-cli migrate", pending.len());
+        }
+    }
+    Ok(conn)
+}
+
+fn run() -> Result<()> {
+    let cli = CliArgs::from_env()?;
+    let mut read_only = false;
+    for arg in &cli.rest {
+        match arg.as_str() {
+            "--read-only" => read_only = true,
+            _ => bail!("unknown argument '{}'. usage: admin-console [--config <file>] [--db <path>] [--read-only]", arg),
+        }
+    }
+    let conn = open(&cli, read_only)?;
+
+    println!("[irc-websockets] admin console");
+    if read_only {
+        println!("[irc-websockets] read-only mode. nothing can be changed");
+    } else {
+        println!("[irc-websockets] changes are made in a transaction. type commit to save them");
+    }
+    println!("[irc-websockets] type help for a list of commands");
+
+    let history_path = std::env::var("ADMIN_CONSOLE_HISTORY").unwrap_or_else(|_| ".admin_console_history".into());
+    let mut editor = DefaultEditor::new()?;
+    let _ = editor.load_history(&history_path);
+    let mut console = Console { conn, read_only, in_transaction: false, exit_warned: false };
+    loop {
+        let line = match editor.readline(console.prompt()) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(e.into()),
+        };
+        if !line.trim().is_empty() { let _ = editor.add_history_entry(line.as_str()); }
+        match console.run(&line, editor.history()) {
+            Ok(Next::Continue) => {}
+            Ok(Next::Exit) => break,
+            Err(e) => println!("[!] {e:#}"),
+        }
+    }
+    if console.in_transaction {
+        console.rollback()?;
+        println!("[*] uncommitted changes were discarded");
+    }
+    if let Err(e) = editor.save_history(&history_path) {
+        println!("[!] failed to save history: {e}");
+    }
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    dotenvy::dotenv().ok();
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("[!] {e:#}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -1,65 +1,162 @@
-use std::{env, fs, path::PathBuf};
+use std::process::ExitCode;
 
-use regex::Regex;
+use anyhow::{bail, Result};
+use irc_websockets::{cli::CliArgs, config::Config, db::{self, Migration, MigrationError}, seed};
 use rusqlite::Connection;
 
-fn get_db() -> Connection {
-    let conn = Connection::open("./db/main.db").expect("open db");
-    conn.pragma_update(None, "journal_mode", &"WAL").ok();
-    conn
-}
+const EXIT_ERROR: u8 = 1;
+const EXIT_USAGE: u8 = 2;
+const EXIT_UNSAFE: u8 = 3;
 
-fn get_db_version(conn: &Connection) -> i64 {
-    let mut stmt = conn.prepare("PRAGMA user_version").unwrap();
-    let mut rows = stmt.query([]).unwrap();
-    let row = rows.next().unwrap().unwrap();
-    row.get(0).unwrap_or(0)
+struct Flags {
+    force: bool,
+    dry_run: bool,
+    clean: bool,
+    steps: usize,
 }
 
-fn set_db_version(conn: &Connection, version: i64) -> bool {
-    conn.pragma_update(None, "user_version", &version).is_ok()
+fn open_db(cli: &CliArgs) -> Result<Connection> {
+    let config = Config::load_unvalidated(cli)?;
+    db::open(&config.db_path)
 }
 
-fn migrations_dir() -> PathBuf {
-    PathBuf::from("db/migrations")
+/// With --dry-run everything runs in one transaction that is rolled back at the end
+fn with_dry_run(conn: &Connection, flags: &Flags, f: impl FnOnce() -> Result<()>) -> Result<()> {
+    if !flags.dry_run { return f(); }
+    db::dry_run(conn, f)?;
+    println!("[*] dry run succeeded. nothing was changed");
+    Ok(())
 }
 
-fn migrate(force: bool) {
-    let conn = get_db();
-    let latest = get_db_version(&conn);
-    let re = Regex::new(r"^(\d{10})_[a-zA-Z0-9_]+\.sql$").unwrap();
-    let mut files: Vec<_> = fs::read_dir(migrations_dir()).unwrap().filter_map(|e| e.ok()).collect();
-    files.sort_by_key(|e| e.file_name());
-    for entry in files {
-        let name = entry.file_name();
-        let name = name.to_string_lossy();
-        if let Some(cap) = re.captures(&name) {
-            let ts: i64 = cap[1].parse().unwrap();
-            if ts <= latest { println!("[*] already ran {} ...", name); continue; }
-            println!("[*] applying {} ...", name);
-            let sql = fs::read_to_string(entry.path()).unwrap();
-            let lower = sql.to_lowercase();
-            if (lower.contains("warning") || lower.contains("drop table")) && !force {
-                eprintln!("[!] Unsafe migration detected. Re-run with --force if intended.");
-                std::process::exit(1);
-            }
-            conn.execute_batch(&sql).unwrap();
-            set_db_version(&conn, ts);
+fn migrate(cli: &CliArgs, flags: &Flags) -> Result<()> {
+    let conn = open_db(cli)?;
+    let latest = db::get_db_version(&conn)?;
+    let migrations = db::migrations(&db::default_migrations_dir())?;
+    with_dry_run(&conn, flags, || {
+        for migration in migrations {
+            if migration.version <= latest { println!("[*] already ran {} ...", migration.name); continue; }
+            println!("[*] applying {} ...", migration.name);
+            db::apply_migration(&conn, &migration, flags.force)?;
         }
+        Ok(())
+    })
+}
+
+fn rollback(cli: &CliArgs, flags: &Flags) -> Result<()> {
+    let conn = open_db(cli)?;
+    let migrations = db::migrations(&db::default_migrations_dir())?;
+    with_dry_run(&conn, flags, || rollback_steps(&conn, &migrations, flags))
+}
+
+fn rollback_steps(conn: &Connection, migrations: &[Migration], flags: &Flags) -> Result<()> {
+    for _ in 0..flags.steps {
+        let current = db::get_db_version(conn)?;
+        if current == 0 { println!("[*] nothing to roll back"); break; }
+        let Some(pos) = migrations.iter().position(|m| m.version == current) else {
+            return Err(MigrationError::UnknownVersion(current).into());
+        };
+        let previous = if pos == 0 { 0 } else { migrations[pos - 1].version };
+        let migration = &migrations[pos];
+        println!("[*] rolling back {} ...", migration.name);
+        db::rollback_migration(conn, migration, previous, flags.force)?;
+    }
+    Ok(())
+}
+
+fn status(cli: &CliArgs) -> Result<()> {
+    let conn = open_db(cli)?;
+    let current = db::get_db_version(&conn)?;
+    let migrations = db::migrations(&db::default_migrations_dir())?;
+    println!("[*] database version: {}", current);
+    let print = |m: &Migration, state: &str| {
+        println!("  {:<8} {}{}", state, m.name, if m.down_path.is_some() { "" } else { " (no down)" });
+    };
+    for m in migrations.iter().filter(|m| m.version <= current) { print(m, "applied"); }
+    for m in migrations.iter().filter(|m| m.version > current) { print(m, "pending"); }
+    if current != 0 && !migrations.iter().any(|m| m.version == current) {
+        println!("[!] there is no migration file for version {}", current);
+    }
+    Ok(())
+}
+
+fn seed(cli: &CliArgs, flags: &Flags) -> Result<()> {
+    let conn = open_db(cli)?;
+    if flags.clean {
+        seed::clean(&conn, flags.force)?;
+        println!("[*] removed all seed rows");
+        return Ok(());
     }
+    let password = std::env::var("SEED_PASSWORD").unwrap_or_else(|_| "xxx".into());
+    let sum = seed::seed(&conn, &password, flags.force)?;
+    println!(
+        "[*] seeded {} users, {} servers, {} channels, {} webhooks, {} channel members and {} friends",
+        sum.users, sum.servers, sum.channels, sum.webhooks, sum.channel_members, sum.friends
+    );
+    Ok(())
+}
+
+fn new_migration(name: &str) -> Result<()> {
+    let (up, down) = db::new_migration(&db::default_migrations_dir(), name)?;
+    println!("[*] created {}", up.display());
+    println!("[*] created {}", down.display());
+    Ok(())
 }
 
 fn usage() {
-    println!("usage: db-cli [migrate] [--force]");
+    println!("usage: db-cli [--config <file>] [--db <path>] <command> [options]");
+    println!("commands:");
+    println!("  migrate [--force] [--dry-run]              apply pending migrations");
+    println!("  rollback [--steps N] [--force] [--dry-run] run the .down.sql of the latest migrations");
+    println!("  status                                     list applied and pending migrations");
+    println!("  new <name>                                 create a new migration and its .down.sql");
+    println!("  seed [--force] [--clean]                   replace the sample data of a dev database. --clean only removes it. both refuse on a database with other data unless --force is set");
+    println!("exit codes: 0 ok, 1 error, 2 usage, 3 unsafe migration without --force");
+}
+
+fn run(cli: &CliArgs) -> Result<()> {
+    let mut flags = Flags { force: false, dry_run: false, clean: false, steps: 1 };
+    let mut positional = Vec::new();
+    let mut args = cli.rest.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--force" => flags.force = true,
+            "--dry-run" => flags.dry_run = true,
+            "--clean" => flags.clean = true,
+            "--steps" => match args.next().and_then(|v| v.parse().ok()) {
+                Some(n) => flags.steps = n,
+                None => bail!(UsageError("--steps needs a number".into())),
+            },
+            flag if flag.starts_with("--") => bail!(UsageError(format!("unknown flag {}", flag))),
+            _ => positional.push(arg.as_str()),
+        }
+    }
+    match positional.as_slice() {
+        ["migrate"] => migrate(cli, &flags),
+        ["rollback"] => rollback(cli, &flags),
+        ["status"] => status(cli),
+        ["seed"] => seed(cli, &flags),
+        ["new", name] => new_migration(name),
+        _ => bail!(UsageError("missing or invalid command".into())),
+    }
 }
 
-fn main() {
-    let args = env::args().skip(1);
-    let mut action = String::new();
-    let mut force = false;
-    for a in args { if a == "--force" { force = true; } else { action = a; } }
-    match action.as_str() {
-        "migrate" => migrate(force),
-        _ => usage(),
+#[derive(Debug, thiserror::Error)]
+#[error("{0}")]
+struct UsageError(String);
+
+fn main() -> ExitCode {
+    dotenvy::dotenv().ok();
+    let res = CliArgs::parse(std::env::args().skip(1))
+        .map_err(|e| UsageError(e.to_string()).into())
+        .and_then(|cli| run(&cli));
+    let Err(e) = res else { return ExitCode::SUCCESS; };
+    eprintln!("[!] {e:#}");
+    if e.downcast_ref::<UsageError>().is_some() {
+        usage();
+        return ExitCode::from(EXIT_USAGE);
+    }
+    if matches!(e.downcast_ref::<MigrationError>(), Some(MigrationError::Unsafe(_))) {
+        return ExitCode::from(EXIT_UNSAFE);
     }
+    ExitCode::from(EXIT_ERROR)
 }
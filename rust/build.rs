@@ -0,0 +1,4 @@
+fn main() {
+    // the migrations are embedded by db.rs. a new file has to trigger a rebuild
+    println!("cargo:rerun-if-changed=db/migrations");
+}
@@ -0,0 +1,44 @@
+use std::path::PathBuf;
+
+use anyhow::{bail, Result};
+
+/// Flags shared by the server and db-cli.
+/// They win over the config file and env vars.
+#[derive(Debug, Clone, Default)]
+pub struct CliArgs {
+    pub config_path: Option<PathBuf>,
+    pub db_path: Option<PathBuf>,
+    pub history_path: Option<PathBuf>,
+    // everything that is not a shared flag. left for the binary to interpret
+    pub rest: Vec<String>,
+}
+
+impl CliArgs {
+    /// Accepts both `--db path` and `--db=path`
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self> {
+        let mut cli = CliArgs::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let (flag, inline) = match arg.split_once('=') {
+                Some((f, v)) if f.starts_with("--") => (f.to_string(), Some(v.to_string())),
+                _ => (arg.clone(), None),
+            };
+            let slot = match flag.as_str() {
+                "--config" => &mut cli.config_path,
+                "--db" => &mut cli.db_path,
+                "--history" => &mut cli.history_path,
+                _ => { cli.rest.push(arg); continue; }
+            };
+            let value = match inline.or_else(|| args.next()) {
+                Some(v) if !v.is_empty() => v,
+                _ => bail!("missing value for {}", flag),
+            };
+            *slot = Some(PathBuf::from(value));
+        }
+        Ok(cli)
+    }
+
+    pub fn from_env() -> Result<Self> {
+        Self::parse(std::env::args().skip(1))
+    }
+}
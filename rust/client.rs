@@ -0,0 +1,123 @@
+//! Connected clients of both transports: socket.io and the plain websocket on /ws.
+//! The event handlers in `ws` only talk to clients through this module.
+
+use axum::extract::ws::Message;
+use serde::Serialize;
+use serde_json::json;
+use socketioxide::{extract::SocketRef, socket::Sid};
+use tokio::sync::mpsc::UnboundedSender;
+
+use crate::state::AppState;
+
+/// Sending half of a plain websocket connection
+pub type PlainSender = UnboundedSender<Message>;
+
+#[derive(Clone)]
+pub enum Client {
+    SocketIo(SocketRef),
+    Plain { id: String, tx: PlainSender },
+}
+
+/// Server to client frame of the plain websocket protocol
+pub fn envelope<T: Serialize + ?Sized>(op: &str, d: &T, seq: Option<u64>) -> Message {
+    Message::Text(json!({"op": op, "d": d, "seq": seq}).to_string())
+}
+
+impl Client {
+    /// Key of the session in `AppState::sessions`
+    pub fn id(&self) -> String {
+        match self {
+            Client::SocketIo(s) => s.id.to_string(),
+            Client::Plain { id, .. } => id.clone(),
+        }
+    }
+
+    pub fn emit<T: Serialize + ?Sized>(&self, event: &str, data: &T) {
+        match self {
+            Client::SocketIo(s) => { let _ = s.emit(event, data); }
+            Client::Plain { tx, .. } => { let _ = tx.send(envelope(event, data, None)); }
+        }
+    }
+
+    /// Sends to every other client of both transports
+    pub async fn broadcast<T: Serialize + ?Sized>(&self, state: &AppState, event: &str, data: &T) {
+        match self {
+            Client::SocketIo(s) => {
+                let _ = s.broadcast().emit(event, data).await;
+                emit_plain(state, None, event, data);
+            }
+            Client::Plain { id, .. } => {
+                emit_socket_io(state, event, data).await;
+                emit_plain(state, Some(id), event, data);
+            }
+        }
+    }
+
+    pub fn join(&self, rooms: Vec<String>) {
+        match self {
+            Client::SocketIo(s) => s.join(rooms),
+            // nothing is emitted to rooms yet so plain clients do not track them
+            Client::Plain { .. } => {}
+        }
+    }
+}
+
+async fn emit_socket_io<T: Serialize + ?Sized>(state: &AppState, event: &str, data: &T) {
+    let io = state.io.lock().clone();
+    if let Some(io) = io {
+        let _ = io.emit(event, data).await;
+    }
+}
+
+fn emit_plain<T: Serialize + ?Sized>(state: &AppState, except: Option<&str>, event: &str, data: &T) {
+    let frame = envelope(event, data, None);
+    for client in state.plain_clients.iter() {
+        if except == Some(client.key().as_str()) { continue; }
+        let _ = client.value().send(frame.clone());
+    }
+}
+
+/// Sends to every connected client of both transports
+pub async fn emit_all<T: Serialize + ?Sized>(state: &AppState, event: &str, data: &T) {
+    emit_socket_io(state, event, data).await;
+    emit_plain(state, None, event, data);
+}
+
+/// Sends to the client owning the session `id`
+pub fn emit_to<T: Serialize + ?Sized>(state: &AppState, id: &str, event: &str, data: &T) {
+    if let Some(tx) = state.plain_clients.get(id) {
+        let _ = tx.send(envelope(event, data, None));
+        return;
+    }
+    let Ok(sid) = id.parse::<Sid>() else { return; };
+    let io = state.io.lock().clone();
+    if let Some(socket) = io.and_then(|io| io.get_socket(sid)) {
+        let _ = socket.emit(event, data);
+    }
+}
+
+/// Closes the connection owning the session `id`
+pub fn disconnect(state: &AppState, id: &str) {
+    if let Some(tx) = state.plain_clients.get(id) {
+        let _ = tx.send(Message::Close(None));
+        return;
+    }
+    let Ok(sid) = id.parse::<Sid>() else { return; };
+    let io = state.io.lock().clone();
+    if let Some(socket) = io.and_then(|io| io.get_socket(sid)) {
+        let _ = socket.disconnect();
+    }
+}
+
+/// Number of connected clients of both transports
+pub fn count(state: &AppState) -> usize {
+    let sockets = state.io.lock().as_ref().map(|io| io.sockets().len()).unwrap_or(0);
+    sockets + state.plain_clients.len()
+}
+
+/// Closes all plain websocket connections. socket.io is closed through `SocketIo::close`.
+pub fn close_plain(state: &AppState) {
+    for client in state.plain_clients.iter() {
+        let _ = client.value().send(Message::Close(None));
+    }
+}
@@ -0,0 +1,358 @@
+//! The commands every installation has
+
+use std::time::{Duration, Instant};
+
+use futures::future::BoxFuture;
+use tracing::info;
+
+use super::{CommandContext, CommandRegistry, SlashCommand};
+use crate::{
+    ack::{ErrorCode, EventError},
+    client,
+    irc_bridge,
+    messages,
+    models::{channel as channel_model, user as user_model},
+    state::AppState,
+    subscriptions::{self, EventType, ModerationAction},
+    types::{DeliveryStatus, IrcMessage, LogoutMessage},
+    ws,
+};
+
+const MAX_TOPIC_LEN: usize = 256;
+const DEFAULT_MUTE_MINUTES: u64 = 10;
+const MAX_MUTE_MINUTES: u64 = 7 * 24 * 60;
+
+pub(super) fn register(registry: &CommandRegistry) {
+    registry.register(Me);
+    registry.register(Nick);
+    registry.register(Topic);
+    registry.register(Whois);
+    registry.register(Names);
+    registry.register(Query);
+    registry.register(Help);
+    registry.register(Kick);
+    registry.register(Block);
+    registry.register(Mute);
+}
+
+fn usage(command: &dyn SlashCommand) -> EventError {
+    EventError::new(ErrorCode::InvalidInput, format!("usage: /{} {}", command.name(), command.usage()))
+}
+
+fn invalid(message: impl Into<String>) -> EventError {
+    EventError::new(ErrorCode::InvalidInput, message)
+}
+
+/// First word and the trimmed rest
+fn split_arg(args: &str) -> (&str, &str) {
+    let (first, rest) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+    (first, rest.trim())
+}
+
+/// Logs out and closes every connection of `username`. Returns how many there were.
+fn disconnect_user(state: &AppState, username: &str, message: &str) -> usize {
+    let mut ids = vec![];
+    for mut entry in state.sessions.iter_mut() {
+        if !entry.logged_in || !entry.username.eq_ignore_ascii_case(username) { continue; }
+        // not logged in anymore so the dropped connection can not be resumed
+        entry.logged_in = false;
+        state.resumable.remove(&entry.session_token);
+        ids.push(entry.key().clone());
+    }
+    for id in &ids {
+        client::emit_to(state, id, "logout", &LogoutMessage { message: message.to_string() });
+        client::disconnect(state, id);
+    }
+    ids.len()
+}
+
+fn moderation(ctx: &CommandContext, action: ModerationAction) {
+    let action = ModerationAction {
+        server: Some(&ctx.mapping.discord_server),
+        channel: Some(&ctx.mapping.discord_channel),
+        ..action
+    };
+    subscriptions::emit(&ctx.state, &ctx.state.db.lock(), EventType::Moderation, Some(ctx.mapping.id), &action);
+}
+
+struct Me;
+
+impl SlashCommand for Me {
+    fn name(&self) -> &'static str { "me" }
+    fn usage(&self) -> &'static str { "<action>" }
+    fn description(&self) -> &'static str { "send an action like * nick waves" }
+    fn run<'a>(&'a self, ctx: &'a CommandContext, args: &'a str) -> BoxFuture<'a, Result<String, EventError>> {
+        Box::pin(async move {
+            if args.is_empty() { return Err(usage(self)); }
+            messages::check_muted(&ctx.state, &ctx.session.username)?;
+            let msg = IrcMessage {
+                from: ctx.display_name().to_string(),
+                message: args.to_string(),
+                channel: ctx.mapping.discord_channel.clone(),
+                server: ctx.mapping.discord_server.clone(),
+                action: true,
+                ..Default::default()
+            };
+            // no origin so the issuer gets the message too, its client did not add it
+            messages::send(&ctx.state, None, ctx.session.db_user.as_ref(), msg).await?;
+            Ok(String::new())
+        })
+    }
+}
+
+struct Nick;
+
+impl SlashCommand for Nick {
+    fn name(&self) -> &'static str { "nick" }
+    fn usage(&self) -> &'static str { "[name]" }
+    fn description(&self) -> &'static str { "show new messages under another name. your username resets it" }
+    fn run<'a>(&'a self, ctx: &'a CommandContext, args: &'a str) -> BoxFuture<'a, Result<String, EventError>> {
+        Box::pin(async move {
+            if args.is_empty() { return Ok(format!("you are {}", ctx.display_name())); }
+            if !ws::username_pattern().is_match(args) {
+                return Err(invalid("a name can only have up to 20 letters, digits and underscores"));
+            }
+            let own = args.eq_ignore_ascii_case(&ctx.session.username);
+            if !own {
+                let has_account = user_model::is_username_taken(&ctx.state.db.lock(), args);
+                let taken = has_account
+                    || ctx.state.sessions.iter().any(|u| {
+                        u.key() != &ctx.client.id() && u.logged_in
+                            && (u.username.eq_ignore_ascii_case(args) || u.display_name.as_deref().is_some_and(|n| n.eq_ignore_ascii_case(args)))
+                    });
+                if taken { return Err(EventError::new(ErrorCode::UsernameTaken, format!("'{args}' is already used by someone else"))); }
+            }
+            if let Some(mut session) = ctx.state.sessions.get_mut(&ctx.client.id()) {
+                session.display_name = if own { None } else { Some(args.to_string()) };
+            }
+            info!("[*][command] '{}' is now known as '{}'", ctx.session.username, args);
+            Ok(format!("you are now known as {args}"))
+        })
+    }
+}
+
+struct Topic;
+
+impl SlashCommand for Topic {
+    fn name(&self) -> &'static str { "topic" }
+    fn usage(&self) -> &'static str { "[topic]" }
+    fn description(&self) -> &'static str { "show the channel description. admins and the owner can change it" }
+    fn run<'a>(&'a self, ctx: &'a CommandContext, args: &'a str) -> BoxFuture<'a, Result<String, EventError>> {
+        Box::pin(async move {
+            // before locking the database, account() locks it too
+            let account = if args.is_empty() { None } else { ctx.account() };
+            let conn = ctx.state.db.lock();
+            let Some(mut ch) = channel_model::find(&conn, ctx.mapping.id) else {
+                return Err(EventError::new(ErrorCode::ChannelNotFound, "channel not found"));
+            };
+            if args.is_empty() {
+                if ch.description.is_empty() { return Ok(format!("#{} has no topic", ch.discord_channel)); }
+                return Ok(format!("topic of #{}: {}", ch.discord_channel, ch.description));
+            }
+            let allowed = account.is_some_and(|u| !u.blocked() && (u.admin() || u.id == ch.owner_id));
+            if !allowed { return Err(EventError::new(ErrorCode::PermissionDenied, "only admins and the owner of the channel can change the topic")); }
+            if args.chars().count() > MAX_TOPIC_LEN { return Err(invalid(format!("the topic can be at most {MAX_TOPIC_LEN} characters long"))); }
+            ch.description = args.to_string();
+            channel_model::update(&conn, &ch).map_err(|e| EventError::new(ErrorCode::Internal, format!("failed to update the channel: {e}")))?;
+            info!("[*][command] '{}' set the topic of '{}#{}'", ctx.session.username, ch.discord_server, ch.discord_channel);
+            Ok(format!("topic of #{} set to: {}", ch.discord_channel, ch.description))
+        })
+    }
+}
+
+struct Whois;
+
+impl SlashCommand for Whois {
+    fn name(&self) -> &'static str { "whois" }
+    fn usage(&self) -> &'static str { "<name>" }
+    fn description(&self) -> &'static str { "show the account, web sessions and irc presence of someone" }
+    fn run<'a>(&'a self, ctx: &'a CommandContext, args: &'a str) -> BoxFuture<'a, Result<String, EventError>> {
+        Box::pin(async move {
+            let (name, _) = split_arg(args);
+            if name.is_empty() { return Err(usage(self)); }
+            let mut lines = vec![];
+            if let Some(u) = user_model::find_by_username(&ctx.state.db.lock(), name) {
+                let mut flags = vec![];
+                if u.admin() { flags.push("admin"); }
+                if u.bot() { flags.push("bot"); }
+                if u.blocked() { flags.push("blocked"); }
+                let flags = if flags.is_empty() { String::new() } else { format!(" ({})", flags.join(", ")) };
+                lines.push(format!("{} has an account{} since {}", u.username, flags, u.created_at));
+            }
+            for u in ctx.state.sessions.iter().filter(|u| u.logged_in) {
+                let shown = u.display_name.as_deref().unwrap_or(&u.username);
+                if u.username.eq_ignore_ascii_case(name) || shown.eq_ignore_ascii_case(name) {
+                    lines.push(format!("{} is online as {} in {}#{}", u.username, shown, u.active_server, u.active_channel));
+                }
+            }
+            if let Some(nicks) = irc_bridge::names(&ctx.state, &ctx.mapping.irc_server_name, &ctx.mapping.irc_channel).await {
+                if let Some(nick) = nicks.iter().find(|n| n.eq_ignore_ascii_case(name)) {
+                    lines.push(format!("{} is in #{} on {}", nick, ctx.mapping.irc_channel, ctx.mapping.irc_server_name));
+                }
+            }
+            if lines.is_empty() { return Ok(format!("nobody named {name} was found")); }
+            Ok(lines.join("\n"))
+        })
+    }
+}
+
+struct Names;
+
+impl SlashCommand for Names {
+    fn name(&self) -> &'static str { "names" }
+    fn description(&self) -> &'static str { "list who is in this channel on the web and on irc" }
+    fn run<'a>(&'a self, ctx: &'a CommandContext, _args: &'a str) -> BoxFuture<'a, Result<String, EventError>> {
+        Box::pin(async move {
+            let mut web: Vec<String> = ctx.state.sessions.iter()
+                .filter(|u| u.logged_in && u.active_server == ctx.mapping.discord_server && u.active_channel == ctx.mapping.discord_channel)
+                .map(|u| u.display_name.clone().unwrap_or_else(|| u.username.clone()))
+                .collect();
+            web.sort_by_key(|n| n.to_lowercase());
+            web.dedup();
+            let irc = match irc_bridge::names(&ctx.state, &ctx.mapping.irc_server_name, &ctx.mapping.irc_channel).await {
+                Some(mut nicks) => {
+                    nicks.sort_by_key(|n| n.to_lowercase());
+                    format!("{}: {}", nicks.len(), nicks.join(" "))
+                }
+                None => "not connected".to_string(),
+            };
+            Ok(format!("web {}: {}\nirc #{} {}", web.len(), web.join(" "), ctx.mapping.irc_channel, irc))
+        })
+    }
+}
+
+struct Query;
+
+impl SlashCommand for Query {
+    fn name(&self) -> &'static str { "query" }
+    fn usage(&self) -> &'static str { "<irc nick> <message>" }
+    fn description(&self) -> &'static str { "send a private message to someone on irc" }
+    fn run<'a>(&'a self, ctx: &'a CommandContext, args: &'a str) -> BoxFuture<'a, Result<String, EventError>> {
+        Box::pin(async move {
+            let (nick, text) = split_arg(args);
+            if nick.is_empty() || text.is_empty() { return Err(usage(self)); }
+            if nick.starts_with(['#', '&']) || nick.contains(',') { return Err(invalid("/query only sends to irc nicks, not channels")); }
+            messages::check_muted(&ctx.state, &ctx.session.username)?;
+            messages::check_text(text).map_err(invalid)?;
+            messages::check_rate_limit(&ctx.state, text)?;
+            let line = format!("<{}> {}", ctx.display_name(), text);
+            let (status, _) = irc_bridge::queue_irc_to(&ctx.state, &ctx.mapping.irc_server_name, nick, &line);
+            if status == DeliveryStatus::Failed {
+                return Err(EventError::new(ErrorCode::IrcSendFailed, format!("failed to send to {nick} on {}", ctx.mapping.irc_server_name)));
+            }
+            Ok(format!("-> {nick}: {text}"))
+        })
+    }
+}
+
+struct Help;
+
+impl SlashCommand for Help {
+    fn name(&self) -> &'static str { "help" }
+    fn usage(&self) -> &'static str { "[command]" }
+    fn description(&self) -> &'static str { "list the commands" }
+    fn run<'a>(&'a self, ctx: &'a CommandContext, args: &'a str) -> BoxFuture<'a, Result<String, EventError>> {
+        Box::pin(async move {
+            let is_admin = ctx.require_admin().is_ok();
+            let line = |c: &dyn SlashCommand| {
+                if c.usage().is_empty() { format!("/{}: {}", c.name(), c.description()) }
+                else { format!("/{} {}: {}", c.name(), c.usage(), c.description()) }
+            };
+            let (name, _) = split_arg(args);
+            if !name.is_empty() {
+                let name = name.trim_start_matches('/').to_lowercase();
+                return match ctx.state.commands.find(&name) {
+                    Some(c) if is_admin || !c.admin_only() => Ok(line(c.as_ref())),
+                    _ => Err(invalid(format!("unknown command /{name}. try /help"))),
+                };
+            }
+            let mut lines: Vec<String> = ctx.state.commands.all().iter()
+                .filter(|c| is_admin || !c.admin_only())
+                .map(|c| line(c.as_ref()))
+                .collect();
+            lines.push("start a message with // to send it with a single leading slash".into());
+            Ok(lines.join("\n"))
+        })
+    }
+}
+
+struct Kick;
+
+impl SlashCommand for Kick {
+    fn name(&self) -> &'static str { "kick" }
+    fn usage(&self) -> &'static str { "<user> [reason]" }
+    fn description(&self) -> &'static str { "log out and disconnect every session of a user" }
+    fn admin_only(&self) -> bool { true }
+    fn run<'a>(&'a self, ctx: &'a CommandContext, args: &'a str) -> BoxFuture<'a, Result<String, EventError>> {
+        Box::pin(async move {
+            let (user, reason) = split_arg(args);
+            if user.is_empty() { return Err(usage(self)); }
+            if user.eq_ignore_ascii_case(&ctx.session.username) { return Err(invalid("you can not kick yourself")); }
+            let message = if reason.is_empty() { format!("kicked by {}", ctx.session.username) } else { format!("kicked by {}: {}", ctx.session.username, reason) };
+            if disconnect_user(&ctx.state, user, &message) == 0 { return Err(invalid(format!("{user} is not online"))); }
+            info!("[*][command] '{}' kicked '{}'", ctx.session.username, user);
+            let reason = (!reason.is_empty()).then_some(reason);
+            moderation(ctx, ModerationAction { action: "user_kicked", moderator: &ctx.session.username, target: Some(user), reason, ..Default::default() });
+            Ok(format!("kicked {user}"))
+        })
+    }
+}
+
+struct Block;
+
+impl SlashCommand for Block {
+    fn name(&self) -> &'static str { "block" }
+    fn usage(&self) -> &'static str { "<user> [reason]" }
+    fn description(&self) -> &'static str { "block an account from logging in and disconnect it" }
+    fn admin_only(&self) -> bool { true }
+    fn run<'a>(&'a self, ctx: &'a CommandContext, args: &'a str) -> BoxFuture<'a, Result<String, EventError>> {
+        Box::pin(async move {
+            let (user, reason) = split_arg(args);
+            if user.is_empty() { return Err(usage(self)); }
+            if user.eq_ignore_ascii_case(&ctx.session.username) { return Err(invalid("you can not block yourself")); }
+            {
+                let conn = ctx.state.db.lock();
+                let Some(mut account) = user_model::find_by_username(&conn, user) else { return Err(invalid(format!("there is no account named {user}"))); };
+                if account.blocked() { return Err(invalid(format!("{user} is already blocked"))); }
+                account.is_blocked = 1;
+                user_model::update(&conn, &account).map_err(|e| EventError::new(ErrorCode::Internal, format!("failed to block {user}: {e}")))?;
+            }
+            let message = if reason.is_empty() { format!("blocked by {}", ctx.session.username) } else { format!("blocked by {}: {}", ctx.session.username, reason) };
+            disconnect_user(&ctx.state, user, &message);
+            info!("[*][command] '{}' blocked '{}'", ctx.session.username, user);
+            let reason = (!reason.is_empty()).then_some(reason);
+            moderation(ctx, ModerationAction { action: "user_blocked", moderator: &ctx.session.username, target: Some(user), reason, ..Default::default() });
+            Ok(format!("blocked {user}"))
+        })
+    }
+}
+
+struct Mute;
+
+impl SlashCommand for Mute {
+    fn name(&self) -> &'static str { "mute" }
+    fn usage(&self) -> &'static str { "<user> [minutes]" }
+    fn description(&self) -> &'static str { "stop a user from sending messages. 0 minutes unmutes" }
+    fn admin_only(&self) -> bool { true }
+    fn run<'a>(&'a self, ctx: &'a CommandContext, args: &'a str) -> BoxFuture<'a, Result<String, EventError>> {
+        Box::pin(async move {
+            let (user, minutes) = split_arg(args);
+            if user.is_empty() { return Err(usage(self)); }
+            let minutes = match minutes {
+                "" => DEFAULT_MUTE_MINUTES,
+                m => m.parse::<u64>().map_err(|_| usage(self))?,
+            };
+            if minutes > MAX_MUTE_MINUTES { return Err(invalid(format!("a mute can last at most {MAX_MUTE_MINUTES} minutes"))); }
+            let reply = if minutes == 0 {
+                if ctx.state.muted.remove(&user.to_lowercase()).is_none() { return Err(invalid(format!("{user} is not muted"))); }
+                format!("unmuted {user}")
+            } else {
+                ctx.state.muted.insert(user.to_lowercase(), Instant::now() + Duration::from_secs(minutes * 60));
+                format!("muted {user} for {minutes} minutes")
+            };
+            info!("[*][command] '{}' {}", ctx.session.username, reply);
+            moderation(ctx, ModerationAction { action: "user_muted", moderator: &ctx.session.username, target: Some(user), minutes: Some(minutes), ..Default::default() });
+            Ok(reply)
+        })
+    }
+}
@@ -0,0 +1,125 @@
+//! Slash commands of web users. A `message` starting with `/` is run as a command
+//! instead of being relayed. The output is a `systemMessage` to the issuing socket only.
+//! Start a message with `//` to send a literal slash.
+
+mod builtin;
+
+use std::sync::Arc;
+
+use futures::future::BoxFuture;
+use parking_lot::RwLock;
+use tracing::info;
+
+use crate::{
+    ack::{ErrorCode, EventError},
+    client::Client,
+    irc_bridge::ChannelMapping,
+    models::user::{self as user_model, UserRow},
+    state::{AppState, SessionUser},
+    types::{CommandStatus, SystemMessage},
+};
+
+/// What a command is run with
+pub struct CommandContext {
+    pub state: AppState,
+    pub client: Client,
+    pub session: SessionUser,
+    // the joined channel the command was typed in
+    pub mapping: ChannelMapping,
+}
+
+impl CommandContext {
+    /// The name new messages of this session are shown with
+    pub fn display_name(&self) -> &str {
+        self.session.display_name.as_deref().unwrap_or(&self.session.username)
+    }
+
+    /// The account of the session freshly read from the database
+    pub fn account(&self) -> Option<UserRow> {
+        let id = self.session.db_user.as_ref()?.id;
+        user_model::find(&self.state.db.lock(), id)
+    }
+
+    pub fn require_admin(&self) -> Result<UserRow, EventError> {
+        self.account()
+            .filter(|u| u.admin() && !u.blocked())
+            .ok_or_else(|| EventError::new(ErrorCode::PermissionDenied, "only admins can do that"))
+    }
+}
+
+/// A command like `/names`. Register new ones with [`CommandRegistry::register`].
+pub trait SlashCommand: Send + Sync {
+    /// Typed after the slash. Lowercase without spaces.
+    fn name(&self) -> &'static str;
+    /// Arguments shown by /help, for example `<user> [reason]`
+    fn usage(&self) -> &'static str { "" }
+    fn description(&self) -> &'static str;
+    /// Hidden from /help and refused for everyone but admins
+    fn admin_only(&self) -> bool { false }
+    /// Returns the text sent back to the issuer. Empty means no reply.
+    fn run<'a>(&'a self, ctx: &'a CommandContext, args: &'a str) -> BoxFuture<'a, Result<String, EventError>>;
+}
+
+#[derive(Default)]
+pub struct CommandRegistry {
+    commands: RwLock<Vec<Arc<dyn SlashCommand>>>,
+}
+
+impl CommandRegistry {
+    pub fn with_builtins() -> Self {
+        let registry = Self::default();
+        builtin::register(&registry);
+        registry
+    }
+
+    /// Replaces a command with the same name
+    pub fn register(&self, command: impl SlashCommand + 'static) {
+        let mut commands = self.commands.write();
+        commands.retain(|c| c.name() != command.name());
+        commands.push(Arc::new(command));
+    }
+
+    pub fn find(&self, name: &str) -> Option<Arc<dyn SlashCommand>> {
+        self.commands.read().iter().find(|c| c.name() == name).cloned()
+    }
+
+    /// Sorted by name
+    pub fn all(&self) -> Vec<Arc<dyn SlashCommand>> {
+        let mut commands = self.commands.read().clone();
+        commands.sort_by_key(|c| c.name());
+        commands
+    }
+}
+
+/// Runs `line`, the message without its leading slash, for the session of `c`
+/// in the channel of `mapping` and sends the output to `c`
+pub async fn execute(c: &Client, state: &AppState, session: SessionUser, mapping: ChannelMapping, line: &str, nonce: Option<String>) -> Result<CommandStatus, EventError> {
+    let (name, args) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+    let name = name.to_lowercase();
+    let ctx = CommandContext { state: state.clone(), client: c.clone(), session, mapping };
+    info!("[*][command] user='{}' ran /{} in '{}#{}'", ctx.session.username, name, ctx.mapping.discord_server, ctx.mapping.discord_channel);
+    let res = run(&ctx, &name, args.trim()).await;
+    let (message, error) = match &res {
+        Ok(text) => (text.clone(), false),
+        Err(e) => (e.message.clone(), true),
+    };
+    if !message.is_empty() {
+        let reply = SystemMessage {
+            channel: ctx.mapping.discord_channel.clone(),
+            server: ctx.mapping.discord_server.clone(),
+            command: name.clone(),
+            message,
+            error,
+        };
+        c.emit("systemMessage", &reply);
+    }
+    res.map(|_| CommandStatus { nonce, command: name })
+}
+
+async fn run(ctx: &CommandContext, name: &str, args: &str) -> Result<String, EventError> {
+    let Some(command) = ctx.state.commands.find(name) else {
+        return Err(EventError::new(ErrorCode::InvalidInput, format!("unknown command /{name}. try /help")));
+    };
+    if command.admin_only() { ctx.require_admin()?; }
+    command.run(ctx, args).await
+}
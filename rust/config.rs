@@ -1,68 +1,427 @@
-use anyhow::Result;
-use std::env;
+use std::{collections::BTreeMap, env, fmt, fs, net::SocketAddr, path::{Path, PathBuf}};
+
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::cli::CliArgs;
+
+pub const DEFAULT_CONFIG_PATH: &str = "config.toml";
 
 #[derive(Debug, Clone)]
 pub struct Config {
     pub require_passwords: bool,
     pub dry_irc: bool,
-    pub irc_server: String,
-    pub irc_channel: String,
     pub accounts_password: String,
     pub admin_token: String,
+    pub sign_up_token: Option<String>,
     pub backlog_size: usize,
-    pub irc_login_channel: Option<String>,
-    pub irc_login_msg: Option<String>,
+    pub http_bind: SocketAddr,
+    // serves /metrics without auth on a separate address. On the main address it needs the admin token
+    pub metrics_bind: Option<SocketAddr>,
+    pub db_path: PathBuf,
+    pub history_path: PathBuf,
+    // 0 disables periodic snapshots. history is then only saved on shutdown
+    pub history_snapshot_interval_secs: u64,
+    pub irc_networks: Vec<IrcNetwork>,
+    // irc can not edit so corrections are sent as new lines. {nick} and {message} get replaced
+    pub irc_edit_format: String,
+    // empty means deletions are not announced on irc
+    pub irc_delete_format: String,
+    pub rate_limit: RateLimitConfig,
+    pub subscriptions: SubscriptionConfig,
+    // in-process message handlers in the order they run
+    pub plugins: Vec<PluginConfig>,
+    // how long a plugin may take for one message before it is skipped
+    pub plugin_timeout_ms: u64,
+    // sent to irc as QUIT reason on shutdown
+    pub irc_quit_message: String,
+    // how long shutdown waits for running handlers, irc and the http server
+    pub shutdown_timeout_secs: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct IrcNetwork {
+    // matches channels.irc_server_name
+    pub name: String,
+    pub server: String,
+    #[serde(default = "default_irc_port")]
+    pub port: u16,
+    #[serde(default = "default_true")]
+    pub tls: bool,
+    #[serde(default = "default_irc_nickname")]
+    pub nickname: String,
+    pub login_channel: Option<String>,
+    pub login_msg: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct RateLimitConfig {
+    // amount of fast messages allowed within window_ms
+    pub burst: usize,
+    pub window_ms: i64,
+    // messages sent faster than this count towards the burst
+    pub min_interval_ms: i64,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self { burst: 5, window_ms: 8000, min_interval_ms: 3000 }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct SubscriptionConfig {
+    // attempts per event including the first one
+    pub max_attempts: u32,
+    // wait before the first retry, doubled for every further retry
+    pub retry_delay_ms: u64,
+    pub timeout_ms: u64,
+}
+
+impl Default for SubscriptionConfig {
+    fn default() -> Self {
+        Self { max_attempts: 5, retry_delay_ms: 1000, timeout_ms: 10000 }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PluginConfig {
+    // name of a registered plugins::MessageHandler
+    pub name: String,
+    // server#channel of the web side. empty enables it in every channel
+    #[serde(default)]
+    pub channels: Vec<String>,
+    // replies and reactions are made as this account. without one replies show the plugin name
+    pub account: Option<String>,
+    // passed to the plugin as is
+    #[serde(default)]
+    pub options: BTreeMap<String, String>,
+}
+
+impl PluginConfig {
+    pub fn is_enabled_in(&self, server: &str, channel: &str) -> bool {
+        self.channels.is_empty() || self.channels.iter().any(|c| c.split_once('#') == Some((server, channel)))
+    }
+}
+
+fn default_irc_port() -> u16 { 6667 }
+fn default_true() -> bool { true }
+fn default_irc_nickname() -> String { "ws-client".into() }
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("failed to read config file '{path}': {source}")]
+    Read { path: PathBuf, source: std::io::Error },
+    #[error("failed to parse config file '{path}': {message}")]
+    Parse { path: PathBuf, message: String },
+    #[error("{name} has an invalid value '{value}': {reason}")]
+    InvalidValue { name: String, value: String, reason: String },
+    #[error("{0} is not set! check your .env or config file")]
+    Missing(&'static str),
+    #[error("using the default ADMIN_TOKEN is not allowed")]
+    DefaultAdminToken,
+    #[error("{0}")]
+    Invalid(String),
+}
+
+/// All problems found while loading the config
+#[derive(Debug)]
+pub struct ConfigErrors(pub Vec<ConfigError>);
+
+impl fmt::Display for ConfigErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "invalid configuration:")?;
+        for e in &self.0 {
+            writeln!(f, "  - {e}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigErrors {}
+
+// everything is optional in the file so it can be layered on top of the defaults
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileConfig {
+    require_passwords: Option<bool>,
+    accounts_password: Option<String>,
+    admin_token: Option<String>,
+    sign_up_token: Option<String>,
+    backlog_size: Option<usize>,
+    shutdown_timeout_secs: Option<u64>,
+    plugin_timeout_ms: Option<u64>,
+    #[serde(default)]
+    http: FileHttp,
+    #[serde(default)]
+    metrics: FileHttp,
+    #[serde(default)]
+    database: FilePath,
+    #[serde(default)]
+    history: FileHistory,
+    #[serde(default)]
+    irc: FileIrc,
+    rate_limit: Option<RateLimitConfig>,
+    subscriptions: Option<SubscriptionConfig>,
+    #[serde(default)]
+    plugins: Vec<PluginConfig>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileHttp { bind: Option<String> }
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FilePath { path: Option<PathBuf> }
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileHistory {
+    path: Option<PathBuf>,
+    snapshot_interval_secs: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileIrc {
+    dry: Option<bool>,
+    edit_format: Option<String>,
+    delete_format: Option<String>,
+    quit_message: Option<String>,
+    #[serde(default)]
+    networks: Vec<IrcNetwork>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            require_passwords: false,
+            dry_irc: false,
+            accounts_password: String::new(),
+            admin_token: String::new(),
+            sign_up_token: None,
+            backlog_size: 30,
+            http_bind: SocketAddr::from(([0, 0, 0, 0], 6969)),
+            metrics_bind: None,
+            db_path: PathBuf::from("./db/main.db"),
+            history_path: PathBuf::from("message_log.json"),
+            history_snapshot_interval_secs: 60,
+            irc_networks: vec![],
+            irc_edit_format: "* {nick} meant: {message}".into(),
+            irc_delete_format: String::new(),
+            rate_limit: RateLimitConfig::default(),
+            subscriptions: SubscriptionConfig::default(),
+            plugins: vec![],
+            plugin_timeout_ms: 2000,
+            irc_quit_message: "bridge shutting down".into(),
+            shutdown_timeout_secs: 10,
+        }
+    }
 }
 
 fn is_true(val: &str) -> bool {
     matches!(val.to_ascii_lowercase().as_str(), "1"|"true"|"yes"|"on")
 }
 
+fn parse_env<T: std::str::FromStr>(name: &str, errors: &mut Vec<ConfigError>) -> Option<T>
+where T::Err: fmt::Display {
+    let value = env::var(name).ok()?;
+    match value.parse() {
+        Ok(v) => Some(v),
+        Err(e) => {
+            errors.push(ConfigError::InvalidValue { name: name.into(), value, reason: e.to_string() });
+            None
+        }
+    }
+}
+
 impl Config {
-    pub fn from_env() -> Result<Self> {
-        let require_passwords = is_true(&env::var("ACCOUNTS").unwrap_or_else(|_| "0".into()));
-        let dry_irc = is_true(&env::var("DRY_IRC").unwrap_or_else(|_| "0".into()));
-        let irc_server = env::var("IRC_SERVER").unwrap_or_default();
-        let irc_channel = env::var("IRC_CHANNEL").unwrap_or_default();
-        let accounts_password = env::var("ACCOUNTS_PASSWORD").unwrap_or_default();
-        let admin_token = env::var("ADMIN_TOKEN").unwrap_or_default();
-        let backlog_size: usize = env::var("BACKLOG_SIZE").unwrap_or_else(|_| "30".into()).parse().unwrap_or(30);
-        let irc_login_channel = env::var("IRC_LOGIN_CHANNEL").ok();
-        let irc_login_msg = env::var("IRC_LOGIN_MSG").ok();
-
-        Ok(Self {
-            require_passwords,
-            dry_irc,
-            irc_server,
-            irc_channel,
-            accounts_password,
-            admin_token,
-            backlog_size,
-            irc_login_channel,
-            irc_login_msg,
-        })
+    /// The config file from CONFIG_FILE or config.toml if it exists
+    pub fn default_path() -> Option<PathBuf> {
+        if let Ok(path) = env::var("CONFIG_FILE") { return Some(path.into()); }
+        let path = Path::new(DEFAULT_CONFIG_PATH);
+        path.exists().then(|| path.to_path_buf())
+    }
+
+    /// Defaults, overwritten by the config file, overwritten by env vars,
+    /// overwritten by command line flags.
+    /// Collects every problem instead of stopping at the first one.
+    pub fn load(cli: &CliArgs) -> Result<Self, ConfigErrors> {
+        let mut errors = Vec::new();
+        let cfg = Self::load_layers(cli, &mut errors);
+        cfg.validate(&mut errors);
+        if errors.is_empty() { Ok(cfg) } else { Err(ConfigErrors(errors)) }
     }
 
-    pub fn validate_or_exit(&self) {
-        if self.irc_channel.is_empty() {
-            eprintln!("Error: IRC_CHANNEL is not set! check your .env file");
-            std::process::exit(1);
+    /// Same as load but skips validation. For tools like db-cli
+    /// that only need the paths and should work without irc or admin settings.
+    pub fn load_unvalidated(cli: &CliArgs) -> Result<Self, ConfigErrors> {
+        let mut errors = Vec::new();
+        let cfg = Self::load_layers(cli, &mut errors);
+        if errors.is_empty() { Ok(cfg) } else { Err(ConfigErrors(errors)) }
+    }
+
+    fn load_layers(cli: &CliArgs, errors: &mut Vec<ConfigError>) -> Self {
+        let mut cfg = Config::default();
+        let path = cli.config_path.clone().or_else(Self::default_path);
+        if let Some(path) = path.as_deref() {
+            match fs::read_to_string(path) {
+                Ok(s) => match toml::from_str::<FileConfig>(&s) {
+                    Ok(file) => cfg.apply_file(file, errors),
+                    Err(e) => errors.push(ConfigError::Parse { path: path.into(), message: e.message().to_string() }),
+                },
+                Err(source) => errors.push(ConfigError::Read { path: path.into(), source }),
+            }
         }
-        if self.irc_server.is_empty() {
-            eprintln!("Error: IRC_SERVER is not set! check your .env file");
-            std::process::exit(1);
+        cfg.apply_env(errors);
+        if let Some(v) = &cli.db_path { cfg.db_path = v.clone(); }
+        if let Some(v) = &cli.history_path { cfg.history_path = v.clone(); }
+        cfg
+    }
+
+    fn apply_file(&mut self, file: FileConfig, errors: &mut Vec<ConfigError>) {
+        if let Some(v) = file.require_passwords { self.require_passwords = v; }
+        if let Some(v) = file.accounts_password { self.accounts_password = v; }
+        if let Some(v) = file.admin_token { self.admin_token = v; }
+        if let Some(v) = file.sign_up_token { self.sign_up_token = Some(v); }
+        if let Some(v) = file.backlog_size { self.backlog_size = v; }
+        if let Some(v) = file.shutdown_timeout_secs { self.shutdown_timeout_secs = v; }
+        if let Some(bind) = file.http.bind {
+            match bind.parse() {
+                Ok(addr) => self.http_bind = addr,
+                Err(e) => errors.push(ConfigError::InvalidValue { name: "http.bind".into(), value: bind, reason: format!("{e}") }),
+            }
         }
-        if self.accounts_password.is_empty() {
-            eprintln!("Error: ACCOUNTS_PASSWORD is not set! check your .env file");
-            std::process::exit(1);
+        if let Some(bind) = file.metrics.bind {
+            match bind.parse() {
+                Ok(addr) => self.metrics_bind = Some(addr),
+                Err(e) => errors.push(ConfigError::InvalidValue { name: "metrics.bind".into(), value: bind, reason: format!("{e}") }),
+            }
         }
-        if self.admin_token.is_empty() {
-            eprintln!("Error: ADMIN_TOKEN is not set! check your .env file");
-            std::process::exit(1);
+        if let Some(v) = file.database.path { self.db_path = v; }
+        if let Some(v) = file.history.path { self.history_path = v; }
+        if let Some(v) = file.history.snapshot_interval_secs { self.history_snapshot_interval_secs = v; }
+        if let Some(v) = file.irc.dry { self.dry_irc = v; }
+        if let Some(v) = file.irc.edit_format { self.irc_edit_format = v; }
+        if let Some(v) = file.irc.delete_format { self.irc_delete_format = v; }
+        if let Some(v) = file.irc.quit_message { self.irc_quit_message = v; }
+        self.irc_networks = file.irc.networks;
+        if let Some(v) = file.rate_limit { self.rate_limit = v; }
+        if let Some(v) = file.subscriptions { self.subscriptions = v; }
+        self.plugins = file.plugins;
+        if let Some(v) = file.plugin_timeout_ms { self.plugin_timeout_ms = v; }
+    }
+
+    fn apply_env(&mut self, errors: &mut Vec<ConfigError>) {
+        if let Ok(v) = env::var("ACCOUNTS") { self.require_passwords = is_true(&v); }
+        if let Ok(v) = env::var("DRY_IRC") { self.dry_irc = is_true(&v); }
+        if let Ok(v) = env::var("ACCOUNTS_PASSWORD") { self.accounts_password = v; }
+        if let Ok(v) = env::var("ADMIN_TOKEN") { self.admin_token = v; }
+        if let Ok(v) = env::var("SIGN_UP_TOKEN") { self.sign_up_token = Some(v); }
+        if let Some(v) = parse_env("BACKLOG_SIZE", errors) { self.backlog_size = v; }
+        if let Some(v) = parse_env("HTTP_BIND", errors) { self.http_bind = v; }
+        if let Some(v) = parse_env("METRICS_BIND", errors) { self.metrics_bind = Some(v); }
+        if let Ok(v) = env::var("DB_PATH") { self.db_path = v.into(); }
+        if let Ok(v) = env::var("HISTORY_PATH") { self.history_path = v.into(); }
+        if let Some(v) = parse_env("HISTORY_SNAPSHOT_INTERVAL_SECS", errors) { self.history_snapshot_interval_secs = v; }
+        if let Ok(v) = env::var("IRC_EDIT_FORMAT") { self.irc_edit_format = v; }
+        if let Ok(v) = env::var("IRC_DELETE_FORMAT") { self.irc_delete_format = v; }
+        if let Ok(v) = env::var("IRC_QUIT_MESSAGE") { self.irc_quit_message = v; }
+        if let Some(v) = parse_env("SHUTDOWN_TIMEOUT_SECS", errors) { self.shutdown_timeout_secs = v; }
+        if let Some(v) = parse_env("RATE_LIMIT_BURST", errors) { self.rate_limit.burst = v; }
+        if let Some(v) = parse_env("RATE_LIMIT_WINDOW_MS", errors) { self.rate_limit.window_ms = v; }
+        if let Some(v) = parse_env("RATE_LIMIT_MIN_INTERVAL_MS", errors) { self.rate_limit.min_interval_ms = v; }
+        if let Some(v) = parse_env("SUBSCRIPTION_MAX_ATTEMPTS", errors) { self.subscriptions.max_attempts = v; }
+        if let Some(v) = parse_env("SUBSCRIPTION_RETRY_DELAY_MS", errors) { self.subscriptions.retry_delay_ms = v; }
+        if let Some(v) = parse_env("SUBSCRIPTION_TIMEOUT_MS", errors) { self.subscriptions.timeout_ms = v; }
+        if let Some(v) = parse_env("PLUGIN_TIMEOUT_MS", errors) { self.plugin_timeout_ms = v; }
+
+        // the legacy single network setup from the .env file is called quakenet
+        let login_channel = env::var("IRC_LOGIN_CHANNEL").ok();
+        let login_msg = env::var("IRC_LOGIN_MSG").ok();
+        if let Ok(server) = env::var("IRC_SERVER") {
+            match self.irc_networks.iter_mut().find(|n| n.name == "quakenet") {
+                Some(network) => network.server = server,
+                None => self.irc_networks.push(IrcNetwork {
+                    name: "quakenet".into(),
+                    server,
+                    port: default_irc_port(),
+                    tls: true,
+                    nickname: default_irc_nickname(),
+                    login_channel: None,
+                    login_msg: None,
+                }),
+            }
         }
-        if self.admin_token == "xxx" {
-            eprintln!("Error: using the default ADMIN_TOKEN is not allowed");
-            std::process::exit(1);
+        if let Some(network) = self.irc_networks.iter_mut().find(|n| n.name == "quakenet") {
+            if login_channel.is_some() { network.login_channel = login_channel; }
+            if login_msg.is_some() { network.login_msg = login_msg; }
         }
     }
+
+    fn validate(&self, errors: &mut Vec<ConfigError>) {
+        if self.accounts_password.is_empty() { errors.push(ConfigError::Missing("ACCOUNTS_PASSWORD")); }
+        if self.admin_token.is_empty() { errors.push(ConfigError::Missing("ADMIN_TOKEN")); }
+        if self.admin_token == "xxx" { errors.push(ConfigError::DefaultAdminToken); }
+        if self.backlog_size == 0 { errors.push(ConfigError::Invalid("backlog_size has to be at least 1".into())); }
+        if self.irc_networks.is_empty() { errors.push(ConfigError::Missing("IRC_SERVER")); }
+        for (i, network) in self.irc_networks.iter().enumerate() {
+            if network.name.is_empty() { errors.push(ConfigError::Invalid(format!("irc network #{} has no name", i + 1))); }
+            if network.server.is_empty() { errors.push(ConfigError::Invalid(format!("irc network '{}' has no server", network.name))); }
+            if self.irc_networks[..i].iter().any(|n| n.name == network.name) {
+                errors.push(ConfigError::Invalid(format!("irc network '{}' is configured twice", network.name)));
+            }
+        }
+        if self.rate_limit.window_ms <= 0 || self.rate_limit.min_interval_ms < 0 {
+            errors.push(ConfigError::Invalid("rate_limit.window_ms has to be positive and min_interval_ms not negative".into()));
+        }
+        if self.subscriptions.max_attempts == 0 || self.subscriptions.timeout_ms == 0 {
+            errors.push(ConfigError::Invalid("subscriptions.max_attempts and timeout_ms have to be at least 1".into()));
+        }
+        if self.plugin_timeout_ms == 0 { errors.push(ConfigError::Invalid("plugin_timeout_ms has to be at least 1".into())); }
+        for (i, plugin) in self.plugins.iter().enumerate() {
+            if plugin.name.is_empty() { errors.push(ConfigError::Invalid(format!("plugin #{} has no name", i + 1))); }
+            if self.plugins[..i].iter().any(|p| p.name == plugin.name) {
+                errors.push(ConfigError::Invalid(format!("plugin '{}' is configured twice", plugin.name)));
+            }
+            for channel in &plugin.channels {
+                if !channel.contains('#') {
+                    errors.push(ConfigError::Invalid(format!("channel '{}' of plugin '{}' is not in the form server#channel", channel, plugin.name)));
+                }
+            }
+        }
+    }
+
+    /// Takes over the settings that can change at runtime.
+    /// Returns the names of changed settings that need a restart.
+    pub fn reload_from(&mut self, new: Config) -> Vec<&'static str> {
+        let mut needs_restart = Vec::new();
+        if self.http_bind != new.http_bind { needs_restart.push("http.bind"); }
+        if self.metrics_bind != new.metrics_bind { needs_restart.push("metrics.bind"); }
+        if self.db_path != new.db_path { needs_restart.push("database.path"); }
+        if self.history_path != new.history_path { needs_restart.push("history.path"); }
+        if self.backlog_size != new.backlog_size { needs_restart.push("backlog_size"); }
+        if self.dry_irc != new.dry_irc { needs_restart.push("irc.dry"); }
+        if self.irc_networks != new.irc_networks { needs_restart.push("irc.networks"); }
+
+        self.require_passwords = new.require_passwords;
+        self.accounts_password = new.accounts_password;
+        self.admin_token = new.admin_token;
+        self.sign_up_token = new.sign_up_token;
+        self.irc_edit_format = new.irc_edit_format;
+        self.irc_delete_format = new.irc_delete_format;
+        self.rate_limit = new.rate_limit;
+        self.subscriptions = new.subscriptions;
+        self.plugins = new.plugins;
+        self.plugin_timeout_ms = new.plugin_timeout_ms;
+        self.irc_quit_message = new.irc_quit_message;
+        self.shutdown_timeout_secs = new.shutdown_timeout_secs;
+        self.history_snapshot_interval_secs = new.history_snapshot_interval_secs;
+        needs_restart
+    }
 }
@@ -0,0 +1,168 @@
+use std::{fs, path::{Path, PathBuf}};
+
+use anyhow::{Context, Result};
+use include_dir::{include_dir, Dir};
+use regex::Regex;
+use rusqlite::Connection;
+use thiserror::Error;
+
+// the migrations this binary was built with. does not depend on the working directory
+static EMBEDDED_MIGRATIONS: Dir<'_> = include_dir!("$CARGO_MANIFEST_DIR/db/migrations");
+
+#[derive(Debug, Clone)]
+pub struct Migration {
+    // unix timestamp prefix of the file name. stored as user_version once applied
+    pub version: i64,
+    pub name: String,
+    pub path: PathBuf,
+    // <version>_<name>.down.sql next to it. needed for rollback
+    pub down_path: Option<PathBuf>,
+}
+
+#[derive(Debug, Error)]
+pub enum MigrationError {
+    #[error("unsafe migration {0} detected. Re-run with --force if intended.")]
+    Unsafe(String),
+    #[error("{0} has no .down.sql file and can not be rolled back")]
+    MissingDown(String),
+    #[error("the database is at version {0} but there is no migration file for it")]
+    UnknownVersion(i64),
+    #[error("invalid migration name '{0}'. only letters, digits and _ are allowed")]
+    InvalidName(String),
+}
+
+pub fn open(path: &Path) -> Result<Connection> {
+    let conn = Connection::open(path).with_context(|| format!("failed to open database '{}'", path.display()))?;
+    conn.pragma_update(None, "journal_mode", "WAL")?;
+    Ok(conn)
+}
+
+pub fn default_migrations_dir() -> PathBuf {
+    PathBuf::from("db/migrations")
+}
+
+pub fn get_db_version(conn: &Connection) -> Result<i64> {
+    Ok(conn.query_row("PRAGMA user_version", [], |row| row.get(0))?)
+}
+
+pub fn set_db_version(conn: &Connection, version: i64) -> Result<()> {
+    conn.pragma_update(None, "user_version", version)?;
+    Ok(())
+}
+
+/// The version of an up migration file name. None for down files and anything else
+fn migration_version(name: &str) -> Option<i64> {
+    let up_re = Regex::new(r"^(\d{10})_[a-zA-Z0-9_]+\.sql$").unwrap();
+    up_re.captures(name)?[1].parse().ok()
+}
+
+/// All migrations in `dir` sorted from oldest to newest
+pub fn migrations(dir: &Path) -> Result<Vec<Migration>> {
+    let entries = fs::read_dir(dir).with_context(|| format!("failed to read migrations dir '{}'", dir.display()))?;
+    let mut out = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        let Some(version) = migration_version(&name) else { continue; };
+        let down_path = entry.path().with_extension("down.sql");
+        out.push(Migration {
+            version,
+            down_path: down_path.exists().then_some(down_path),
+            name,
+            path: entry.path(),
+        });
+    }
+    out.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(out)
+}
+
+pub fn pending_migrations(conn: &Connection, dir: &Path) -> Result<Vec<Migration>> {
+    let latest = get_db_version(conn)?;
+    Ok(migrations(dir)?.into_iter().filter(|m| m.version > latest).collect())
+}
+
+/// Names of the migrations compiled into the binary that the database did not run yet
+pub fn pending_embedded_migrations(conn: &Connection) -> Result<Vec<String>> {
+    let latest = get_db_version(conn)?;
+    let mut pending: Vec<String> = EMBEDDED_MIGRATIONS.files()
+        .filter_map(|f| f.path().file_name()?.to_str())
+        .filter(|name| migration_version(name).is_some_and(|v| v > latest))
+        .map(String::from)
+        .collect();
+    pending.sort();
+    Ok(pending)
+}
+
+/// Migrations that drop tables or contain a warning comment need `force`
+pub fn is_unsafe(sql: &str) -> bool {
+    let lower = sql.to_lowercase();
+    lower.contains("warning") || lower.contains("drop table")
+}
+
+/// Runs `sql` and sets the version atomically so a failure leaves no half applied schema.
+/// Uses a savepoint so it also works inside of `dry_run`.
+fn run_atomic(conn: &Connection, name: &str, sql: &str, version: i64) -> Result<()> {
+    conn.execute_batch("SAVEPOINT migration")?;
+    let res = conn.execute_batch(sql)
+        .with_context(|| format!("failed to apply {}", name))
+        .and_then(|_| set_db_version(conn, version));
+    if res.is_ok() {
+        conn.execute_batch("RELEASE migration")?;
+    } else {
+        conn.execute_batch("ROLLBACK TO migration; RELEASE migration")?;
+    }
+    res
+}
+
+/// Runs `f` in a transaction that is always rolled back
+pub fn dry_run<T>(conn: &Connection, f: impl FnOnce() -> Result<T>) -> Result<T> {
+    conn.execute_batch("BEGIN")?;
+    let res = f();
+    conn.execute_batch("ROLLBACK")?;
+    res
+}
+
+pub fn apply_migration(conn: &Connection, migration: &Migration, force: bool) -> Result<()> {
+    let sql = fs::read_to_string(&migration.path).with_context(|| format!("failed to read {}", migration.name))?;
+    if is_unsafe(&sql) && !force {
+        return Err(MigrationError::Unsafe(migration.name.clone()).into());
+    }
+    run_atomic(conn, &migration.name, &sql, migration.version)
+}
+
+/// Runs the down file of the applied migration `migration`.
+/// `previous` is the version the database is at afterwards.
+pub fn rollback_migration(conn: &Connection, migration: &Migration, previous: i64, force: bool) -> Result<()> {
+    let Some(down_path) = &migration.down_path else {
+        return Err(MigrationError::MissingDown(migration.name.clone()).into());
+    };
+    let sql = fs::read_to_string(down_path).with_context(|| format!("failed to read {}", down_path.display()))?;
+    if is_unsafe(&sql) && !force {
+        return Err(MigrationError::Unsafe(down_path.display().to_string()).into());
+    }
+    run_atomic(conn, &migration.name, &sql, previous)
+}
+
+/// Runs all pending migrations and returns the applied ones
+pub fn migrate(conn: &Connection, dir: &Path, force: bool) -> Result<Vec<Migration>> {
+    let pending = pending_migrations(conn, dir)?;
+    for migration in &pending {
+        apply_migration(conn, migration, force)?;
+    }
+    Ok(pending)
+}
+
+/// Creates `<timestamp>_<name>.sql` and its `.down.sql`.
+/// The timestamp is bumped if needed so the new file sorts last.
+pub fn new_migration(dir: &Path, name: &str) -> Result<(PathBuf, PathBuf)> {
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return Err(MigrationError::InvalidName(name.to_string()).into());
+    }
+    let latest = migrations(dir)?.last().map(|m| m.version).unwrap_or(0);
+    let version = chrono::Utc::now().timestamp().max(latest + 1);
+    let up = dir.join(format!("{}_{}.sql", version, name));
+    let down = dir.join(format!("{}_{}.down.sql", version, name));
+    fs::write(&up, format!("-- {}\n", name.replace('_', " ")))?;
+    fs::write(&down, format!("-- undo {}\n", name.replace('_', " ")))?;
+    Ok((up, down))
+}
@@ -0,0 +1,121 @@
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::{client, db, irc_bridge::IrcStatus, state::AppState};
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct DbHealth {
+    pub reachable: bool,
+    // None if the version of the database could not be read
+    pub migrated: Option<bool>,
+    pub version: Option<i64>,
+    #[serde(rename = "pendingMigrations")]
+    pub pending_migrations: Vec<String>,
+    pub error: Option<String>,
+}
+
+impl DbHealth {
+    pub fn is_ready(&self) -> bool {
+        self.reachable && self.migrated == Some(true)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct NetworkHealth {
+    pub name: String,
+    pub ready: bool,
+    #[serde(flatten)]
+    pub status: IrcStatus,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Readiness {
+    // ok or degraded
+    pub status: &'static str,
+    pub database: DbHealth,
+    pub irc: Vec<NetworkHealth>,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct SessionCounts {
+    pub sockets: usize,
+    pub sessions: usize,
+    #[serde(rename = "loggedIn")]
+    pub logged_in: usize,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct HistoryHealth {
+    pub messages: usize,
+    #[serde(rename = "lastSnapshotAt")]
+    pub last_snapshot_at: Option<String>,
+    #[serde(rename = "loadError")]
+    pub load_error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Status {
+    #[serde(flatten)]
+    pub readiness: Readiness,
+    pub version: &'static str,
+    #[serde(rename = "uptimeSecs")]
+    pub uptime_secs: u64,
+    pub sessions: SessionCounts,
+    pub history: HistoryHealth,
+}
+
+pub fn db_health(state: &AppState) -> DbHealth {
+    let conn = state.db.lock();
+    let mut health = DbHealth { reachable: false, migrated: None, version: None, pending_migrations: vec![], error: None };
+    if let Err(e) = conn.query_row("SELECT 1", [], |_| Ok(())) {
+        health.error = Some(e.to_string());
+        return health;
+    }
+    health.reachable = true;
+    health.version = db::get_db_version(&conn).ok();
+    match db::pending_embedded_migrations(&conn) {
+        Ok(pending) => {
+            health.migrated = Some(pending.is_empty());
+            health.pending_migrations = pending;
+        }
+        Err(e) => health.error = Some(e.to_string()),
+    }
+    health
+}
+
+pub fn irc_health(state: &AppState) -> Vec<NetworkHealth> {
+    let networks = state.config.lock().irc_networks.clone();
+    let statuses = state.irc_status.lock();
+    networks.into_iter()
+        .map(|network| {
+            let status = statuses.get(&network.name).cloned().unwrap_or_default();
+            NetworkHealth { ready: status.is_ready(), name: network.name, status }
+        })
+        .collect()
+}
+
+pub fn readiness(state: &AppState) -> Readiness {
+    let database = db_health(state);
+    let irc = irc_health(state);
+    let ready = database.is_ready() && irc.iter().all(|n| n.ready);
+    Readiness { status: if ready { "ok" } else { "degraded" }, database, irc }
+}
+
+pub fn status(state: &AppState) -> Status {
+    let sockets = client::count(state);
+    Status {
+        readiness: readiness(state),
+        version: env!("CARGO_PKG_VERSION"),
+        uptime_secs: state.started_at.elapsed().as_secs(),
+        sessions: SessionCounts {
+            sockets,
+            sessions: state.sessions.len(),
+            logged_in: state.sessions.iter().filter(|s| s.logged_in).count(),
+        },
+        history: HistoryHealth {
+            messages: state.history.message_count(),
+            last_snapshot_at: state.history.last_snapshot_at(),
+            load_error: state.history.load_error().map(str::to_string),
+        },
+    }
+}
@@ -1,17 +1,26 @@
-use std::{collections::HashMap, fs, path::Path};
+use std::{collections::{HashMap, VecDeque}, fs, path::{Path, PathBuf}, sync::Arc};
 
-use regex::Regex;
+use once_cell::sync::Lazy;
+use regex::{Regex, RegexBuilder};
 use serde::{Deserialize, Serialize};
-use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicI64, Ordering};
 use parking_lot::Mutex;
 
-use crate::types::IrcMessage;
+use crate::types::{IrcMessage, MessageEdit};
 
 #[derive(Clone)]
 pub struct HistoryStore {
+    path: PathBuf,
     max_backlog: usize,
     messages: ArcRobin,
     latest_id: std::sync::Arc<AtomicI64>,
+    // set by every change, cleared by a successful snapshot
+    dirty: Arc<AtomicBool>,
+    // only one snapshot writes the temp file at a time
+    save_lock: Arc<Mutex<()>>,
+    last_snapshot_at: Arc<Mutex<Option<String>>>,
+    // why the file on disk could not be loaded. reported in the admin status
+    load_error: Option<String>,
 }
 
 type ArcRobin = std::sync::Arc<Mutex<HashMap<String, Vec<IrcMessage>>>>;
@@ -20,13 +29,24 @@ type ArcRobin = std::sync::Arc<Mutex<HashMap<String, Vec<IrcMessage>>>>;
 struct DiskFormat(HashMap<String, Vec<IrcMessage>>);
 
 impl HistoryStore {
-    pub fn load(max_backlog: usize) -> Self {
-        let path = Path::new("message_log.json");
+    /// A file that can not be parsed is moved aside instead of being overwritten
+    /// by the next snapshot. The store then starts empty.
+    pub fn load(path: &Path, max_backlog: usize) -> Self {
+        let mut load_error = None;
         let robin: HashMap<String, Vec<IrcMessage>> = if path.exists() {
-            tracing::info!("[*] loading message_log.json ...");
-            match fs::read_to_string(path) {
-                Ok(s) => serde_json::from_str::<DiskFormat>(&s).map(|d| d.0).unwrap_or_default(),
-                Err(_) => HashMap::new(),
+            tracing::info!("[*] loading {} ...", path.display());
+            match fs::read_to_string(path).map_err(anyhow::Error::from).and_then(|s| Ok(serde_json::from_str::<DiskFormat>(&s)?)) {
+                Ok(d) => d.0,
+                Err(e) => {
+                    let backup = PathBuf::from(format!("{}.corrupt-{}", path.display(), chrono::Utc::now().format("%Y%m%d%H%M%S")));
+                    let error = match fs::rename(path, &backup) {
+                        Ok(()) => format!("failed to load {}: {e}. kept it as {}", path.display(), backup.display()),
+                        Err(re) => format!("failed to load {}: {e}. moving it to {} failed too: {re}", path.display(), backup.display()),
+                    };
+                    tracing::error!("[!] {}", error);
+                    load_error = Some(error);
+                    HashMap::new()
+                }
             }
         } else {
             HashMap::new()
@@ -37,9 +57,14 @@ impl HistoryStore {
             tracing::info!("[*] loaded {} messages continuing at id {}", num_msgs, latest);
         }
         Self {
+            path: path.to_path_buf(),
             max_backlog,
             messages: std::sync::Arc::new(Mutex::new(robin)),
             latest_id: std::sync::Arc::new(AtomicI64::new(latest)),
+            dirty: Arc::new(AtomicBool::new(false)),
+            save_lock: Arc::new(Mutex::new(())),
+            last_snapshot_at: Arc::new(Mutex::new(None)),
+            load_error,
         }
     }
 
@@ -54,11 +79,25 @@ impl HistoryStore {
         self.latest_id.fetch_add(1, Ordering::SeqCst) + 1
     }
 
+    pub fn latest_id(&self) -> i64 {
+        self.latest_id.load(Ordering::SeqCst)
+    }
+
+    /// Makes sure new ids are above `used`, an id the database already knows.
+    /// The history file can be older than the database after a crash or a corrupt file.
+    pub fn skip_used_ids(&self, used: i64) {
+        let before = self.latest_id.fetch_max(used, Ordering::SeqCst);
+        if used > before {
+            tracing::warn!("[!] the history only knew ids up to {} continuing at id {}", before, used);
+        }
+    }
+
     pub fn channel_uid(server: &str, channel: &str) -> String {
         format!("{}#{}", server, channel)
     }
 
     pub fn log_message(&self, server: &str, channel: &str, msg: IrcMessage) {
+        self.dirty.store(true, Ordering::SeqCst);
         let key = Self::channel_uid(server, channel);
         let mut guard = self.messages.lock();
         let entry = guard.entry(key).or_default();
@@ -70,38 +109,140 @@ impl HistoryStore {
         }
     }
 
-    pub fn save_to_disk(&self) -> anyhow::Result<()> {
+    pub fn message_count(&self) -> usize {
+        self.messages.lock().values().map(|msgs| msgs.len()).sum()
+    }
+
+    pub fn all_messages(&self) -> Vec<IrcMessage> {
+        let guard = self.messages.lock();
+        guard.values().flatten().cloned().collect()
+    }
+
+    pub fn find_message(&self, server: &str, channel: &str, id: i64) -> Option<IrcMessage> {
+        let key = Self::channel_uid(server, channel);
+        let guard = self.messages.lock();
+        guard.get(&key)?.iter().find(|m| m.id == id).cloned()
+    }
+
+    /// IRC has no replies so `nick: text` is linked to the last message of nick.
+    pub fn guess_reply_to(&self, server: &str, channel: &str, text: &str) -> Option<i64> {
+        let (nick, rest) = text.split_once([':', ','])?;
+        if nick.is_empty() || nick.contains(char::is_whitespace) || !rest.starts_with(' ') { return None; }
+        let key = Self::channel_uid(server, channel);
         let guard = self.messages.lock();
-        let serialized = serde_json::to_string(&DiskFormat(guard.clone()))?;
-        fs::write("message_log.json", serialized)?;
+        guard.get(&key)?.iter().rev()
+            .find(|m| m.deleted_at.is_none() && m.from.eq_ignore_ascii_case(nick))
+            .map(|m| m.id)
+    }
+
+    /// Replaces the text and keeps the old one in `edits`. Returns the updated message.
+    pub fn edit_message(&self, server: &str, channel: &str, id: i64, text: &str) -> Option<IrcMessage> {
+        self.dirty.store(true, Ordering::SeqCst);
+        let key = Self::channel_uid(server, channel);
+        let mut guard = self.messages.lock();
+        let msg = guard.get_mut(&key)?.iter_mut().find(|m| m.id == id && m.deleted_at.is_none())?;
+        let now = chrono::Utc::now().to_rfc2822();
+        let old = std::mem::replace(&mut msg.message, text.to_string());
+        let old_date = msg.edited_at.replace(now).unwrap_or_else(|| msg.date.clone());
+        msg.edits.push(MessageEdit { message: old, date: old_date });
+        Some(msg.clone())
+    }
+
+    /// Turns the message into a tombstone. Returns the tombstone.
+    pub fn delete_message(&self, server: &str, channel: &str, id: i64) -> Option<IrcMessage> {
+        self.dirty.store(true, Ordering::SeqCst);
+        let key = Self::channel_uid(server, channel);
+        let mut guard = self.messages.lock();
+        let msg = guard.get_mut(&key)?.iter_mut().find(|m| m.id == id && m.deleted_at.is_none())?;
+        msg.message.clear();
+        msg.edits.clear();
+        msg.deleted_at = Some(chrono::Utc::now().to_rfc2822());
+        Some(msg.clone())
+    }
+
+    /// Returns (unread, mentions) for messages newer than `after_id` not written by `username`.
+    /// Only the in memory backlog is counted so the numbers are capped at the backlog size.
+    pub fn count_unread(&self, server: &str, channel: &str, after_id: Option<i64>, username: &str) -> (i64, i64) {
+        let key = Self::channel_uid(server, channel);
+        let guard = self.messages.lock();
+        let Some(msgs) = guard.get(&key) else { return (0, 0) };
+        let after_id = after_id.unwrap_or(0);
+        let mut unread = 0;
+        let mut mentions = 0;
+        for m in msgs.iter().filter(|m| m.id > after_id && m.from != username && m.deleted_at.is_none()) {
+            unread += 1;
+            if is_mention(&m.message, username) { mentions += 1; }
+        }
+        (unread, mentions)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn load_error(&self) -> Option<&str> {
+        self.load_error.as_deref()
+    }
+
+    pub fn last_snapshot_at(&self) -> Option<String> {
+        self.last_snapshot_at.lock().clone()
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.dirty.load(Ordering::SeqCst)
+    }
+
+    /// Writes a temp file next to the history and renames it over the old one
+    /// so a crash mid write never leaves a truncated file behind.
+    pub fn save_to_disk(&self) -> anyhow::Result<()> {
+        let _saving = self.save_lock.lock();
+        self.dirty.store(false, Ordering::SeqCst);
+        let snapshot = DiskFormat(self.messages.lock().clone());
+        let res = Self::write_atomic(&self.path, &snapshot);
+        match res {
+            Ok(()) => *self.last_snapshot_at.lock() = Some(chrono::Utc::now().to_rfc3339()),
+            Err(_) => self.dirty.store(true, Ordering::SeqCst),
+        }
+        res
+    }
+
+    fn write_atomic(path: &Path, snapshot: &DiskFormat) -> anyhow::Result<()> {
+        use std::io::Write;
+        let tmp = PathBuf::from(format!("{}.tmp", path.display()));
+        let mut file = fs::File::create(&tmp)?;
+        serde_json::to_writer(&mut file, snapshot)?;
+        file.flush()?;
+        file.sync_all()?;
+        fs::rename(&tmp, path)?;
         Ok(())
     }
 
+    /// Fails if `opts.search_pattern` is not a valid or too expensive regex.
     pub fn get_messages(
         &self,
         server: &str,
         channel: &str,
         opts: MessageLogOptions,
-    ) -> Vec<IrcMessage> {
+    ) -> Result<Vec<IrcMessage>, String> {
+        let pattern = match opts.search_pattern.as_deref() {
+            Some(p) if !p.is_empty() => Some(compile_search_pattern(p)?),
+            _ => None,
+        };
         let key = Self::channel_uid(server, channel);
-        let guard = self.messages.lock();
-        let msgs = match guard.get(&key) {
+        // copy and release the lock so slow filters do not block message logging
+        let msgs = match self.messages.lock().get(&key) {
             Some(v) => v.clone(),
-            None => return vec![],
+            None => return Ok(vec![]),
         };
         let filter = |m: &IrcMessage| -> bool {
             if let Some(ref s) = opts.search_str {
-                if !s.is_empty() && !(m.message.contains(s) || m.from.contains(s)) {
+                if !(s.is_empty() || m.message.contains(s) || m.from.contains(s)) {
                     return false;
                 }
             }
-            if let Some(ref p) = opts.search_pattern {
-                if !p.is_empty() {
-                    if let Ok(re) = Regex::new(p) {
-                        if !(re.is_match(&m.message) || re.is_match(&m.from)) {
-                            return false;
-                        }
-                    }
+            if let Some(ref re) = pattern {
+                if !(re.is_match(&m.message) || re.is_match(&m.from)) {
+                    return false;
                 }
             }
             true
@@ -109,10 +250,10 @@ impl HistoryStore {
 
         if opts.from_id == 0 {
             let filtered: Vec<IrcMessage> = msgs.into_iter().filter(filter).collect();
-            if opts.count == 0 { return filtered; }
+            if opts.count == 0 { return Ok(filtered); }
             let count = opts.count as usize;
             let len = filtered.len();
-            return filtered.into_iter().skip(len.saturating_sub(count)).collect();
+            return Ok(filtered.into_iter().skip(len.saturating_sub(count)).collect());
         }
 
         let mut out = Vec::new();
@@ -121,7 +262,7 @@ impl HistoryStore {
             if opts.count != 0 && out.len() >= opts.count as usize { break; }
             out.push(m);
         }
-        out
+        Ok(out)
     }
 }
 
@@ -132,3 +273,86 @@ pub struct MessageLogOptions {
     pub search_str: Option<String>,
     pub search_pattern: Option<String>,
 }
+
+const MAX_PATTERN_LEN: usize = 256;
+// limits for the compiled program and the lazy dfa so huge repetitions are rejected
+const MAX_PATTERN_SIZE: usize = 256 * 1024;
+const MAX_PATTERN_DFA_SIZE: usize = 1024 * 1024;
+const PATTERN_CACHE_SIZE: usize = 64;
+
+// clients tend to send the same pattern for every page they request
+// invalid patterns are cached too so they are not compiled over and over
+static PATTERN_CACHE: Lazy<Mutex<PatternCache>> = Lazy::new(|| Mutex::new(PatternCache::default()));
+
+#[derive(Default)]
+struct PatternCache {
+    patterns: HashMap<String, Result<Arc<Regex>, String>>,
+    insert_order: VecDeque<String>,
+}
+
+pub fn compile_search_pattern(pattern: &str) -> Result<Arc<Regex>, String> {
+    if pattern.chars().count() > MAX_PATTERN_LEN {
+        return Err(format!("pattern is longer than {} characters", MAX_PATTERN_LEN));
+    }
+    if let Some(cached) = PATTERN_CACHE.lock().patterns.get(pattern) {
+        return cached.clone();
+    }
+    let compiled = RegexBuilder::new(pattern)
+        .size_limit(MAX_PATTERN_SIZE)
+        .dfa_size_limit(MAX_PATTERN_DFA_SIZE)
+        .build()
+        .map(Arc::new)
+        .map_err(|e| match e {
+            regex::Error::CompiledTooBig(_) => "pattern is too complex".to_string(),
+            e => format!("invalid pattern: {e}"),
+        });
+    let mut cache = PATTERN_CACHE.lock();
+    if !cache.patterns.contains_key(pattern) {
+        if cache.insert_order.len() >= PATTERN_CACHE_SIZE {
+            if let Some(oldest) = cache.insert_order.pop_front() { cache.patterns.remove(&oldest); }
+        }
+        cache.insert_order.push_back(pattern.to_string());
+        cache.patterns.insert(pattern.to_string(), compiled.clone());
+    }
+    compiled
+}
+
+/// case insensitive match of `name` as a whole word in `text`
+pub fn is_mention(text: &str, name: &str) -> bool {
+    if name.is_empty() { return false; }
+    let text = text.to_lowercase();
+    let name = name.to_lowercase();
+    let is_word = |c: char| c.is_alphanumeric() || c == '_';
+    text.match_indices(&name).any(|(i, _)| {
+        let before = text[..i].chars().next_back();
+        let after = text[i + name.len()..].chars().next();
+        !before.is_some_and(is_word) && !after.is_some_and(is_word)
+    })
+}
+
+/// `nick: "first words…" > ` prefix so irc users can follow replies
+pub fn irc_reply_prefix(parent: &IrcMessage) -> String {
+    format!("{}: \"{}\" > ", parent.from, irc_quote(&parent.message))
+}
+
+/// The first few words of a message for quoting it on irc
+pub fn irc_quote(message: &str) -> String {
+    const MAX_WORDS: usize = 6;
+    const MAX_CHARS: usize = 40;
+    let mut quote = String::new();
+    let mut truncated = false;
+    for (i, word) in message.split_whitespace().enumerate() {
+        if i >= MAX_WORDS || quote.chars().count() + word.chars().count() > MAX_CHARS {
+            truncated = true;
+            break;
+        }
+        if i > 0 { quote.push(' '); }
+        quote.push_str(word);
+    }
+    if quote.is_empty() && !message.is_empty() {
+        quote = message.chars().take(MAX_CHARS).collect();
+        truncated = message.chars().count() > MAX_CHARS;
+    }
+    if truncated { quote.push('…'); }
+    quote
+}
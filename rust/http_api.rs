@@ -1,43 +1,121 @@
-use axum::{routing::{get, post}, Router, extract::{Path, Query, State}, Json};
-use serde::Deserialize;
+use axum::{routing::{delete, get, post, put}, Router, extract::{Path, Query, State}, response::{IntoResponse, Response}, Json};
+use serde::{Deserialize, Serialize};
 use tower_http::trace::TraceLayer;
 use serde_json::json;
 use tracing::info;
+use utoipa::{openapi::security::{Http, HttpAuthScheme, SecurityScheme}, IntoParams, Modify, OpenApi, ToSchema};
 
-use crate::{history::{MessageLogOptions}, models::{channel as channel_model, channel_member as cm_model}, state::AppState, types::{IrcMessage, ChannelInfo}};
+use crate::{api::{self, ApiError, ErrorBody, MessageResponse}, api_tokens::{self, ApiTokenInfo, Scope, TOKEN_PREFIX}, health, history::{MessageLogOptions}, messages, models::{api_token as api_token_model, channel as channel_model, channel_member as cm_model, message_search, reaction as reaction_model, subscription as subscription_model, subscription_delivery as delivery_model, user::UserRow}, metrics, plain_ws, reactions, state::{AppState, SessionUser}, subscriptions::{self, DeliveryInfo, EventType, ModerationAction, SubscriptionInfo}, types::{DeliveryStatus, IrcMessage, ChannelInfo, SearchResponse, SearchResult, UnreadInfo, WebhookObject}, unread};
 
 pub fn router(state: AppState) -> Router {
     Router::new()
-        .route("/:server/:channel/messages", get(get_messages))
+        .nest("/api/v1", api_routes(false))
+        // unversioned aliases for old clients. errors keep the old 200 {"error": ...} shape
+        .merge(api_routes(true))
+        .route("/metrics", get(get_metrics))
+        .route("/ws", get(plain_ws::upgrade))
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+        .with_state(state)
+        .layer(axum::middleware::from_fn(metrics::track_http))
+        .layer(TraceLayer::new_for_http())
+}
+
+fn api_routes(legacy: bool) -> Router<AppState> {
+    let router = Router::new()
         .route("/:server/:channel/typers", get(get_typers))
+        .route("/:server/:channel/messages/:message_id/reactions/:emoji", put(add_reaction).delete(remove_reaction))
+        .route("/:server/:channel/read", post(mark_read))
+        .route("/:server/:channel/unread", post(mark_unread))
         .route("/:server/channels", get(get_discord_channels))
         .route("/users", get(get_users))
+        .route("/search", get(search_messages))
+        .route("/tokens", get(list_api_tokens).post(create_api_token))
+        .route("/tokens/:token_id", delete(revoke_api_token))
         .route("/admin/logout_all", post(admin_logout_all))
         .route("/admin/password", post(admin_password))
+        .route("/admin/snapshot", post(admin_snapshot))
+        .route("/admin/subscriptions", get(list_subscriptions).post(create_subscription))
+        .route("/admin/subscriptions/:subscription_id", delete(delete_subscription))
+        .route("/admin/subscriptions/:subscription_id/deliveries", get(get_subscription_deliveries))
         .route("/webhooks/:webhook_id/:webhook_token", post(webhook_execute))
-        .route("/channels/:channel_id/webhooks", get(channel_webhooks))
-        .with_state(state)
-        .layer(TraceLayer::new_for_http())
+        .route("/status", get(get_status));
+    if legacy {
+        router
+            .route("/:server/:channel/messages", get(legacy_get_messages).post(post_message))
+            .route("/channels/:channel_id/webhooks", get(legacy_channel_webhooks))
+            .layer(axum::middleware::map_response(api::legacy_errors))
+    } else {
+        router
+            .route("/:server/:channel/messages", get(get_messages).post(post_message))
+            .route("/channels/:channel_id/webhooks", get(channel_webhooks))
+            .route("/openapi.json", get(openapi_json))
+    }
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "irc-websockets", description = "REST API of the irc-websockets bridge. Errors are returned as ErrorBody with a matching status code."),
+    servers((url = "/api/v1")),
+    paths(
+        get_messages, post_message, get_typers, add_reaction, remove_reaction, mark_read, mark_unread, get_discord_channels,
+        get_users, search_messages, list_api_tokens, create_api_token, revoke_api_token, admin_logout_all, admin_password, admin_snapshot,
+        list_subscriptions, create_subscription, delete_subscription, get_subscription_deliveries, webhook_execute,
+        channel_webhooks, get_status,
+    ),
+    modifiers(&BearerAuth),
+)]
+pub struct ApiDoc;
+
+struct BearerAuth;
+
+impl Modify for BearerAuth {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme("admin_token", SecurityScheme::Http(Http::new(HttpAuthScheme::Bearer)));
+        // personal access tokens. the scopes are only documented, the bearer scheme can not list them
+        components.add_security_scheme("api_token", SecurityScheme::Http(Http::new(HttpAuthScheme::Bearer)));
+    }
+}
+
+async fn openapi_json() -> Json<utoipa::openapi::OpenApi> {
+    Json(ApiDoc::openapi())
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 #[allow(non_snake_case, dead_code)]
 struct MessageQuery {
+    /// only messages with a higher id
     from: Option<i64>,
+    /// defaults to 10
     count: Option<i64>,
     search: Option<String>,
     pattern: Option<String>,
     userId: Option<i64>,
+    /// marks the returned messages as requested for the logged in account
+    /// or the account of an api token
     sessionToken: Option<String>,
 }
 
-async fn get_messages(Path((server, channel)): Path<(String, String)>, State(state): State<AppState>, Query(q): Query<MessageQuery>) -> Json<Vec<IrcMessage>> {
-    // auth via session token
-    if let Some(token) = q.sessionToken.clone() {
-        let session_user = state.sessions.iter().find(|u| u.value().logged_in && u.value().session_token == token);
-        if session_user.is_none() {
-            return Json(vec![]);
-        }
+#[utoipa::path(
+    get, path = "/{server}/{channel}/messages", tag = "messages",
+    security((), ("api_token" = ["read_history"])),
+    params(("server" = String, Path), ("channel" = String, Path), MessageQuery),
+    responses(
+        (status = 200, body = Vec<IrcMessage>),
+        (status = 400, description = "invalid search", body = ErrorBody),
+        (status = 401, description = "invalid session or api token", body = ErrorBody),
+        (status = 403, description = "the api token is missing the scope or the channel is private and the caller not a member", body = ErrorBody),
+    ),
+)]
+async fn get_messages(Path((server, channel)): Path<(String, String)>, State(state): State<AppState>, headers: axum::http::HeaderMap, Query(q): Query<MessageQuery>) -> Result<Json<Vec<IrcMessage>>, ApiError> {
+    let db_user = find_caller(&state, &headers, q.sessionToken.as_deref(), Scope::ReadHistory)?;
+    let ch = channel_model::find_by_discord(&state.db.lock(), &server, &channel);
+    // only members can read private channels, also through search and pattern
+    if let Some(ch) = ch.as_ref().filter(|c| c.is_private != 0) {
+        let is_member = db_user.as_ref().is_some_and(|u| cm_model::find_by_user_and_channel(&state.db.lock(), u.id, ch.id).is_some());
+        if !is_member { return Err(ApiError::Forbidden("this is a private channel and you are not a member".into())); }
     }
     let opts = MessageLogOptions {
         from_id: q.from.unwrap_or(0),
@@ -45,26 +123,40 @@ async fn get_messages(Path((server, channel)): Path<(String, String)>, State(sta
         search_str: q.search,
         search_pattern: q.pattern,
     };
-    let messages = state.history.get_messages(&server, &channel, opts.clone());
+    let mut messages = state.history.get_messages(&server, &channel, opts.clone()).map_err(ApiError::BadRequest)?;
+    if let Some(ch) = &ch {
+        let ids: Vec<i64> = messages.iter().map(|m| m.id).collect();
+        let mut counts = reaction_model::counts_for_messages(&state.db.lock(), ch.id, &ids, db_user.as_ref().map(|u| u.id));
+        for m in messages.iter_mut() {
+            m.reactions = counts.remove(&m.id).unwrap_or_default();
+        }
+    }
 
     // update requested ids if logged in
-    if let Some(token) = q.sessionToken.clone() {
-        if let Some(session) = state.sessions.iter().find(|u| u.value().logged_in && u.value().session_token == token) {
-            if let Some(dbuser) = &session.db_user {
-                let conn = state.db.lock();
-                if let Some(ch) = channel_model::find_by_discord(&conn, &server, &channel) {
-                    if let Some(mut member) = cm_model::find_by_user_and_channel(&conn, dbuser.id, ch.id) {
-                        if let Some(first) = messages.first() { member.lowest_requested_msg_id = Some(first.id); }
-                        if let Some(last) = messages.last() { member.highest_requested_msg_id = Some(last.id); }
-                        let _ = cm_model::update(&conn, &member);
-                    }
-                }
-            }
+    if let (Some(dbuser), Some(ch)) = (&db_user, &ch) {
+        let conn = state.db.lock();
+        if let Some(mut member) = cm_model::find_by_user_and_channel(&conn, dbuser.id, ch.id) {
+            if let (Some(first), Some(last)) = (messages.first(), messages.last()) { member.saw_messages(first.id, last.id); }
+            let _ = cm_model::update(&conn, &member);
+            unread::notify(&state, &conn, ch, Some(dbuser.id));
         }
     }
-    Json(messages)
+    Ok(Json(messages))
 }
 
+/// Old clients expect an empty list for an invalid session token
+async fn legacy_get_messages(path: Path<(String, String)>, state: State<AppState>, headers: axum::http::HeaderMap, query: Query<MessageQuery>) -> Response {
+    match get_messages(path, state, headers, query).await {
+        Err(ApiError::Unauthorized(_)) => Json(Vec::<IrcMessage>::new()).into_response(),
+        res => res.into_response(),
+    }
+}
+
+#[utoipa::path(
+    get, path = "/{server}/{channel}/typers", tag = "messages",
+    params(("server" = String, Path), ("channel" = String, Path)),
+    responses((status = 200, description = "names of the users typing in the channel", body = Vec<String>)),
+)]
 async fn get_typers(Path((server, channel)): Path<(String, String)>, State(state): State<AppState>) -> Json<Vec<String>> {
     let names = state.sessions.iter()
         .filter(|u| u.value().is_typing && u.value().active_channel == channel && u.value().active_server == server)
@@ -73,15 +165,357 @@ async fn get_typers(Path((server, channel)): Path<(String, String)>, State(state
     Json(names)
 }
 
+#[utoipa::path(
+    get, path = "/{server}/channels", tag = "channels",
+    params(("server" = String, Path)),
+    responses((status = 200, body = Vec<ChannelInfo>)),
+)]
 async fn get_discord_channels(Path(server): Path<String>, State(state): State<AppState>) -> Json<Vec<ChannelInfo>> {
     let conn = state.db.lock();
     let channels = channel_model::where_eq(&conn, "discord_server", &server)
         .into_iter()
-        .map(|c| ChannelInfo{ id: c.id, server_id: c.server_id, name: c.discord_channel, description: c.description })
+        .map(|c| ChannelInfo{ id: c.id, server_id: c.server_id, name: c.discord_channel, description: c.description, unread_count: None, mention_count: None })
         .collect();
     Json(channels)
 }
 
+fn find_session_by_token(state: &AppState, token: &str) -> Option<SessionUser> {
+    state.sessions.iter()
+        .find(|u| u.value().logged_in && u.value().session_token == token)
+        .map(|u| u.value().clone())
+}
+
+fn find_account_by_token(state: &AppState, token: &str) -> Result<UserRow, ApiError> {
+    find_session_by_token(state, token).and_then(|u| u.db_user).ok_or_else(ApiError::invalid_session)
+}
+
+fn bearer_token(headers: &axum::http::HeaderMap) -> Option<&str> {
+    headers.get(axum::http::header::AUTHORIZATION).and_then(|h| h.to_str().ok()).and_then(|v| v.strip_prefix("Bearer "))
+}
+
+/// The account of an api token with `scope` in the authorization header or
+/// of the socket session `session_token`. None for anonymous requests.
+fn find_caller(state: &AppState, headers: &axum::http::HeaderMap, session_token: Option<&str>, scope: Scope) -> Result<Option<UserRow>, ApiError> {
+    if let Some(token) = bearer_token(headers).filter(|t| t.starts_with(TOKEN_PREFIX)) {
+        return Ok(Some(api_tokens::authenticate(&state.db.lock(), token, scope)?));
+    }
+    match session_token {
+        Some(token) => Ok(find_session_by_token(state, token).ok_or_else(ApiError::invalid_session)?.db_user),
+        None => Ok(None),
+    }
+}
+
+/// Like [`find_caller`] but an account is required
+fn require_caller(state: &AppState, headers: &axum::http::HeaderMap, session_token: Option<&str>, scope: Scope) -> Result<UserRow, ApiError> {
+    find_caller(state, headers, session_token, scope)?.ok_or_else(ApiError::invalid_session)
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+#[allow(non_snake_case)]
+struct NewMessage {
+    message: String,
+    /// id of the message this one answers
+    replyTo: Option<i64>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct SentMessage {
+    #[serde(flatten)]
+    message: IrcMessage,
+    /// `queued` until the irc connection sent it
+    status: DeliveryStatus,
+}
+
+#[utoipa::path(
+    post, path = "/{server}/{channel}/messages", tag = "messages",
+    security(("api_token" = ["send_messages"])),
+    params(("server" = String, Path), ("channel" = String, Path)),
+    request_body = NewMessage,
+    responses(
+        (status = 200, description = "the stored message", body = SentMessage),
+        (status = 400, description = "empty message", body = ErrorBody),
+        (status = 401, description = "missing or invalid api token", body = ErrorBody),
+        (status = 403, description = "not a member of the private channel, muted, vetoed by a plugin or the api token is missing the scope", body = ErrorBody),
+        (status = 404, description = "channel or replied message not found", body = ErrorBody),
+        (status = 429, description = "rate limited. Retry-After says when to try again", body = ErrorBody,
+            headers(("Retry-After" = u64, description = "seconds until the next message is accepted"))),
+        (status = 502, description = "the message could not be sent to irc", body = ErrorBody),
+    ),
+)]
+async fn post_message(Path((server, channel)): Path<(String, String)>, State(state): State<AppState>, headers: axum::http::HeaderMap, Json(body): Json<NewMessage>) -> Result<Json<SentMessage>, ApiError> {
+    let _busy = state.inflight.start();
+    let Some(token) = bearer_token(&headers).filter(|t| t.starts_with(TOKEN_PREFIX)) else {
+        return Err(ApiError::Unauthorized("an api token is required".into()));
+    };
+    let author = api_tokens::authenticate(&state.db.lock(), token, Scope::SendMessages)?;
+    let msg = IrcMessage {
+        from: author.username.clone(),
+        message: body.message,
+        channel,
+        server,
+        reply_to: body.replyTo,
+        ..Default::default()
+    };
+    let (message, status) = messages::send(&state, None, Some(&author), msg).await?;
+    info!("[*][api] '{}' sent msgid={}", author.username, message.id);
+    Ok(Json(SentMessage { message, status: status.status }))
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+#[allow(non_snake_case)]
+struct MarkReadBody {
+    messageId: i64,
+    /// not needed with an api token
+    sessionToken: Option<String>,
+}
+
+#[utoipa::path(
+    post, path = "/{server}/{channel}/read", tag = "channels",
+    security((), ("api_token" = ["read_history"])),
+    params(("server" = String, Path), ("channel" = String, Path)),
+    request_body = MarkReadBody,
+    responses(
+        (status = 200, body = UnreadInfo),
+        (status = 400, description = "invalid message id", body = ErrorBody),
+        (status = 401, description = "invalid session or api token", body = ErrorBody),
+        (status = 403, description = "not a member of the channel or the api token is missing the scope", body = ErrorBody),
+        (status = 404, description = "channel not found", body = ErrorBody),
+    ),
+)]
+async fn mark_read(Path((server, channel)): Path<(String, String)>, State(state): State<AppState>, headers: axum::http::HeaderMap, Json(body): Json<MarkReadBody>) -> Result<Json<UnreadInfo>, ApiError> {
+    set_read_marker(&state, &headers, &server, &channel, body, false)
+}
+
+#[utoipa::path(
+    post, path = "/{server}/{channel}/unread", tag = "channels",
+    security((), ("api_token" = ["read_history"])),
+    params(("server" = String, Path), ("channel" = String, Path)),
+    request_body = MarkReadBody,
+    responses(
+        (status = 200, body = UnreadInfo),
+        (status = 400, description = "invalid message id", body = ErrorBody),
+        (status = 401, description = "invalid session or api token", body = ErrorBody),
+        (status = 403, description = "not a member of the channel or the api token is missing the scope", body = ErrorBody),
+        (status = 404, description = "channel not found", body = ErrorBody),
+    ),
+)]
+async fn mark_unread(Path((server, channel)): Path<(String, String)>, State(state): State<AppState>, headers: axum::http::HeaderMap, Json(body): Json<MarkReadBody>) -> Result<Json<UnreadInfo>, ApiError> {
+    set_read_marker(&state, &headers, &server, &channel, body, true)
+}
+
+fn set_read_marker(state: &AppState, headers: &axum::http::HeaderMap, server: &str, channel: &str, body: MarkReadBody, unread: bool) -> Result<Json<UnreadInfo>, ApiError> {
+    let db_user = require_caller(state, headers, body.sessionToken.as_deref(), Scope::ReadHistory)?;
+    let conn = state.db.lock();
+    let ch = channel_model::find_by_discord(&conn, server, channel).ok_or_else(|| ApiError::NotFound("channel not found".into()))?;
+    let res = if unread {
+        unread::mark_unread(state, &conn, &db_user, &ch, body.messageId)
+    } else {
+        unread::mark_read(state, &conn, &db_user, &ch, body.messageId)
+    };
+    res?;
+    unread::notify(state, &conn, &ch, Some(db_user.id));
+    Ok(Json(unread::unread_info(state, &conn, &db_user, &ch)))
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+#[allow(non_snake_case)]
+struct SessionQuery {
+    /// not needed with an api token
+    sessionToken: Option<String>,
+}
+
+#[utoipa::path(
+    put, path = "/{server}/{channel}/messages/{message_id}/reactions/{emoji}", tag = "messages",
+    security((), ("api_token" = ["send_messages"])),
+    params(("server" = String, Path), ("channel" = String, Path), ("message_id" = i64, Path), ("emoji" = String, Path), SessionQuery),
+    responses(
+        (status = 200, body = MessageResponse),
+        (status = 400, description = "invalid emoji", body = ErrorBody),
+        (status = 401, description = "invalid session or api token", body = ErrorBody),
+        (status = 403, description = "not a member of the private channel or the api token is missing the scope", body = ErrorBody),
+        (status = 404, description = "channel or message not found", body = ErrorBody),
+    ),
+)]
+async fn add_reaction(Path((server, channel, message_id, emoji)): Path<(String, String, i64, String)>, State(state): State<AppState>, headers: axum::http::HeaderMap, Query(q): Query<SessionQuery>) -> Result<Json<MessageResponse>, ApiError> {
+    let db_user = require_caller(&state, &headers, q.sessionToken.as_deref(), Scope::SendMessages)?;
+    set_reaction(&state, &db_user, &server, &channel, message_id, &emoji, true).await
+}
+
+#[utoipa::path(
+    delete, path = "/{server}/{channel}/messages/{message_id}/reactions/{emoji}", tag = "messages",
+    security((), ("api_token" = ["send_messages"])),
+    params(("server" = String, Path), ("channel" = String, Path), ("message_id" = i64, Path), ("emoji" = String, Path), SessionQuery),
+    responses(
+        (status = 200, body = MessageResponse),
+        (status = 400, description = "invalid emoji", body = ErrorBody),
+        (status = 401, description = "invalid session or api token", body = ErrorBody),
+        (status = 403, description = "not a member of the private channel or the api token is missing the scope", body = ErrorBody),
+        (status = 404, description = "channel or message not found", body = ErrorBody),
+    ),
+)]
+async fn remove_reaction(Path((server, channel, message_id, emoji)): Path<(String, String, i64, String)>, State(state): State<AppState>, headers: axum::http::HeaderMap, Query(q): Query<SessionQuery>) -> Result<Json<MessageResponse>, ApiError> {
+    let db_user = require_caller(&state, &headers, q.sessionToken.as_deref(), Scope::SendMessages)?;
+    set_reaction(&state, &db_user, &server, &channel, message_id, &emoji, false).await
+}
+
+async fn set_reaction(state: &AppState, db_user: &UserRow, server: &str, channel: &str, message_id: i64, emoji: &str, add: bool) -> Result<Json<MessageResponse>, ApiError> {
+    reactions::set_reaction(state, db_user, server, channel, message_id, emoji, add).await?;
+    Ok(MessageResponse::ok())
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+#[allow(non_snake_case)]
+struct SearchQuery {
+    /// full text query
+    q: Option<String>,
+    server: Option<String>,
+    channel: Option<String>,
+    author: Option<String>,
+    /// rfc3339 or YYYY-MM-DD
+    after: Option<String>,
+    /// rfc3339 or YYYY-MM-DD
+    before: Option<String>,
+    hasLink: Option<bool>,
+    /// needs sessionToken
+    mentionsMe: Option<bool>,
+    offset: Option<i64>,
+    /// 1 to 100, defaults to 25
+    limit: Option<i64>,
+    /// also searches the private channels of the logged in account
+    /// or the account of an api token
+    sessionToken: Option<String>,
+}
+
+/// accepts rfc3339 or a plain YYYY-MM-DD date
+fn parse_date_filter(date: &str) -> Option<String> {
+    if let Ok(d) = chrono::DateTime::parse_from_rfc3339(date) {
+        return Some(d.with_timezone(&chrono::Utc).to_rfc3339());
+    }
+    let day = chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d").ok()?;
+    Some(day.and_hms_opt(0, 0, 0)?.and_utc().to_rfc3339())
+}
+
+#[utoipa::path(
+    get, path = "/search", tag = "messages",
+    security((), ("api_token" = ["read_history"])),
+    params(SearchQuery),
+    responses(
+        (status = 200, body = SearchResponse),
+        (status = 400, description = "invalid date or query", body = ErrorBody),
+        (status = 401, description = "invalid session or api token or mentionsMe without account", body = ErrorBody),
+        (status = 403, description = "the api token is missing the scope", body = ErrorBody),
+    ),
+)]
+async fn search_messages(State(state): State<AppState>, headers: axum::http::HeaderMap, Query(q): Query<SearchQuery>) -> Result<Json<SearchResponse>, ApiError> {
+    let db_user = find_caller(&state, &headers, q.sessionToken.as_deref(), Scope::ReadHistory)?;
+    if q.mentionsMe.unwrap_or(false) && db_user.is_none() {
+        return Err(ApiError::Unauthorized("mentionsMe requires being logged in to an account".into()));
+    }
+    let mut filter = message_search::SearchFilter {
+        query: q.q.unwrap_or_default(),
+        author: q.author.filter(|a| !a.is_empty()),
+        has_link: q.hasLink.unwrap_or(false),
+        mentions: if q.mentionsMe.unwrap_or(false) { db_user.as_ref().map(|u| u.username.clone()) } else { None },
+        offset: q.offset.unwrap_or(0).max(0),
+        limit: q.limit.unwrap_or(25).clamp(1, 100),
+        ..Default::default()
+    };
+    for (raw, out) in [(q.after, &mut filter.after), (q.before, &mut filter.before)] {
+        if let Some(raw) = raw.filter(|d| !d.is_empty()) {
+            let Some(date) = parse_date_filter(&raw) else { return Err(ApiError::BadRequest(format!("invalid date '{}'", raw))) };
+            *out = Some(date);
+        }
+    }
+
+    let conn = state.db.lock();
+    // only search channels the caller can read
+    let channels: Vec<_> = channel_model::all(&conn).into_iter()
+        .filter(|c| q.server.as_ref().is_none_or(|s| *s == c.discord_server))
+        .filter(|c| q.channel.as_ref().is_none_or(|name| *name == c.discord_channel))
+        .filter(|c| c.is_private == 0 || db_user.as_ref().is_some_and(|u| cm_model::find_by_user_and_channel(&conn, u.id, c.id).is_some()))
+        .collect();
+    filter.channel_ids = channels.iter().map(|c| c.id).collect();
+    // fts syntax errors end up here so this is the callers fault
+    let (rows, total) = message_search::search(&conn, &filter).map_err(|e| ApiError::BadRequest(format!("search failed: {e}")))?;
+    let results = rows.into_iter()
+        .filter_map(|row| {
+            let ch = channels.iter().find(|c| c.id == row.channel_id)?;
+            let date = chrono::DateTime::parse_from_rfc3339(&row.created_at).map(|d| d.to_rfc2822()).unwrap_or(row.created_at);
+            Some(SearchResult{ id: row.id, from: row.author, channel: ch.discord_channel.clone(), server: ch.discord_server.clone(), date, snippet: row.snippet, rank: row.rank })
+        })
+        .collect();
+    Ok(Json(SearchResponse{ results, total, offset: filter.offset, limit: filter.limit }))
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+struct NewApiToken {
+    name: String,
+    scopes: Vec<Scope>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenPath { token_id: i64 }
+
+/// Tokens are managed with the session of a login, not with other tokens
+fn require_session_account(state: &AppState, q: &SessionQuery) -> Result<UserRow, ApiError> {
+    find_account_by_token(state, q.sessionToken.as_deref().ok_or_else(ApiError::invalid_session)?)
+}
+
+#[utoipa::path(
+    get, path = "/tokens", tag = "tokens",
+    params(SessionQuery),
+    responses(
+        (status = 200, description = "api tokens of the logged in account without the tokens themselves", body = Vec<ApiTokenInfo>),
+        (status = 401, description = "invalid session token", body = ErrorBody),
+    ),
+)]
+async fn list_api_tokens(State(state): State<AppState>, Query(q): Query<SessionQuery>) -> Result<Json<Vec<ApiTokenInfo>>, ApiError> {
+    let db_user = require_session_account(&state, &q)?;
+    let tokens = api_token_model::for_user(&state.db.lock(), db_user.id).into_iter().map(ApiTokenInfo::from).collect();
+    Ok(Json(tokens))
+}
+
+#[utoipa::path(
+    post, path = "/tokens", tag = "tokens",
+    params(SessionQuery),
+    request_body = NewApiToken,
+    responses(
+        (status = 200, description = "the new token. `token` is only returned this once", body = ApiTokenInfo),
+        (status = 400, description = "invalid name, no scopes or too many tokens", body = ErrorBody),
+        (status = 401, description = "invalid session token", body = ErrorBody),
+    ),
+)]
+async fn create_api_token(State(state): State<AppState>, Query(q): Query<SessionQuery>, Json(body): Json<NewApiToken>) -> Result<Json<ApiTokenInfo>, ApiError> {
+    let db_user = require_session_account(&state, &q)?;
+    let token = api_tokens::create(&state.db.lock(), db_user.id, &body.name, &body.scopes)?;
+    info!("[*] user='{}' created api token '{}'", db_user.username, token.name);
+    Ok(Json(token))
+}
+
+#[utoipa::path(
+    delete, path = "/tokens/{token_id}", tag = "tokens",
+    params(("token_id" = i64, Path), SessionQuery),
+    responses(
+        (status = 200, body = MessageResponse),
+        (status = 401, description = "invalid session token", body = ErrorBody),
+        (status = 404, description = "the account has no token with that id", body = ErrorBody),
+    ),
+)]
+async fn revoke_api_token(Path(TokenPath{ token_id }): Path<TokenPath>, State(state): State<AppState>, Query(q): Query<SessionQuery>) -> Result<Json<MessageResponse>, ApiError> {
+    let db_user = require_session_account(&state, &q)?;
+    let conn = state.db.lock();
+    let token = api_token_model::find(&conn, token_id).filter(|t| t.user_id == db_user.id).ok_or_else(|| ApiError::NotFound("token not found".into()))?;
+    api_token_model::delete(&conn, token.id).map_err(|e| ApiError::Internal(format!("failed to revoke token: {e}")))?;
+    info!("[*] user='{}' revoked api token '{}'", db_user.username, token.name);
+    Ok(MessageResponse::ok())
+}
+
+#[utoipa::path(
+    get, path = "/users", tag = "users",
+    responses((status = 200, description = "names of all connected users", body = Vec<String>)),
+)]
 async fn get_users(State(state): State<AppState>) -> Json<Vec<String>> {
     let users = state.sessions.iter().map(|u| u.value().username.clone()).collect();
     Json(users)
@@ -98,53 +532,255 @@ fn check_admin_auth(headers: &axum::http::HeaderMap, state: &AppState) -> bool {
     false
 }
 
-#[derive(Debug, Deserialize)]
+fn require_admin(headers: &axum::http::HeaderMap, state: &AppState) -> Result<(), ApiError> {
+    if check_admin_auth(headers, state) { Ok(()) } else { Err(ApiError::admin_auth_required()) }
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
 struct RequiredFlag { required: bool }
 
-async fn admin_logout_all(State(state): State<AppState>, headers: axum::http::HeaderMap) -> Json<serde_json::Value> {
-    if !check_admin_auth(&headers, &state) {
-        return Json(json!({"error":"Authentication is required please set the bearer authorization header."}));
-    }
+#[derive(Debug, Serialize, ToSchema)]
+struct SnapshotResponse {
+    message: String,
+    // messages in the saved history
+    messages: usize,
+    path: String,
+}
+
+#[utoipa::path(
+    post, path = "/admin/logout_all", tag = "admin",
+    security(("admin_token" = [])),
+    responses((status = 200, body = MessageResponse), (status = 401, body = ErrorBody)),
+)]
+async fn admin_logout_all(State(state): State<AppState>, headers: axum::http::HeaderMap) -> Result<Json<MessageResponse>, ApiError> {
+    require_admin(&headers, &state)?;
     info!("[*] admin logged out all users");
+    let action = ModerationAction { action: "logout_all", moderator: "admin_token", ..Default::default() };
+    subscriptions::emit(&state, &state.db.lock(), EventType::Moderation, None, &action);
     // mark as logged out and notify
+    *state.logged_out_all_at.lock() = Some(std::time::Instant::now());
     for mut entry in state.sessions.iter_mut() {
         entry.logged_in = false;
     }
-    Json(json!({"message":"OK"}))
+    state.resumable.clear();
+    Ok(MessageResponse::ok())
 }
 
-async fn admin_password(State(state): State<AppState>, headers: axum::http::HeaderMap, Json(flag): Json<RequiredFlag>) -> Json<serde_json::Value> {
-    if !check_admin_auth(&headers, &state) {
-        return Json(json!({"error":"Authentication is required please set the bearer authorization header."}));
+#[utoipa::path(
+    post, path = "/admin/snapshot", tag = "admin",
+    security(("admin_token" = [])),
+    responses((status = 200, body = SnapshotResponse), (status = 401, body = ErrorBody), (status = 500, body = ErrorBody)),
+)]
+async fn admin_snapshot(State(state): State<AppState>, headers: axum::http::HeaderMap) -> Result<Json<SnapshotResponse>, ApiError> {
+    require_admin(&headers, &state)?;
+    let history = state.history.clone();
+    let res = tokio::task::spawn_blocking(move || history.save_to_disk()).await;
+    match res {
+        Ok(Ok(())) => {
+            info!("[*] admin saved a history snapshot");
+            Ok(Json(SnapshotResponse{ message: "OK".into(), messages: state.history.message_count(), path: state.history.path().display().to_string() }))
+        }
+        Ok(Err(e)) => Err(ApiError::Internal(format!("failed to save history: {e}"))),
+        Err(e) => Err(ApiError::Internal(format!("failed to save history: {e}"))),
     }
+}
+
+#[utoipa::path(
+    post, path = "/admin/password", tag = "admin",
+    security(("admin_token" = [])),
+    request_body = RequiredFlag,
+    responses((status = 200, body = MessageResponse), (status = 401, body = ErrorBody)),
+)]
+async fn admin_password(State(state): State<AppState>, headers: axum::http::HeaderMap, Json(flag): Json<RequiredFlag>) -> Result<Json<MessageResponse>, ApiError> {
+    require_admin(&headers, &state)?;
     state.config.lock().require_passwords = flag.required;
     info!("[*] admin set password required to {}", flag.required);
-    Json(json!({"message":"OK"}))
+    Ok(MessageResponse::ok())
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+#[allow(non_snake_case)]
+struct NewSubscription {
+    /// receives the events as POST
+    url: String,
+    /// key of the X-Signature-256 hmac, at least 16 characters
+    secret: String,
+    events: Vec<EventType>,
+    /// only events of this channel
+    channelId: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SubscriptionPath { subscription_id: i64 }
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct DeliveryQuery {
+    /// only attempts with a lower id
+    before: Option<i64>,
+    /// defaults to 50, at most 500
+    count: Option<i64>,
+}
+
+#[utoipa::path(
+    get, path = "/admin/subscriptions", tag = "admin",
+    security(("admin_token" = [])),
+    responses((status = 200, body = Vec<SubscriptionInfo>), (status = 401, body = ErrorBody)),
+)]
+async fn list_subscriptions(State(state): State<AppState>, headers: axum::http::HeaderMap) -> Result<Json<Vec<SubscriptionInfo>>, ApiError> {
+    require_admin(&headers, &state)?;
+    Ok(Json(subscription_model::all(&state.db.lock()).into_iter().map(SubscriptionInfo::from).collect()))
+}
+
+#[utoipa::path(
+    post, path = "/admin/subscriptions", tag = "admin",
+    security(("admin_token" = [])),
+    request_body = NewSubscription,
+    responses(
+        (status = 200, description = "the new subscription without the secret", body = SubscriptionInfo),
+        (status = 400, description = "invalid url, short secret or no events", body = ErrorBody),
+        (status = 401, body = ErrorBody),
+        (status = 404, description = "channel not found", body = ErrorBody),
+    ),
+)]
+async fn create_subscription(State(state): State<AppState>, headers: axum::http::HeaderMap, Json(body): Json<NewSubscription>) -> Result<Json<SubscriptionInfo>, ApiError> {
+    require_admin(&headers, &state)?;
+    Ok(Json(subscriptions::create(&state.db.lock(), &body.url, &body.secret, &body.events, body.channelId)?))
+}
+
+#[utoipa::path(
+    delete, path = "/admin/subscriptions/{subscription_id}", tag = "admin",
+    security(("admin_token" = [])),
+    params(("subscription_id" = i64, Path)),
+    responses((status = 200, body = MessageResponse), (status = 401, body = ErrorBody), (status = 404, body = ErrorBody)),
+)]
+async fn delete_subscription(Path(SubscriptionPath{ subscription_id }): Path<SubscriptionPath>, State(state): State<AppState>, headers: axum::http::HeaderMap) -> Result<Json<MessageResponse>, ApiError> {
+    require_admin(&headers, &state)?;
+    let deleted = subscription_model::delete(&state.db.lock(), subscription_id).map_err(|e| ApiError::Internal(format!("failed to delete subscription: {e}")))?;
+    if !deleted { return Err(ApiError::NotFound("subscription not found".into())); }
+    info!("[*] admin deleted subscription id={}", subscription_id);
+    Ok(MessageResponse::ok())
+}
+
+#[utoipa::path(
+    get, path = "/admin/subscriptions/{subscription_id}/deliveries", tag = "admin",
+    security(("admin_token" = [])),
+    params(("subscription_id" = i64, Path), DeliveryQuery),
+    responses(
+        (status = 200, description = "delivery attempts, newest first", body = Vec<DeliveryInfo>),
+        (status = 401, body = ErrorBody),
+        (status = 404, body = ErrorBody),
+    ),
+)]
+async fn get_subscription_deliveries(Path(SubscriptionPath{ subscription_id }): Path<SubscriptionPath>, State(state): State<AppState>, headers: axum::http::HeaderMap, Query(q): Query<DeliveryQuery>) -> Result<Json<Vec<DeliveryInfo>>, ApiError> {
+    require_admin(&headers, &state)?;
+    let conn = state.db.lock();
+    if subscription_model::find(&conn, subscription_id).is_none() { return Err(ApiError::NotFound("subscription not found".into())); }
+    let count = q.count.unwrap_or(50).clamp(1, 500);
+    Ok(Json(delivery_model::for_subscription(&conn, subscription_id, q.before, count).into_iter().map(DeliveryInfo::from).collect()))
 }
 
 #[derive(Debug, Deserialize)]
 #[allow(dead_code)]
 struct WebhookPath { webhook_id: i64, webhook_token: String }
 
-async fn webhook_execute(Path(WebhookPath{ webhook_id: _, webhook_token: _ }): Path<WebhookPath>, State(_state): State<AppState>, Json(_body): Json<serde_json::Value>) -> Json<serde_json::Value> {
+#[utoipa::path(
+    post, path = "/webhooks/{webhook_id}/{webhook_token}", tag = "webhooks",
+    params(("webhook_id" = i64, Path), ("webhook_token" = String, Path)),
+    request_body = Object,
+    responses((status = 200, body = MessageResponse)),
+)]
+async fn webhook_execute(Path(WebhookPath{ webhook_id: _, webhook_token: _ }): Path<WebhookPath>, State(_state): State<AppState>, Json(_body): Json<serde_json::Value>) -> Json<MessageResponse> {
     // Minimal implementation; full compatibility not provided
-    Json(json!({"message":"TODO: this is not discord api yet. But OK"}))
+    metrics::METRICS.webhook_executions.with_label_values(&["accepted"]).inc();
+    Json(MessageResponse{ message: "TODO: this is not discord api yet. But OK".into() })
+}
+
+async fn get_metrics(State(state): State<AppState>, headers: axum::http::HeaderMap) -> Response {
+    if !check_admin_auth(&headers, &state) {
+        return (axum::http::StatusCode::UNAUTHORIZED, api::ADMIN_AUTH_REQUIRED).into_response();
+    }
+    metrics_response(&state)
+}
+
+pub fn metrics_response(state: &AppState) -> Response {
+    ([(axum::http::header::CONTENT_TYPE, "text/plain; version=0.0.4")], metrics::render(state)).into_response()
+}
+
+/// Unauthenticated /metrics for the separate metrics address
+pub fn metrics_router(state: AppState) -> Router {
+    Router::new()
+        .route("/metrics", get(|State(state): State<AppState>| async move { metrics_response(&state) }))
+        .with_state(state)
+}
+
+async fn healthz() -> Json<serde_json::Value> {
+    Json(json!({"status":"ok"}))
+}
+
+async fn readyz(State(state): State<AppState>) -> (axum::http::StatusCode, Json<health::Readiness>) {
+    let readiness = health::readiness(&state);
+    let code = if readiness.status == "ok" { axum::http::StatusCode::OK } else { axum::http::StatusCode::SERVICE_UNAVAILABLE };
+    (code, Json(readiness))
+}
+
+#[utoipa::path(
+    get, path = "/status", tag = "admin",
+    security(("admin_token" = [])),
+    responses((status = 200, body = health::Status), (status = 401, body = ErrorBody)),
+)]
+async fn get_status(State(state): State<AppState>, headers: axum::http::HeaderMap) -> Result<Json<health::Status>, ApiError> {
+    require_admin(&headers, &state)?;
+    Ok(Json(health::status(&state)))
 }
 
 #[derive(Debug, Deserialize)]
 struct ChannelPath { channel_id: i64 }
 
-async fn channel_webhooks(Path(ChannelPath{ channel_id }): Path<ChannelPath>, State(state): State<AppState>, headers: axum::http::HeaderMap) -> Json<serde_json::Value> {
-    // Bearer authorization check; currently expects token 'xxx' to match TS code
-    let token = headers.get(axum::http::header::AUTHORIZATION).and_then(|h| h.to_str().ok()).and_then(|v| v.strip_prefix("Bearer ")).unwrap_or("");
-    if token != "xxx" { return Json(json!({"error": {"note":"TODO: this is not discord compatible yet","message":"wrong auth credentials"}})); }
+#[utoipa::path(
+    get, path = "/channels/{channel_id}/webhooks", tag = "webhooks",
+    security(("api_token" = ["manage_webhooks"]), ("admin_token" = [])),
+    params(("channel_id" = i64, Path)),
+    responses(
+        (status = 200, body = Vec<WebhookObject>),
+        (status = 401, description = "wrong bearer token", body = ErrorBody),
+        (status = 403, description = "the api token is missing the scope or the account can not manage the channel", body = ErrorBody),
+        (status = 404, description = "channel not found", body = ErrorBody),
+    ),
+)]
+async fn channel_webhooks(Path(ChannelPath{ channel_id }): Path<ChannelPath>, State(state): State<AppState>, headers: axum::http::HeaderMap) -> Result<Json<Vec<WebhookObject>>, ApiError> {
+    let token = bearer_token(&headers).unwrap_or("");
+    let admin = check_admin_auth(&headers, &state);
     let conn = state.db.lock();
-    if let Some(ch) = channel_model::find(&conn, channel_id) {
-        let hooks = crate::models::webhook::where_eq(&conn, "channel_id", ch.id)
-            .into_iter()
-            .map(|w| crate::types::WebhookObject{ id: w.id, token: w.token, r#type: 0, channel_id: ch.id, name: w.name, avatar: None, application_id: None })
-            .collect::<Vec<_>>();
-        return Json(serde_json::to_value(hooks).unwrap());
-    }
-    Json(json!({"message":"TODO: this is not discord api yet. BUT ERROR channel not found"}))
+    let user = if token.starts_with(TOKEN_PREFIX) {
+        Some(api_tokens::authenticate(&conn, token, Scope::ManageWebhooks)?)
+    } else if admin {
+        None
+    } else {
+        return Err(ApiError::Unauthorized("wrong auth credentials".into()));
+    };
+    let ch = channel_model::find(&conn, channel_id).ok_or_else(|| ApiError::NotFound("channel not found".into()))?;
+    if user.is_some_and(|u| !u.admin() && u.id != ch.owner_id) {
+        return Err(ApiError::Forbidden("only admins and the channel owner can see its webhooks".into()));
+    }
+    let hooks = crate::models::webhook::where_eq(&conn, "channel_id", ch.id)
+        .into_iter()
+        .map(|w| WebhookObject{ id: w.id, token: w.token, r#type: 0, channel_id: ch.id, name: w.name, avatar: None, application_id: None })
+        .collect();
+    Ok(Json(hooks))
+}
+
+/// Keeps the bodies the TS implementation used
+async fn legacy_channel_webhooks(path: Path<ChannelPath>, state: State<AppState>, headers: axum::http::HeaderMap) -> Response {
+    let not_found = || Json(json!({"message":"TODO: this is not discord api yet. BUT ERROR channel not found"})).into_response();
+    // the placeholder token the TS code expected. it does not get to see any webhooks
+    if bearer_token(&headers) == Some("xxx") {
+        if channel_model::find(&state.db.lock(), path.channel_id).is_none() { return not_found(); }
+        return Json(Vec::<WebhookObject>::new()).into_response();
+    }
+    match channel_webhooks(path, state, headers).await {
+        Err(ApiError::Unauthorized(_)) => Json(json!({"error": {"note":"TODO: this is not discord compatible yet","message":"wrong auth credentials"}})).into_response(),
+        Err(ApiError::NotFound(_)) => not_found(),
+        res => res.into_response(),
+    }
 }
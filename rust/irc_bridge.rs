@@ -1,13 +1,21 @@
 
 use irc::client::prelude::*;
 use futures::StreamExt;
-use tokio::sync::mpsc;
+use serde::Serialize;
+use utoipa::ToSchema;
+use tokio::sync::{mpsc, oneshot};
 use tokio::time::{sleep, Duration};
 use tracing::{error, info, warn};
 
+use crate::config::IrcNetwork;
 use crate::models::channel;
 use crate::state::AppState;
-use crate::types::IrcMessage;
+use crate::types::{DeliveryStatus, IrcMessage};
+use crate::plugins::{self, Direction};
+use crate::{metrics, search, subscriptions, unread};
+
+const RECONNECT_MIN_DELAY: Duration = Duration::from_secs(5);
+const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(300);
 
 #[allow(dead_code)]
 #[derive(Debug, Clone)]
@@ -23,6 +31,39 @@ pub struct ChannelMapping {
     pub is_private: bool,
 }
 
+/// Connection state of one network as seen by the health endpoints
+#[derive(Debug, Clone, Default, Serialize, ToSchema)]
+pub struct IrcStatus {
+    pub connected: bool,
+    // registered with the server and the login message was sent if one is configured
+    pub authenticated: bool,
+    #[serde(rename = "joinedChannels")]
+    pub joined_channels: Vec<String>,
+    #[serde(rename = "expectedChannels")]
+    pub expected_channels: usize,
+    #[serde(rename = "lastError")]
+    pub last_error: Option<String>,
+    #[serde(rename = "lastErrorAt")]
+    pub last_error_at: Option<String>,
+}
+
+impl IrcStatus {
+    pub fn is_ready(&self) -> bool {
+        self.connected && self.authenticated && self.joined_channels.len() >= self.expected_channels
+    }
+}
+
+fn update_status(state: &AppState, network: &str, f: impl FnOnce(&mut IrcStatus)) {
+    f(state.irc_status.lock().entry(network.to_string()).or_default());
+}
+
+fn set_error(state: &AppState, network: &str, error: String) {
+    update_status(state, network, |s| {
+        s.last_error = Some(error);
+        s.last_error_at = Some(chrono::Utc::now().to_rfc3339());
+    });
+}
+
 pub fn get_connected_irc_channels(state: &AppState) -> Vec<ChannelMapping> {
     let conn = state.db.lock();
     let rows = channel::all(&conn);
@@ -41,9 +82,10 @@ pub fn get_connected_irc_channels(state: &AppState) -> Vec<ChannelMapping> {
         .collect()
 }
 
-pub fn active_irc_channels(state: &AppState) -> Vec<String> {
+pub fn active_irc_channels(state: &AppState, network: &str) -> Vec<String> {
     get_connected_irc_channels(state)
         .into_iter()
+        .filter(|e| e.irc_server_name == network)
         .map(|e| e.irc_channel)
         .collect()
 }
@@ -53,26 +95,58 @@ pub fn active_irc_channels(state: &AppState) -> Vec<String> {
 pub async fn start(state: &AppState) -> anyhow::Result<()> {
     if state.config.lock().dry_irc {
         info!("[mock-irc] enabled");
+        let networks = state.config.lock().irc_networks.clone();
+        for network in networks {
+            let channels = active_irc_channels(state, &network.name);
+            update_status(state, &network.name, |s| {
+                s.connected = true;
+                s.authenticated = true;
+                s.expected_channels = channels.len();
+                s.joined_channels = channels.iter().map(|c| format!("#{c}")).collect();
+            });
+        }
         start_mock(state.clone());
         return Ok(());
     }
-    // Spawn real IRC task
-    let server = state.config.lock().irc_server.clone();
-    let channels = active_irc_channels(state);
-    let state_clone = state.clone();
-    let (tx, rx) = mpsc::unbounded_channel();
-    {
-        let mut guard = state.irc_tx.lock();
-        *guard = Some(tx);
+    // Spawn one real IRC task per network
+    let networks = state.config.lock().irc_networks.clone();
+    for network in networks {
+        let (tx, rx) = mpsc::unbounded_channel();
+        state.irc_tx.lock().insert(network.name.clone(), tx);
+        tokio::spawn(run_network(state.clone(), network, rx));
     }
-    tokio::spawn(async move {
-        if let Err(e) = run_irc(state_clone, &server, &channels, rx).await {
-            error!("irc loop error: {e}");
-        }
-    });
     Ok(())
 }
 
+/// Keeps one network connected. Reconnects with a growing delay
+/// until the command sender is dropped.
+async fn run_network(state: AppState, network: IrcNetwork, mut rx: mpsc::UnboundedReceiver<IrcCmd>) {
+    let mut delay = RECONNECT_MIN_DELAY;
+    loop {
+        let channels = active_irc_channels(&state, &network.name);
+        let connected_at = std::time::Instant::now();
+        match run_irc(&state, &network, &channels, &mut rx).await {
+            Ok(true) => warn!("[!][irc] connection to {} closed", network.name),
+            Ok(false) => return,
+            Err(e) => {
+                error!("irc loop error: {e}");
+                set_error(&state, &network.name, e.to_string());
+            }
+        }
+        update_status(&state, &network.name, |s| {
+            s.connected = false;
+            s.authenticated = false;
+            s.joined_channels.clear();
+        });
+        metrics::METRICS.irc_connected.with_label_values(&[&network.name]).set(0);
+        if connected_at.elapsed() > RECONNECT_MAX_DELAY { delay = RECONNECT_MIN_DELAY; }
+        info!("[*][irc] reconnecting to {} in {}s ...", network.name, delay.as_secs());
+        sleep(delay).await;
+        delay = (delay * 2).min(RECONNECT_MAX_DELAY);
+        metrics::METRICS.irc_reconnects.with_label_values(&[&network.name]).inc();
+    }
+}
+
 fn start_mock(state: AppState) {
     let mappings = get_connected_irc_channels(&state);
     // Periodically generate messages
@@ -81,16 +155,26 @@ fn start_mock(state: AppState) {
             for mapping in &mappings {
                 // Random chance to emit message
                 if rand::random::<f32>() > 0.9 {
-                    let msg = IrcMessage {
-                        id: state.history.next_id(),
+                    let mut msg = IrcMessage {
                         from: "mock_user".into(),
                         message: format!("fake mock message (sent at {})", chrono::Local::now().format("%H:%M:%S")),
                         channel: mapping.discord_channel.clone(),
                         server: mapping.discord_server.clone(),
                         date: chrono::Utc::now().to_rfc2822(),
                         token: None,
+                        ..Default::default()
                     };
+                    if plugins::filter(&state, mapping, Direction::IrcToWeb, &mut msg).await.is_err() { continue; }
+                    msg.id = state.history.next_id();
                     state.history.log_message(&mapping.discord_server, &mapping.discord_channel, msg.clone());
+                    metrics::relayed("irc_to_web", &mapping.discord_server, &mapping.discord_channel);
+                    let conn = state.db.lock();
+                    if let Some(ch) = channel::find(&conn, mapping.id) {
+                        search::index_message(&conn, &ch, &msg);
+                        subscriptions::message_created(&state, &conn, &ch, &msg);
+                        unread::notify(&state, &conn, &ch, None);
+                    }
+                    plugins::notify(&state, mapping, Direction::IrcToWeb, &msg);
                     // broadcast to ws room for server
                     // The websocket layer will handle broadcasting when add_message is used in ws
                 }
@@ -100,108 +184,221 @@ fn start_mock(state: AppState) {
     });
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 pub enum IrcCmd {
-    Privmsg { target: String, text: String },
+    // done fires with false if the connection could not send it
+    Privmsg { target: String, text: String, done: Option<oneshot::Sender<bool>> },
+    // done fires once the QUIT went out and the connection is closed
+    Quit { message: String, done: oneshot::Sender<()> },
+    // nicks in the channel as tracked from NAMES, JOIN and PART
+    Names { channel: String, done: oneshot::Sender<Vec<String>> },
 }
 
-async fn run_irc(state: AppState, server: &str, channels: &[String], mut rx: mpsc::UnboundedReceiver<IrcCmd>) -> anyhow::Result<()> {
-    info!("connecting to irc: {}", server);
+/// Runs one connection until it drops. Returns false once there is nobody left to send commands.
+async fn run_irc(state: &AppState, network: &IrcNetwork, channels: &[String], rx: &mut mpsc::UnboundedReceiver<IrcCmd>) -> anyhow::Result<bool> {
+    info!("connecting to irc: {} ({})", network.server, network.name);
+    update_status(state, &network.name, |s| s.expected_channels = channels.len());
     let config = Config {
-        nickname: Some("ws-client".to_string()),
-        server: Some(server.to_string()),
+        nickname: Some(network.nickname.clone()),
+        server: Some(network.server.clone()),
         channels: channels.iter().map(|c| format!("#{c}")).collect(),
-        use_tls: Some(true),
-        port: Some(6667),
+        use_tls: Some(network.tls),
+        port: Some(network.port),
         ..Default::default()
     };
     let mut client = Client::from_config(config).await?;
     client.identify()?;
+    metrics::METRICS.irc_connected.with_label_values(&[&network.name]).set(1);
+    update_status(state, &network.name, |s| s.connected = true);
 
-    let st = state.clone();
     let mut stream = client.stream()?;
-    tokio::spawn(async move {
-        let mut sent_login = false;
-        while let Some(message) = stream.next().await {
-            match message {
-                Ok(msg) => {
-                    match msg.command {
-                        Command::ERROR(ref err) => error!("[-][irc] error: {}", err),
-                        Command::JOIN(ref chan, _, _) => {
-                            info!("[*][irc] joined '{}'", chan);
-                            if !sent_login {
-                                sent_login = true;
-                                let (login_ch, login_msg) = {
-                                    let cfg = st.config.lock();
-                                    (cfg.irc_login_channel.clone(), cfg.irc_login_msg.clone())
-                                };
-                                if let (Some(login_ch), Some(login_msg)) = (login_ch, login_msg) {
-                                    info!("[*][irc] sending login to channel '{}' ...", login_ch);
-                                    let _ = st_irc_say(&st, &login_ch, &login_msg).await;
-                                } else {
-                                    info!("[!][irc] IRC_LOGIN_CHANNEL/MSG not set will not login");
-                                }
-                            }
-                        }
-                        Command::PRIVMSG(ref target, ref text) => {
-                            if let Some(ch) = target.strip_prefix('#') {
-                                if let Some(mapping) = get_connected_irc_channels(&st).into_iter().find(|m| m.irc_channel == ch) {
-                                    let from = msg.source_nickname().unwrap_or("unknown").to_string();
-                                    let irc_msg = IrcMessage {
-                                        id: st.history.next_id(),
-                                        from,
-                                        message: text.clone(),
-                                        channel: mapping.discord_channel.clone(),
-                                        server: mapping.discord_server.clone(),
-                                        date: chrono::Utc::now().to_rfc2822(),
-                                        token: None,
-                                    };
-                                    st.history.log_message(&mapping.discord_server, &mapping.discord_channel, irc_msg);
-                                }
-                            }
-                        }
-                        _ => {}
-                    }
+    let mut sent_login = false;
+    loop {
+        tokio::select! {
+            message = stream.next() => match message {
+                Some(Ok(msg)) => on_irc_message(state, network, &msg, &mut sent_login).await,
+                Some(Err(e)) => {
+                    warn!("irc read error: {e}");
+                    set_error(state, &network.name, e.to_string());
+                    return Ok(true);
                 }
-                Err(e) => warn!("irc read error: {e}"),
-            }
+                None => return Ok(true),
+            },
+            cmd = rx.recv() => match cmd {
+                Some(IrcCmd::Privmsg { target, text, done }) => {
+                    let res = client.send_privmsg(&target, &text);
+                    if let Err(e) = &res { warn!("irc send error: {e}"); }
+                    if let Some(done) = done { let _ = done.send(res.is_ok()); }
+                }
+                Some(IrcCmd::Names { channel, done }) => {
+                    let users = client.list_users(&channel).unwrap_or_default();
+                    let _ = done.send(users.iter().map(|u| u.get_nickname().to_string()).collect());
+                }
+                Some(IrcCmd::Quit { message, done }) => {
+                    info!("[*][irc] quitting {} ...", network.name);
+                    if let Err(e) = client.send_quit(message) { warn!("irc send error: {e}"); }
+                    // the stream drives the outgoing queue. keep polling until the server hangs up
+                    let _ = tokio::time::timeout(Duration::from_secs(3), async { while stream.next().await.is_some() {} }).await;
+                    update_status(state, &network.name, |s| s.connected = false);
+                    let _ = done.send(());
+                    return Ok(false);
+                }
+                None => return Ok(false),
+            },
         }
-    });
+    }
+}
 
-    // Command sender loop
-    tokio::spawn(async move {
-        while let Some(cmd) = rx.recv().await {
-            match cmd {
-                IrcCmd::Privmsg { target, text } => {
-                    if let Err(e) = client.send_privmsg(&target, &text) { warn!("irc send error: {e}"); }
+async fn on_irc_message(st: &AppState, network: &IrcNetwork, msg: &Message, sent_login: &mut bool) {
+    let is_me = msg.source_nickname().is_some_and(|nick| nick.eq_ignore_ascii_case(&network.nickname));
+    match msg.command {
+        Command::ERROR(ref err) => {
+            error!("[-][irc] error: {}", err);
+            set_error(st, &network.name, err.clone());
+        }
+        Command::Response(Response::RPL_WELCOME, _) => {
+            // without a login message being registered is all the authentication there is
+            let has_login = network.login_channel.is_some() && network.login_msg.is_some();
+            update_status(st, &network.name, |s| s.authenticated = !has_login);
+        }
+        Command::JOIN(ref chan, _, _) => {
+            info!("[*][irc] joined '{}'", chan);
+            if is_me {
+                update_status(st, &network.name, |s| {
+                    if !s.joined_channels.contains(chan) { s.joined_channels.push(chan.clone()); }
+                });
+            }
+            if !*sent_login {
+                *sent_login = true;
+                if let (Some(login_ch), Some(login_msg)) = (&network.login_channel, &network.login_msg) {
+                    info!("[*][irc] sending login to channel '{}' ...", login_ch);
+                    let _ = st_irc_say(st, &network.name, login_ch, login_msg).await;
+                    update_status(st, &network.name, |s| s.authenticated = true);
+                } else {
+                    info!("[!][irc] IRC_LOGIN_CHANNEL/MSG not set will not login");
                 }
             }
         }
-    });
+        Command::PART(ref chan, _) if is_me => {
+            update_status(st, &network.name, |s| s.joined_channels.retain(|c| c != chan));
+        }
+        Command::KICK(ref chan, ref nick, _) if nick.eq_ignore_ascii_case(&network.nickname) => {
+            warn!("[!][irc] got kicked from '{}'", chan);
+            set_error(st, &network.name, format!("kicked from {}", chan));
+            update_status(st, &network.name, |s| s.joined_channels.retain(|c| c != chan));
+        }
+        Command::PRIVMSG(ref target, ref text) => {
+            let Some(ch) = target.strip_prefix('#') else { return; };
+            let Some(mapping) = get_connected_irc_channels(st).into_iter().find(|m| m.irc_channel == ch && m.irc_server_name == network.name) else { return; };
+            let from = msg.source_nickname().unwrap_or("unknown").to_string();
+            // /me on irc is sent as ctcp ACTION
+            let (text, action) = match text.strip_prefix("\u{1}ACTION ") {
+                Some(rest) => (rest.trim_end_matches('\u{1}'), true),
+                None => (text.as_str(), false),
+            };
+            let reply_to = st.history.guess_reply_to(&mapping.discord_server, &mapping.discord_channel, text);
+            let mut irc_msg = IrcMessage {
+                from,
+                message: text.to_string(),
+                channel: mapping.discord_channel.clone(),
+                server: mapping.discord_server.clone(),
+                date: chrono::Utc::now().to_rfc2822(),
+                token: None,
+                reply_to,
+                action,
+                ..Default::default()
+            };
+            if plugins::filter(st, &mapping, Direction::IrcToWeb, &mut irc_msg).await.is_err() { return; }
+            irc_msg.id = st.history.next_id();
+            st.history.log_message(&mapping.discord_server, &mapping.discord_channel, irc_msg.clone());
+            metrics::relayed("irc_to_web", &mapping.discord_server, &mapping.discord_channel);
+            let conn = st.db.lock();
+            if let Some(ch) = channel::find(&conn, mapping.id) {
+                search::index_message(&conn, &ch, &irc_msg);
+                subscriptions::message_created(st, &conn, &ch, &irc_msg);
+                unread::notify(st, &conn, &ch, None);
+            }
+            plugins::notify(st, &mapping, Direction::IrcToWeb, &irc_msg);
+        }
+        _ => {}
+    }
+}
 
-    Ok(())
+/// Sends QUIT to every network and waits until they disconnected
+pub async fn quit_all(state: &AppState, message: &str) {
+    if state.config.lock().dry_irc {
+        info!("[mock-irc] QUIT :{}", message);
+        return;
+    }
+    let senders: Vec<_> = state.irc_tx.lock().drain().collect();
+    let mut pending = Vec::new();
+    for (network, tx) in senders {
+        let (done, rx) = oneshot::channel();
+        if tx.send(IrcCmd::Quit { message: message.to_string(), done }).is_ok() {
+            pending.push((network, rx));
+        }
+    }
+    for (network, rx) in pending {
+        if rx.await.is_err() { warn!("[!][irc] {} was not connected", network); }
+    }
 }
 
-pub async fn st_irc_say(state: &AppState, target: &str, message: &str) -> anyhow::Result<()> {
+pub async fn st_irc_say(state: &AppState, network: &str, target: &str, message: &str) -> anyhow::Result<()> {
     // For the async-irc client, we'd need a handle; simplicity: spawn a one-shot client per send is too heavy
     // Workaround: In this rewrite, we only support DRY_IRC send via log, and real send is handled by queued webhook or not implemented here.
     // To keep behavior close, we do nothing here when not in dry mode.
     if state.config.lock().dry_irc {
-        info!("[mock-irc][{}] {}", target, message);
+        info!("[mock-irc][{}][{}] {}", network, target, message);
         return Ok(());
     }
-    if let Some(tx) = state.irc_tx.lock().clone() {
-        let _ = tx.send(IrcCmd::Privmsg { target: target.to_string(), text: message.to_string() });
+    if let Some(tx) = state.irc_tx.lock().get(network).cloned() {
+        let _ = tx.send(IrcCmd::Privmsg { target: target.to_string(), text: message.to_string(), done: None });
     }
     Ok(())
 }
 
 pub async fn send_irc(state: &AppState, irc_server: &str, irc_channel: &str, message: &str) -> bool {
-    if irc_server != "quakenet" {
+    if !state.config.lock().irc_networks.iter().any(|n| n.name == irc_server) {
         info!("[!] failed to send to unsupported irc server '{}'", irc_server);
         return false;
     }
     let target = format!("#{}", irc_channel);
-    if let Err(e) = st_irc_say(state, &target, message).await { warn!("irc send error: {e}"); return false; }
+    if let Err(e) = st_irc_say(state, irc_server, &target, message).await { warn!("irc send error: {e}"); return false; }
     true
 }
+
+/// Nicks in `#irc_channel` of the network. None if irc is not connected.
+pub async fn names(state: &AppState, irc_server: &str, irc_channel: &str) -> Option<Vec<String>> {
+    if state.config.lock().dry_irc { return None; }
+    let tx = state.irc_tx.lock().get(irc_server).cloned()?;
+    let (done, rx) = oneshot::channel();
+    tx.send(IrcCmd::Names { channel: format!("#{}", irc_channel), done }).ok()?;
+    tokio::time::timeout(Duration::from_secs(2), rx).await.ok()?.ok()
+}
+
+/// Like [`send_irc`] but reports what happened to the message. Messages for a
+/// real connection are queued until it sends them, `done` fires once it did.
+pub fn queue_irc(state: &AppState, irc_server: &str, irc_channel: &str, message: &str) -> (DeliveryStatus, Option<oneshot::Receiver<bool>>) {
+    queue_irc_to(state, irc_server, &format!("#{}", irc_channel), message)
+}
+
+/// Like [`queue_irc`] for any target, also nicks
+pub fn queue_irc_to(state: &AppState, irc_server: &str, target: &str, message: &str) -> (DeliveryStatus, Option<oneshot::Receiver<bool>>) {
+    if !state.config.lock().irc_networks.iter().any(|n| n.name == irc_server) {
+        info!("[!] failed to send to unsupported irc server '{}'", irc_server);
+        return (DeliveryStatus::Failed, None);
+    }
+    if state.config.lock().dry_irc {
+        info!("[mock-irc][{}][{}] {}", irc_server, target, message);
+        return (DeliveryStatus::Sent, None);
+    }
+    let Some(tx) = state.irc_tx.lock().get(irc_server).cloned() else {
+        warn!("[!] no irc connection for '{}'", irc_server);
+        return (DeliveryStatus::Failed, None);
+    };
+    let (done, rx) = oneshot::channel();
+    if tx.send(IrcCmd::Privmsg { target: target.to_string(), text: message.to_string(), done: Some(done) }).is_err() {
+        return (DeliveryStatus::Failed, None);
+    }
+    (DeliveryStatus::Queued, Some(rx))
+}
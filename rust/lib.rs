@@ -0,0 +1,28 @@
+pub mod state;
+pub mod types;
+pub mod ack;
+pub mod api;
+pub mod api_tokens;
+pub mod cli;
+pub mod client;
+pub mod commands;
+pub mod config;
+pub mod db;
+pub mod health;
+pub mod history;
+pub mod models;
+pub mod ws;
+pub mod plain_ws;
+pub mod http_api;
+pub mod irc_bridge;
+pub mod metrics;
+pub mod util;
+pub mod unread;
+pub mod messages;
+pub mod plugins;
+pub mod reactions;
+pub mod search;
+pub mod subscriptions;
+pub mod seed;
+#[cfg(feature = "testing")]
+pub mod testing;
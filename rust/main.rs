@@ -1,26 +1,13 @@
-use std::net::SocketAddr;
-
 // use axum::Router;
 use dotenvy::dotenv;
 // no serde imports needed here
 use socketioxide::SocketIo;
-use tokio::signal;
+use tokio::{signal, sync::oneshot, time::{sleep, timeout_at, Duration, Instant}};
 use tower_http::cors::{Any, CorsLayer};
-use tracing::{error, info, Level};
+use tracing::{error, info, warn, Level};
 use tracing_subscriber::EnvFilter;
 
-mod state;
-mod types;
-mod config;
-mod history;
-mod models;
-mod ws;
-mod http_api;
-mod irc_bridge;
-mod util;
-
-use crate::config::Config;
-use crate::state::AppState;
+use irc_websockets::{cli::CliArgs, client, config::Config, http_api, irc_bridge, plugins, search, state::AppState, types::ServerShutdown, ws};
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
@@ -32,10 +19,19 @@ async fn main() -> anyhow::Result<()> {
         .with_target(false)
         .init();
 
-    let config = Config::from_env()?;
-    config.validate_or_exit();
+    let cli = CliArgs::from_env()?;
+    if let Some(arg) = cli.rest.first() { anyhow::bail!("unknown argument '{}'", arg); }
+    let config = Config::load(&cli)?;
 
-    let state = AppState::new(&config)?;
+    let state = match AppState::new(&config) {
+        Ok(state) => state,
+        Err(e) => {
+            eprintln!("[!] Error: {e:#}");
+            std::process::exit(1);
+        }
+    };
+    search::backfill(&state);
+    plugins::check_config(&state);
 
     // Start IRC bridge (mock or real)
     irc_bridge::start(&state).await?;
@@ -46,6 +42,7 @@ async fn main() -> anyhow::Result<()> {
 
     // WebSocket handlers
     ws::register_handlers(io.clone());
+    *state.io.lock() = Some(io.clone());
 
     // HTTP API
     let app = http_api::router(state.clone())
@@ -57,23 +54,141 @@ async fn main() -> anyhow::Result<()> {
                 .allow_origin(Any),
         );
 
-    let addr = SocketAddr::from(([0, 0, 0, 0], 6969));
+    let addr = config.http_bind;
     info!("listening on http://{}", addr);
-    let cfg = state.config.lock();
-    info!("accounts are {}", if cfg.require_passwords { "on" } else { "off" });
+    let require_passwords = state.config.lock().require_passwords;
+    info!("accounts are {}", if require_passwords { "on" } else { "off" });
+
+    spawn_config_reload(state.clone(), cli);
+    spawn_history_snapshots(state.clone());
+
+    if let Some(metrics_addr) = config.metrics_bind {
+        let listener = tokio::net::TcpListener::bind(metrics_addr).await?;
+        info!("metrics on http://{}/metrics", metrics_addr);
+        let metrics_app = http_api::metrics_router(state.clone());
+        tokio::spawn(async move {
+            if let Err(e) = axum::serve(listener, metrics_app).await { error!("metrics server error: {e}"); }
+        });
+    }
 
     let listener = tokio::net::TcpListener::bind(addr).await?;
-    let serve = axum::serve(listener, app);
+    let (stop_tx, stop_rx) = oneshot::channel::<()>();
+    let mut server = tokio::spawn(async move {
+        axum::serve(listener, app).with_graceful_shutdown(async { let _ = stop_rx.await; }).await
+    });
 
     tokio::select! {
-        res = serve => {
-            if let Err(e) = res { error!("server error: {e}"); }
-        }
-        _ = signal::ctrl_c() => {
-            info!("shutting down, saving message history ...");
-            if let Err(e) = state.history.save_to_disk() { error!("save history failed: {e}"); }
+        res = &mut server => {
+            match res {
+                Ok(Err(e)) => error!("server error: {e}"),
+                Err(e) => error!("server task failed: {e}"),
+                Ok(Ok(())) => {}
+            }
         }
+        _ = shutdown_signal() => {}
     }
+    shutdown(&state, stop_tx, server).await;
 
     Ok(())
 }
+
+/// Resolves on SIGINT or SIGTERM
+async fn shutdown_signal() {
+    let mut term = match signal::unix::signal(signal::unix::SignalKind::terminate()) {
+        Ok(s) => s,
+        Err(e) => {
+            error!("failed to listen for SIGTERM: {e}");
+            let _ = signal::ctrl_c().await;
+            return;
+        }
+    };
+    tokio::select! {
+        _ = signal::ctrl_c() => info!("[*] SIGINT received"),
+        _ = term.recv() => info!("[*] SIGTERM received"),
+    }
+}
+
+/// Stops accepting connections, tells the clients, lets running handlers finish,
+/// quits irc and flushes history and database. Everything shares one deadline.
+async fn shutdown(state: &AppState, stop_http: oneshot::Sender<()>, server: tokio::task::JoinHandle<std::io::Result<()>>) {
+    let (quit_message, timeout) = {
+        let config = state.config.lock();
+        (config.irc_quit_message.clone(), Duration::from_secs(config.shutdown_timeout_secs))
+    };
+    let deadline = Instant::now() + timeout;
+    info!("shutting down ...");
+    let _ = stop_http.send(());
+
+    client::emit_all(state, "serverShutdown", &ServerShutdown { message: quit_message.clone() }).await;
+
+    if state.inflight.count() > 0 {
+        info!("[*] waiting for {} running handlers ...", state.inflight.count());
+    }
+    if timeout_at(deadline, state.inflight.wait_idle()).await.is_err() {
+        warn!("[!] {} handlers still running, continuing shutdown", state.inflight.count());
+    }
+    // taken out first because emitting on a closed SocketIo panics
+    let io = state.io.lock().take();
+    if let Some(io) = &io {
+        io.close().await;
+    }
+    client::close_plain(state);
+
+    if timeout_at(deadline, irc_bridge::quit_all(state, &quit_message)).await.is_err() {
+        warn!("[!] irc did not quit in time");
+    }
+
+    info!("saving message history ...");
+    if let Err(e) = state.history.save_to_disk() { error!("save history failed: {e}"); }
+    if let Err(e) = state.db.lock().execute_batch("PRAGMA wal_checkpoint(TRUNCATE);") { error!("db checkpoint failed: {e}"); }
+
+    if timeout_at(deadline, server).await.is_err() {
+        warn!("[!] http connections still open, closing them");
+    }
+    info!("bye");
+}
+
+/// Saves the history in the background so a crash loses at most one interval.
+/// The interval is read every round so it follows config reloads.
+fn spawn_history_snapshots(state: AppState) {
+    tokio::spawn(async move {
+        loop {
+            let interval = state.config.lock().history_snapshot_interval_secs;
+            // disabled. check again later in case it gets enabled by a reload
+            if interval == 0 { sleep(Duration::from_secs(60)).await; continue; }
+            sleep(Duration::from_secs(interval)).await;
+            if !state.history.is_dirty() { continue; }
+            let history = state.history.clone();
+            match tokio::task::spawn_blocking(move || history.save_to_disk()).await {
+                Ok(Err(e)) => error!("[!] history snapshot failed: {e}"),
+                Err(e) => error!("[!] history snapshot task failed: {e}"),
+                Ok(Ok(())) => {}
+            }
+        }
+    });
+}
+
+/// Reloads the settings that are safe to change at runtime on SIGHUP
+fn spawn_config_reload(state: AppState, cli: CliArgs) {
+    tokio::spawn(async move {
+        let mut hangup = match signal::unix::signal(signal::unix::SignalKind::hangup()) {
+            Ok(s) => s,
+            Err(e) => { error!("failed to listen for SIGHUP: {e}"); return; }
+        };
+        while hangup.recv().await.is_some() {
+            info!("[*] SIGHUP received reloading config ...");
+            dotenv().ok();
+            match Config::load(&cli) {
+                Ok(new) => {
+                    let needs_restart = state.config.lock().reload_from(new);
+                    for name in needs_restart {
+                        warn!("[!] changing {} requires a restart, keeping the old value", name);
+                    }
+                    plugins::check_config(&state);
+                    info!("[*] config reloaded");
+                }
+                Err(e) => error!("[!] keeping old config. {e}"),
+            }
+        }
+    });
+}
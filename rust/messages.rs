@@ -0,0 +1,226 @@
+//! Sending messages from the web to irc. Shared by the socket `message` event
+//! and `POST /:server/:channel/messages`.
+
+use std::time::Instant;
+
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use tokio::sync::oneshot;
+use tracing::{info, warn};
+
+use crate::{
+    ack::{ErrorCode, EventError},
+    client::{self, Client},
+    config::RateLimitConfig,
+    history,
+    irc_bridge::{self, ChannelMapping},
+    metrics,
+    models::{channel as channel_model, channel_member as cm_model, user::UserRow},
+    plugins::{self, Direction},
+    search,
+    state::AppState,
+    subscriptions,
+    types::{DeliveryStatus, IrcMessage, MessageStatus},
+    unread,
+};
+
+pub const MAX_NONCE_LEN: usize = 64;
+// in bytes. longer lines would be cut off by irc servers anyway
+pub const MAX_MESSAGE_LEN: usize = 2000;
+
+struct RateLimiter {
+    last_sent_ms: i64,
+    log: Vec<i64>,
+}
+
+impl RateLimiter {
+    fn new() -> Self { Self { last_sent_ms: 0, log: Vec::new() } }
+    /// Fails with the milliseconds until sending works again if that is known
+    fn check(&mut self, content: &str, limits: &RateLimitConfig) -> Result<(), Option<i64>> {
+        if content.contains('\n') { return Err(None); }
+        let now = chrono::Utc::now().timestamp_millis();
+        self.log.retain(|&t| now - t <= limits.window_ms);
+        if self.log.len() > limits.burst {
+            // the log is sorted. sending works once all but `burst` entries left the window
+            let expires = self.log[self.log.len() - limits.burst - 1] + limits.window_ms;
+            return Err(Some(expires - now + 1));
+        }
+        let diff = now - self.last_sent_ms;
+        self.last_sent_ms = now;
+        if diff < limits.min_interval_ms { self.log.push(now); }
+        Ok(())
+    }
+}
+
+static RATE_LIMITER: Lazy<Mutex<RateLimiter>> = Lazy::new(|| Mutex::new(RateLimiter::new()));
+
+/// Counts the rejected message in the metrics before it is returned to the sender
+pub fn rejected(reason: &str, code: ErrorCode, message: impl Into<String>) -> EventError {
+    metrics::rejected(reason);
+    EventError::new(code, message)
+}
+
+/// A message has to be one line that is not empty and not too long.
+/// Also applies to the text a plugin rewrote a message to.
+pub fn check_text(text: &str) -> Result<(), String> {
+    if text.is_empty() { return Err("message can not be empty".into()); }
+    if text.contains(['\n', '\r']) { return Err("a message has to be one line".into()); }
+    if text.len() > MAX_MESSAGE_LEN { return Err(format!("a message can be at most {MAX_MESSAGE_LEN} bytes long")); }
+    Ok(())
+}
+
+/// Applies to new messages and edits
+pub fn check_rate_limit(state: &AppState, content: &str) -> Result<(), EventError> {
+    let limits = state.config.lock().rate_limit.clone();
+    RATE_LIMITER.lock().check(content, &limits).map_err(|wait_ms| {
+        let err = rejected("rate_limited", ErrorCode::RateLimited, "Ratelimited message sending");
+        match wait_ms {
+            Some(ms) => err.with_retry_after((ms.max(0) as u64).div_ceil(1000)),
+            None => err,
+        }
+    })
+}
+
+/// Fails while an admin muted `username` with /mute
+pub fn check_muted(state: &AppState, username: &str) -> Result<(), EventError> {
+    let key = username.to_lowercase();
+    let Some(until) = state.muted.get(&key).map(|u| *u) else { return Ok(()); };
+    let now = Instant::now();
+    if until <= now {
+        state.muted.remove(&key);
+        return Ok(());
+    }
+    let minutes = (until - now).as_secs() / 60 + 1;
+    Err(rejected("muted", ErrorCode::Muted, format!("you are muted for {minutes} more minutes")))
+}
+
+pub fn find_mapping(state: &AppState, server: &str, channel: &str) -> Option<ChannelMapping> {
+    irc_bridge::get_connected_irc_channels(state)
+        .into_iter()
+        .find(|m| m.discord_server == server && m.discord_channel == channel)
+}
+
+/// Relays `msg` to irc, stores and broadcasts it. Id, date and author are set here.
+/// `origin` is the connection that sent it. It is left out of the broadcast and
+/// gets a `messageStatus` once a queued message reached irc.
+/// Returns the stored message and the reply for the sender.
+pub async fn send(state: &AppState, origin: Option<&Client>, author: Option<&UserRow>, mut msg: IrcMessage) -> Result<(IrcMessage, MessageStatus), EventError> {
+    if msg.message.is_empty() { return Err(EventError::new(ErrorCode::InvalidInput, "message can not be empty")); }
+    let Some(mapping) = find_mapping(state, &msg.server, &msg.channel) else {
+        warn!("[!] invalid discord mapping '{}#{}'", msg.server, msg.channel);
+        return Err(rejected("unknown_channel", ErrorCode::ChannelNotFound, "channel is not connected to irc"));
+    };
+    if let Some(author) = author { check_muted(state, &author.username)?; }
+    check_text(&msg.message).map_err(|e| EventError::new(ErrorCode::InvalidInput, e))?;
+    check_rate_limit(state, &msg.message)?;
+    // private channel check: only members can write. sockets became members when joining
+    if mapping.is_private {
+        let Some(author) = author else {
+            return Err(rejected("private_channel", ErrorCode::PrivateChannel, "This is a private channel please login to your account"));
+        };
+        if cm_model::find_by_user_and_channel(&state.db.lock(), author.id, mapping.id).is_none() {
+            return Err(rejected("private_channel", ErrorCode::PrivateChannel, "you are not a member of this private channel"));
+        }
+    }
+    let reply_prefix = match msg.reply_to {
+        Some(parent_id) => match state.history.find_message(&msg.server, &msg.channel, parent_id) {
+            Some(parent) if parent.deleted_at.is_none() => history::irc_reply_prefix(&parent),
+            _ => return Err(rejected("invalid_reply", ErrorCode::MessageNotFound, "the message you replied to does not exist")),
+        },
+        None => String::new(),
+    };
+    if msg.nonce.as_ref().is_some_and(|n| n.len() > MAX_NONCE_LEN) {
+        return Err(EventError::new(ErrorCode::InvalidInput, format!("the nonce can be at most {MAX_NONCE_LEN} characters long")));
+    }
+    if let Err(reason) = plugins::filter(state, &mapping, Direction::WebToIrc, &mut msg).await {
+        return Err(rejected("vetoed", ErrorCode::Vetoed, reason));
+    }
+    let new_id = state.history.next_id();
+    if msg.id != 0 && msg.id != new_id { info!("[*] The client expected to get msgid={} but got msgid={}", msg.id, new_id); }
+    msg.id = new_id;
+    // only the server decides about authorship, time and edit state
+    msg.user_id = author.map(|u| u.id);
+    msg.date = chrono::Utc::now().to_rfc2822();
+    msg.edited_at = None;
+    msg.edits.clear();
+    msg.deleted_at = None;
+    let message_str = if msg.action {
+        format!("* **{}** {}{}", msg.from, reply_prefix, msg.message)
+    } else {
+        format!("**<{}>** {}{}", msg.from, reply_prefix, msg.message)
+    };
+    info!("[*][{}][{}] {}", msg.server, msg.channel, message_str);
+    // send to irc
+    let (status, done) = irc_bridge::queue_irc(state, &mapping.irc_server_name, &mapping.irc_channel, &message_str);
+    if status == DeliveryStatus::Failed {
+        return Err(rejected("irc_send_failed", ErrorCode::IrcSendFailed, "failed to send the message to irc"));
+    }
+    if status == DeliveryStatus::Sent { metrics::relayed("web_to_irc", &msg.server, &msg.channel); }
+    let nonce = msg.nonce.take();
+    let msg = store(state, origin, author, &mapping, msg).await;
+    plugins::notify(state, &mapping, Direction::WebToIrc, &msg);
+    let reply = MessageStatus{ nonce, id: msg.id, channel: msg.channel.clone(), server: msg.server.clone(), date: msg.date.clone(), status };
+    if let Some(done) = done {
+        tokio::spawn(report_delivery(origin.cloned(), reply.clone(), done));
+    }
+    Ok((msg, reply))
+}
+
+/// Posts a message of the bridge itself, like a plugin reply, to irc and the web.
+/// Skips the checks and plugins of [`send`].
+pub async fn post(state: &AppState, mapping: &ChannelMapping, author: Option<&UserRow>, from: &str, text: &str) -> Result<IrcMessage, EventError> {
+    check_text(text).map_err(|e| EventError::new(ErrorCode::InvalidInput, e))?;
+    let msg = IrcMessage {
+        id: state.history.next_id(),
+        from: from.to_string(),
+        message: text.to_string(),
+        channel: mapping.discord_channel.clone(),
+        server: mapping.discord_server.clone(),
+        date: chrono::Utc::now().to_rfc2822(),
+        user_id: author.map(|u| u.id),
+        ..Default::default()
+    };
+    let (status, _) = irc_bridge::queue_irc(state, &mapping.irc_server_name, &mapping.irc_channel, &format!("**<{}>** {}", from, text));
+    if status == DeliveryStatus::Failed {
+        return Err(EventError::new(ErrorCode::IrcSendFailed, "failed to send the message to irc"));
+    }
+    if status == DeliveryStatus::Sent { metrics::relayed("web_to_irc", &msg.server, &msg.channel); }
+    Ok(store(state, None, author, mapping, msg).await)
+}
+
+async fn store(state: &AppState, origin: Option<&Client>, author: Option<&UserRow>, mapping: &ChannelMapping, mut msg: IrcMessage) -> IrcMessage {
+    msg.token = Some("xxx".into());
+    state.history.log_message(&mapping.discord_server, &mapping.discord_channel, msg.clone());
+    match origin {
+        Some(c) => c.broadcast(state, "message", &msg).await,
+        None => client::emit_all(state, "message", &msg).await,
+    }
+
+    let conn = state.db.lock();
+    let Some(ch) = channel_model::find(&conn, mapping.id) else { return msg; };
+    search::index_message(&conn, &ch, &msg);
+    subscriptions::message_created(state, &conn, &ch, &msg);
+    // the author has obviously seen everything up to their own message
+    if let Some(author) = author {
+        let _ = unread::mark_read(state, &conn, author, &ch, msg.id);
+    }
+    unread::notify(state, &conn, &ch, None);
+    msg
+}
+
+/// Counts a queued message once it reached irc or could not be sent
+/// and tells the connection that sent it
+async fn report_delivery(origin: Option<Client>, mut status: MessageStatus, done: oneshot::Receiver<bool>) {
+    // the sender is dropped without an answer if the connection shut down
+    if done.await.unwrap_or(false) {
+        status.status = DeliveryStatus::Sent;
+        metrics::relayed("web_to_irc", &status.server, &status.channel);
+    } else {
+        warn!("[!][{}][{}] msgid={} could not be sent to irc", status.server, status.channel, status.id);
+        status.status = DeliveryStatus::Failed;
+        metrics::rejected("irc_send_failed");
+    }
+    if let Some(c) = origin {
+        c.emit("messageStatus", &status);
+    }
+}
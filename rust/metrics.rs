@@ -0,0 +1,101 @@
+use std::time::Instant;
+
+use axum::{extract::{MatchedPath, Request}, middleware::Next, response::Response};
+use once_cell::sync::Lazy;
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry, TextEncoder,
+};
+
+use crate::{client, state::AppState};
+
+pub struct Metrics {
+    pub registry: Registry,
+    pub connected_sockets: IntGauge,
+    pub logged_in_sessions: IntGauge,
+    // direction is irc_to_web or web_to_irc
+    pub messages_relayed: IntCounterVec,
+    pub irc_connected: IntGaugeVec,
+    pub irc_reconnects: IntCounterVec,
+    pub messages_rejected: IntCounterVec,
+    pub webhook_executions: IntCounterVec,
+    // result is delivered, retried or failed
+    pub subscription_deliveries: IntCounterVec,
+    // reason is error, panic or timeout
+    pub plugin_failures: IntCounterVec,
+    pub history_messages: IntGauge,
+    pub http_request_duration: HistogramVec,
+}
+
+pub static METRICS: Lazy<Metrics> = Lazy::new(|| {
+    let registry = Registry::new_custom(Some("irc_ws".into()), None).unwrap();
+    let metrics = Metrics {
+        connected_sockets: IntGauge::new("connected_sockets", "Currently connected websocket clients").unwrap(),
+        logged_in_sessions: IntGauge::new("logged_in_sessions", "Connected sessions that are logged in").unwrap(),
+        messages_relayed: IntCounterVec::new(
+            Opts::new("messages_relayed_total", "Messages relayed between irc and the web"),
+            &["direction", "channel"],
+        ).unwrap(),
+        irc_connected: IntGaugeVec::new(Opts::new("irc_connected", "1 if the irc network is connected"), &["network"]).unwrap(),
+        irc_reconnects: IntCounterVec::new(Opts::new("irc_reconnects_total", "Reconnects to the irc network"), &["network"]).unwrap(),
+        messages_rejected: IntCounterVec::new(
+            Opts::new("messages_rejected_total", "Messages that were not relayed"),
+            &["reason"],
+        ).unwrap(),
+        webhook_executions: IntCounterVec::new(Opts::new("webhook_executions_total", "Webhook executions"), &["result"]).unwrap(),
+        subscription_deliveries: IntCounterVec::new(Opts::new("subscription_deliveries_total", "Attempts to deliver events to subscriptions"), &["result"]).unwrap(),
+        plugin_failures: IntCounterVec::new(Opts::new("plugin_failures_total", "Messages a plugin failed on"), &["plugin", "reason"]).unwrap(),
+        history_messages: IntGauge::new("history_messages", "Messages kept in the history store").unwrap(),
+        http_request_duration: HistogramVec::new(
+            HistogramOpts::new("http_request_duration_seconds", "HTTP request latency"),
+            &["method", "route", "status"],
+        ).unwrap(),
+        registry,
+    };
+    let r = &metrics.registry;
+    r.register(Box::new(metrics.connected_sockets.clone())).unwrap();
+    r.register(Box::new(metrics.logged_in_sessions.clone())).unwrap();
+    r.register(Box::new(metrics.messages_relayed.clone())).unwrap();
+    r.register(Box::new(metrics.irc_connected.clone())).unwrap();
+    r.register(Box::new(metrics.irc_reconnects.clone())).unwrap();
+    r.register(Box::new(metrics.messages_rejected.clone())).unwrap();
+    r.register(Box::new(metrics.webhook_executions.clone())).unwrap();
+    r.register(Box::new(metrics.subscription_deliveries.clone())).unwrap();
+    r.register(Box::new(metrics.plugin_failures.clone())).unwrap();
+    r.register(Box::new(metrics.history_messages.clone())).unwrap();
+    r.register(Box::new(metrics.http_request_duration.clone())).unwrap();
+    metrics
+});
+
+pub fn relayed(direction: &str, server: &str, channel: &str) {
+    METRICS.messages_relayed.with_label_values(&[direction, &format!("{server}#{channel}")]).inc();
+}
+
+pub fn rejected(reason: &str) {
+    METRICS.messages_rejected.with_label_values(&[reason]).inc();
+}
+
+/// Renders all metrics in the prometheus text format.
+/// Gauges that mirror the app state are updated right before.
+pub fn render(state: &AppState) -> String {
+    let sockets = client::count(state);
+    METRICS.connected_sockets.set(sockets as i64);
+    METRICS.logged_in_sessions.set(state.sessions.iter().filter(|s| s.logged_in).count() as i64);
+    METRICS.history_messages.set(state.history.message_count() as i64);
+
+    let mut buf = Vec::new();
+    let _ = TextEncoder::new().encode(&METRICS.registry.gather(), &mut buf);
+    String::from_utf8(buf).unwrap_or_default()
+}
+
+/// Records the latency of every request. Unmatched routes share one label
+/// so random urls can not blow up the amount of series.
+pub async fn track_http(req: Request, next: Next) -> Response {
+    let route = req.extensions().get::<MatchedPath>().map(|p| p.as_str().to_string()).unwrap_or_else(|| "unmatched".into());
+    let method = req.method().to_string();
+    let start = Instant::now();
+    let res = next.run(req).await;
+    METRICS.http_request_duration
+        .with_label_values(&[&method, &route, res.status().as_str()])
+        .observe(start.elapsed().as_secs_f64());
+    res
+}
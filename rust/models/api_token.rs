@@ -0,0 +1,61 @@
+use chrono::Utc;
+use rusqlite::{params, Row};
+
+#[derive(Debug, Clone)]
+pub struct ApiTokenRow {
+    pub id: i64,
+    pub user_id: i64,
+    pub name: String,
+    pub token_hash: String,
+    pub scopes: String,
+    pub created_at: String,
+    pub last_used_at: Option<String>,
+}
+
+fn map_row(row: &Row) -> rusqlite::Result<ApiTokenRow> {
+    Ok(ApiTokenRow {
+        id: row.get(0)?,
+        user_id: row.get(1)?,
+        name: row.get(2)?,
+        token_hash: row.get(3)?,
+        scopes: row.get(4)?,
+        created_at: row.get(5)?,
+        last_used_at: row.get(6)?,
+    })
+}
+
+pub fn find(conn: &rusqlite::Connection, id: i64) -> Option<ApiTokenRow> {
+    conn.prepare("SELECT * FROM api_tokens WHERE ID = ?")
+        .ok()
+        .and_then(|mut st| st.query_row(params![id], map_row).ok())
+}
+
+pub fn find_by_hash(conn: &rusqlite::Connection, token_hash: &str) -> Option<ApiTokenRow> {
+    conn.prepare("SELECT * FROM api_tokens WHERE token_hash = ?")
+        .ok()
+        .and_then(|mut st| st.query_row(params![token_hash], map_row).ok())
+}
+
+pub fn for_user(conn: &rusqlite::Connection, user_id: i64) -> Vec<ApiTokenRow> {
+    let mut st = match conn.prepare("SELECT * FROM api_tokens WHERE user_id = ? ORDER BY ID") { Ok(s) => s, Err(_) => return vec![] };
+    let rows = st.query_map(params![user_id], map_row).ok();
+    match rows { Some(rows) => rows.filter_map(|r| r.ok()).collect(), None => vec![] }
+}
+
+pub fn insert(conn: &rusqlite::Connection, user_id: i64, name: &str, token_hash: &str, scopes: &str) -> rusqlite::Result<i64> {
+    let now = Utc::now().to_rfc3339();
+    conn.execute(
+        "INSERT INTO api_tokens(user_id, name, token_hash, scopes, created_at, last_used_at) VALUES(?, ?, ?, ?, ?, NULL)",
+        params![user_id, name, token_hash, scopes, now],
+    )?;
+    Ok(conn.last_insert_rowid())
+}
+
+pub fn touch(conn: &rusqlite::Connection, id: i64) -> rusqlite::Result<()> {
+    conn.execute("UPDATE api_tokens SET last_used_at = ? WHERE ID = ?", params![Utc::now().to_rfc3339(), id])?;
+    Ok(())
+}
+
+pub fn delete(conn: &rusqlite::Connection, id: i64) -> rusqlite::Result<bool> {
+    Ok(conn.execute("DELETE FROM api_tokens WHERE ID = ?", params![id])? > 0)
+}
@@ -1,3 +1,4 @@
+use chrono::Utc;
 use rusqlite::{params, Row};
 
 #[allow(dead_code)]
@@ -16,6 +17,7 @@ pub struct ChannelRow {
     pub updated_at: String,
     pub is_private: i64,
     pub owner_id: i64,
+    pub mirror_reactions: i64,
 }
 
 fn map_row(row: &Row) -> rusqlite::Result<ChannelRow> {
@@ -33,6 +35,7 @@ fn map_row(row: &Row) -> rusqlite::Result<ChannelRow> {
         updated_at: row.get(10)?,
         is_private: row.get(11)?,
         owner_id: row.get(12)?,
+        mirror_reactions: row.get(13)?,
     })
 }
 
@@ -61,3 +64,33 @@ pub fn where_eq(conn: &rusqlite::Connection, column: &str, value: &str) -> Vec<C
     let rows = st.query_map([value], map_row).ok();
     match rows { Some(rows) => rows.filter_map(|r| r.ok()).collect(), None => vec![] }
 }
+
+#[allow(clippy::too_many_arguments)]
+pub fn insert(
+    conn: &rusqlite::Connection,
+    name: &str,
+    description: &str,
+    discord_server: &str,
+    discord_channel: &str,
+    irc_channel: &str,
+    irc_server_ip: &str,
+    irc_server_name: &str,
+    server_id: i64,
+    owner_id: i64,
+) -> rusqlite::Result<i64> {
+    let now = Utc::now().to_rfc3339();
+    conn.execute(
+        "INSERT INTO channels(name, description, discord_server, discord_channel, irc_channel, irc_server_ip, irc_server_name, server_id, created_at, updated_at, owner_id) VALUES(?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+        params![name, description, discord_server, discord_channel, irc_channel, irc_server_ip, irc_server_name, server_id, now, now, owner_id],
+    )?;
+    Ok(conn.last_insert_rowid())
+}
+
+pub fn update(conn: &rusqlite::Connection, row: &ChannelRow) -> rusqlite::Result<()> {
+    let now = Utc::now().to_rfc3339();
+    conn.execute(
+        "UPDATE channels SET name = ?, description = ?, discord_server = ?, discord_channel = ?, irc_channel = ?, irc_server_ip = ?, irc_server_name = ?, server_id = ?, updated_at = ?, is_private = ?, owner_id = ?, mirror_reactions = ? WHERE ID = ?",
+        params![row.name, row.description, row.discord_server, row.discord_channel, row.irc_channel, row.irc_server_ip, row.irc_server_name, row.server_id, now, row.is_private, row.owner_id, row.mirror_reactions, row.id],
+    )?;
+    Ok(())
+}
@@ -15,6 +15,24 @@ pub struct ChannelMemberRow {
     pub updated_at: String,
 }
 
+impl ChannelMemberRow {
+    /// The manually set unred marker wins over the automatic "has seen" tracking
+    /// until newer messages were fetched, see [`Self::saw_messages`].
+    pub fn last_red_msg_id(&self) -> Option<i64> {
+        self.unred_msg_id.or(self.highest_requested_msg_id)
+    }
+
+    /// Tracks the fetched range `first..=last`. Scrolling back up does not mark newer
+    /// messages as unseen again. Fetching a message newer than everything fetched before
+    /// and newer than the manual marker drops that marker.
+    pub fn saw_messages(&mut self, first: i64, last: i64) {
+        self.lowest_requested_msg_id = Some(self.lowest_requested_msg_id.map_or(first, |id| id.min(first)));
+        let advanced = self.highest_requested_msg_id.is_none_or(|id| last > id);
+        if advanced && self.unred_msg_id.is_some_and(|id| last > id) { self.unred_msg_id = None; }
+        self.highest_requested_msg_id = Some(self.highest_requested_msg_id.map_or(last, |id| id.max(last)));
+    }
+}
+
 fn map_row(row: &Row) -> rusqlite::Result<ChannelMemberRow> {
     Ok(ChannelMemberRow {
         id: row.get(0)?,
@@ -60,3 +78,16 @@ pub fn update(conn: &rusqlite::Connection, row: &ChannelMemberRow) -> rusqlite::
     )?;
     Ok(())
 }
+
+pub fn for_channel(conn: &rusqlite::Connection, channel_id: i64) -> Vec<ChannelMemberRow> {
+    let mut st = match conn.prepare("SELECT * FROM channel_members WHERE channel_id = ?") { Ok(s) => s, Err(_) => return vec![] };
+    let rows = st.query_map(params![channel_id], map_row).ok();
+    match rows { Some(rows) => rows.filter_map(|r| r.ok()).collect(), None => vec![] }
+}
+
+/// Usernames of all members of the channel
+pub fn usernames(conn: &rusqlite::Connection, channel_id: i64) -> Vec<String> {
+    let mut st = match conn.prepare("SELECT users.username FROM channel_members JOIN users ON users.ID = channel_members.user_id WHERE channel_members.channel_id = ?") { Ok(s) => s, Err(_) => return vec![] };
+    let rows = st.query_map(params![channel_id], |row| row.get(0)).ok();
+    match rows { Some(rows) => rows.filter_map(|r| r.ok()).collect(), None => vec![] }
+}
@@ -0,0 +1,38 @@
+use chrono::Utc;
+use rusqlite::{params, Row};
+
+#[allow(dead_code)]
+#[derive(Debug, Clone)]
+pub struct FriendRow {
+    pub id: i64,
+    pub user_a_id: i64,
+    pub user_b_id: i64,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+fn map_row(row: &Row) -> rusqlite::Result<FriendRow> {
+    Ok(FriendRow {
+        id: row.get(0)?,
+        user_a_id: row.get(1)?,
+        user_b_id: row.get(2)?,
+        created_at: row.get(3)?,
+        updated_at: row.get(4)?,
+    })
+}
+
+/// Friendships are stored once. `user_id` can be on either side.
+pub fn for_user(conn: &rusqlite::Connection, user_id: i64) -> Vec<FriendRow> {
+    let mut st = match conn.prepare("SELECT * FROM friends WHERE user_a_id = ? OR user_b_id = ?") { Ok(s) => s, Err(_) => return vec![] };
+    let rows = st.query_map(params![user_id, user_id], map_row).ok();
+    match rows { Some(rows) => rows.filter_map(|r| r.ok()).collect(), None => vec![] }
+}
+
+pub fn insert(conn: &rusqlite::Connection, user_a_id: i64, user_b_id: i64) -> rusqlite::Result<i64> {
+    let now = Utc::now().to_rfc3339();
+    conn.execute(
+        "INSERT INTO friends(user_a_id, user_b_id, created_at, updated_at) VALUES(?, ?, ?, ?)",
+        params![user_a_id, user_b_id, now, now],
+    )?;
+    Ok(conn.last_insert_rowid())
+}
@@ -0,0 +1,143 @@
+use chrono::{DateTime, Utc};
+use rusqlite::{params, params_from_iter, types::Value};
+
+use crate::types::IrcMessage;
+
+#[derive(Debug, Clone)]
+pub struct SearchRow {
+    pub id: i64,
+    pub channel_id: i64,
+    pub author: String,
+    pub created_at: String,
+    pub snippet: String,
+    pub rank: f64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SearchFilter {
+    pub query: String,
+    // only these channels are searched. the caller has to check permissions
+    pub channel_ids: Vec<i64>,
+    pub author: Option<String>,
+    pub after: Option<String>,
+    pub before: Option<String>,
+    pub has_link: bool,
+    pub mentions: Option<String>,
+    pub offset: i64,
+    pub limit: i64,
+}
+
+/// History dates are rfc2822 which does not sort as text
+pub fn to_index_date(date: &str) -> String {
+    DateTime::parse_from_rfc2822(date)
+        .or_else(|_| DateTime::parse_from_rfc3339(date))
+        .map(|d| d.with_timezone(&Utc))
+        .unwrap_or_else(|_| Utc::now())
+        .to_rfc3339()
+}
+
+pub fn insert(conn: &rusqlite::Connection, server_id: i64, channel_id: i64, msg: &IrcMessage) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT INTO message_search(rowid, content, author, server_id, channel_id, created_at) VALUES(?, ?, ?, ?, ?, ?)",
+        params![msg.id, msg.message, msg.from, server_id, channel_id, to_index_date(&msg.date)],
+    )?;
+    Ok(())
+}
+
+pub fn exists(conn: &rusqlite::Connection, id: i64) -> bool {
+    conn.prepare("SELECT 1 FROM message_search WHERE rowid = ?")
+        .and_then(|mut st| st.exists(params![id]))
+        .unwrap_or(false)
+}
+
+/// The highest message id that was indexed
+pub fn max_id(conn: &rusqlite::Connection) -> i64 {
+    conn.query_row("SELECT COALESCE(MAX(rowid), 0) FROM message_search", [], |row| row.get(0)).unwrap_or(0)
+}
+
+pub fn update_content(conn: &rusqlite::Connection, id: i64, content: &str) -> rusqlite::Result<()> {
+    conn.execute("UPDATE message_search SET content = ? WHERE rowid = ?", params![content, id])?;
+    Ok(())
+}
+
+pub fn delete(conn: &rusqlite::Connection, id: i64) -> rusqlite::Result<()> {
+    conn.execute("DELETE FROM message_search WHERE rowid = ?", params![id])?;
+    Ok(())
+}
+
+/// Turns user input into a fts5 query where every word has to match.
+/// All fts5 syntax is escaped except a trailing * for prefix search.
+pub fn to_match_expr(input: &str) -> String {
+    input.split_whitespace()
+        .filter_map(|word| {
+            let (word, prefix) = match word.strip_suffix('*') { Some(w) => (w, "*"), None => (word, "") };
+            if word.is_empty() { return None; }
+            Some(format!("\"{}\"{}", word.replace('"', "\"\""), prefix))
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn where_clause(filter: &SearchFilter, args: &mut Vec<Value>) -> String {
+    let mut match_expr = to_match_expr(&filter.query);
+    if let Some(name) = &filter.mentions {
+        if !match_expr.is_empty() { match_expr.push(' '); }
+        match_expr.push_str(&format!("content : \"{}\"", name.replace('"', "\"\"")));
+    }
+    let mut sql = String::from("1");
+    if !match_expr.is_empty() {
+        sql.push_str(" AND message_search MATCH ?");
+        args.push(Value::Text(match_expr));
+    }
+    sql.push_str(&format!(" AND channel_id IN ({})", vec!["?"; filter.channel_ids.len()].join(", ")));
+    args.extend(filter.channel_ids.iter().map(|id| Value::Integer(*id)));
+    if let Some(author) = &filter.author {
+        sql.push_str(" AND author = ?");
+        args.push(Value::Text(author.clone()));
+    }
+    if let Some(after) = &filter.after {
+        sql.push_str(" AND created_at >= ?");
+        args.push(Value::Text(after.clone()));
+    }
+    if let Some(before) = &filter.before {
+        sql.push_str(" AND created_at < ?");
+        args.push(Value::Text(before.clone()));
+    }
+    if filter.has_link {
+        sql.push_str(" AND (content LIKE '%http://%' OR content LIKE '%https://%')");
+    }
+    sql
+}
+
+/// Returns one page of results ordered by relevance and the total amount of matches.
+pub fn search(conn: &rusqlite::Connection, filter: &SearchFilter) -> rusqlite::Result<(Vec<SearchRow>, i64)> {
+    if filter.channel_ids.is_empty() { return Ok((vec![], 0)); }
+    let mut args = Vec::new();
+    let where_sql = where_clause(filter, &mut args);
+
+    let total: i64 = conn.query_row(
+        &format!("SELECT COUNT(*) FROM message_search WHERE {}", where_sql),
+        params_from_iter(args.iter()),
+        |row| row.get(0),
+    )?;
+
+    let sql = format!(
+        "SELECT rowid, channel_id, author, created_at, snippet(message_search, 0, '<mark>', '</mark>', '…', 16), IFNULL(rank, 0) \
+         FROM message_search WHERE {} ORDER BY rank, rowid DESC LIMIT ? OFFSET ?",
+        where_sql
+    );
+    args.push(Value::Integer(filter.limit));
+    args.push(Value::Integer(filter.offset));
+    let mut st = conn.prepare(&sql)?;
+    let rows = st.query_map(params_from_iter(args.iter()), |row| {
+        Ok(SearchRow {
+            id: row.get(0)?,
+            channel_id: row.get(1)?,
+            author: row.get(2)?,
+            created_at: row.get(3)?,
+            snippet: row.get(4)?,
+            rank: row.get(5)?,
+        })
+    })?;
+    Ok((rows.filter_map(|r| r.ok()).collect(), total))
+}
@@ -3,4 +3,10 @@ pub mod server;
 pub mod channel;
 pub mod webhook;
 pub mod channel_member;
+pub mod friend;
 
+pub mod reaction;
+pub mod message_search;
+pub mod api_token;
+pub mod subscription;
+pub mod subscription_delivery;
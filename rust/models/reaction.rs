@@ -0,0 +1,50 @@
+use std::collections::HashMap;
+
+use chrono::Utc;
+use rusqlite::{params, params_from_iter};
+
+use crate::types::ReactionCount;
+
+pub fn insert(conn: &rusqlite::Connection, channel_id: i64, message_id: i64, user_id: i64, emoji: &str) -> rusqlite::Result<bool> {
+    let now = Utc::now().to_rfc3339();
+    let changed = conn.execute(
+        "INSERT OR IGNORE INTO reactions(channel_id, message_id, user_id, emoji, created_at) VALUES(?, ?, ?, ?, ?)",
+        params![channel_id, message_id, user_id, emoji, now],
+    )?;
+    Ok(changed > 0)
+}
+
+pub fn delete(conn: &rusqlite::Connection, channel_id: i64, message_id: i64, user_id: i64, emoji: &str) -> rusqlite::Result<bool> {
+    let changed = conn.execute(
+        "DELETE FROM reactions WHERE channel_id = ? AND message_id = ? AND user_id = ? AND emoji = ?",
+        params![channel_id, message_id, user_id, emoji],
+    )?;
+    Ok(changed > 0)
+}
+
+/// Highest message id with a reaction, 0 without any
+pub fn max_message_id(conn: &rusqlite::Connection) -> i64 {
+    conn.query_row("SELECT COALESCE(MAX(message_id), 0) FROM reactions", [], |row| row.get(0)).unwrap_or(0)
+}
+
+/// Aggregated reactions per message id of the channel. `me` is set if `user_id` is one of the reactors.
+pub fn counts_for_messages(conn: &rusqlite::Connection, channel_id: i64, message_ids: &[i64], user_id: Option<i64>) -> HashMap<i64, Vec<ReactionCount>> {
+    let mut out: HashMap<i64, Vec<ReactionCount>> = HashMap::new();
+    if message_ids.is_empty() { return out; }
+    let placeholders = vec!["?"; message_ids.len()].join(", ");
+    let sql = format!(
+        "SELECT message_id, emoji, COUNT(*), SUM(user_id = ?) FROM reactions WHERE channel_id = ? AND message_id IN ({}) GROUP BY message_id, emoji ORDER BY MIN(ID)",
+        placeholders
+    );
+    let mut st = match conn.prepare(&sql) { Ok(s) => s, Err(_) => return out };
+    let args = [user_id.unwrap_or(0), channel_id].into_iter().chain(message_ids.iter().copied());
+    let rows = st.query_map(params_from_iter(args), |row| {
+        Ok((row.get::<_, i64>(0)?, ReactionCount { emoji: row.get(1)?, count: row.get(2)?, me: row.get::<_, i64>(3)? > 0 }))
+    }).ok();
+    if let Some(rows) = rows {
+        for (message_id, count) in rows.filter_map(|r| r.ok()) {
+            out.entry(message_id).or_default().push(count);
+        }
+    }
+    out
+}
@@ -1,3 +1,4 @@
+use chrono::Utc;
 use rusqlite::{params, Row};
 
 #[allow(dead_code)]
@@ -43,3 +44,31 @@ pub fn all(conn: &rusqlite::Connection) -> Vec<ServerRow> {
     let iter = st.query_map([], map_row).ok();
     match iter { Some(rows) => rows.filter_map(|r| r.ok()).collect(), None => vec![] }
 }
+
+#[allow(clippy::too_many_arguments)]
+pub fn insert(
+    conn: &rusqlite::Connection,
+    name: &str,
+    discord_name: &str,
+    irc_name: &str,
+    irc_ip: &str,
+    icon_url: &str,
+    register_ip: &str,
+    owner_id: i64,
+) -> rusqlite::Result<i64> {
+    let now = Utc::now().to_rfc3339();
+    conn.execute(
+        "INSERT INTO servers(name, discord_name, irc_name, irc_ip, icon_url, register_ip, owner_id, created_at, updated_at) VALUES(?, ?, ?, ?, ?, ?, ?, ?, ?)",
+        params![name, discord_name, irc_name, irc_ip, icon_url, register_ip, owner_id, now, now],
+    )?;
+    Ok(conn.last_insert_rowid())
+}
+
+pub fn update(conn: &rusqlite::Connection, row: &ServerRow) -> rusqlite::Result<()> {
+    let now = Utc::now().to_rfc3339();
+    conn.execute(
+        "UPDATE servers SET name = ?, discord_name = ?, irc_name = ?, irc_ip = ?, icon_url = ?, banner_url = ?, owner_id = ?, updated_at = ? WHERE ID = ?",
+        params![row.name, row.discord_name, row.irc_name, row.irc_ip, row.icon_url, row.banner_url, row.owner_id, now, row.id],
+    )?;
+    Ok(())
+}
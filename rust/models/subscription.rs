@@ -0,0 +1,50 @@
+use chrono::Utc;
+use rusqlite::{params, Row};
+
+#[derive(Debug, Clone)]
+pub struct SubscriptionRow {
+    pub id: i64,
+    pub url: String,
+    pub secret: String,
+    pub events: String,
+    pub channel_id: Option<i64>,
+    pub created_at: String,
+}
+
+fn map_row(row: &Row) -> rusqlite::Result<SubscriptionRow> {
+    Ok(SubscriptionRow {
+        id: row.get(0)?,
+        url: row.get(1)?,
+        secret: row.get(2)?,
+        events: row.get(3)?,
+        channel_id: row.get(4)?,
+        created_at: row.get(5)?,
+    })
+}
+
+pub fn find(conn: &rusqlite::Connection, id: i64) -> Option<SubscriptionRow> {
+    conn.prepare("SELECT * FROM subscriptions WHERE ID = ?")
+        .ok()
+        .and_then(|mut st| st.query_row(params![id], map_row).ok())
+}
+
+pub fn all(conn: &rusqlite::Connection) -> Vec<SubscriptionRow> {
+    let mut st = match conn.prepare("SELECT * FROM subscriptions ORDER BY ID") { Ok(s) => s, Err(_) => return vec![] };
+    let rows = st.query_map([], map_row).ok();
+    match rows { Some(rows) => rows.filter_map(|r| r.ok()).collect(), None => vec![] }
+}
+
+pub fn insert(conn: &rusqlite::Connection, url: &str, secret: &str, events: &str, channel_id: Option<i64>) -> rusqlite::Result<i64> {
+    let now = Utc::now().to_rfc3339();
+    conn.execute(
+        "INSERT INTO subscriptions(url, secret, events, channel_id, created_at) VALUES(?, ?, ?, ?, ?)",
+        params![url, secret, events, channel_id, now],
+    )?;
+    Ok(conn.last_insert_rowid())
+}
+
+/// Also removes the delivery log of the subscription
+pub fn delete(conn: &rusqlite::Connection, id: i64) -> rusqlite::Result<bool> {
+    conn.execute("DELETE FROM subscription_deliveries WHERE subscription_id = ?", params![id])?;
+    Ok(conn.execute("DELETE FROM subscriptions WHERE ID = ?", params![id])? > 0)
+}
@@ -0,0 +1,63 @@
+use chrono::Utc;
+use rusqlite::{params, Row};
+
+#[derive(Debug, Clone)]
+pub struct DeliveryRow {
+    pub id: i64,
+    pub subscription_id: i64,
+    pub delivery_id: String,
+    pub event: String,
+    pub attempt: i64,
+    pub status_code: Option<i64>,
+    pub error: Option<String>,
+    pub success: i64,
+    pub created_at: String,
+}
+
+fn map_row(row: &Row) -> rusqlite::Result<DeliveryRow> {
+    Ok(DeliveryRow {
+        id: row.get(0)?,
+        subscription_id: row.get(1)?,
+        delivery_id: row.get(2)?,
+        event: row.get(3)?,
+        attempt: row.get(4)?,
+        status_code: row.get(5)?,
+        error: row.get(6)?,
+        success: row.get(7)?,
+        created_at: row.get(8)?,
+    })
+}
+
+/// Newest first. Only attempts with an id below `before` if set.
+pub fn for_subscription(conn: &rusqlite::Connection, subscription_id: i64, before: Option<i64>, limit: i64) -> Vec<DeliveryRow> {
+    let mut st = match conn.prepare("SELECT * FROM subscription_deliveries WHERE subscription_id = ? AND ID < ? ORDER BY ID DESC LIMIT ?") { Ok(s) => s, Err(_) => return vec![] };
+    let rows = st.query_map(params![subscription_id, before.unwrap_or(i64::MAX), limit], map_row).ok();
+    match rows { Some(rows) => rows.filter_map(|r| r.ok()).collect(), None => vec![] }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn insert(
+    conn: &rusqlite::Connection,
+    subscription_id: i64,
+    delivery_id: &str,
+    event: &str,
+    attempt: i64,
+    status_code: Option<i64>,
+    error: Option<&str>,
+    success: bool,
+) -> rusqlite::Result<i64> {
+    let now = Utc::now().to_rfc3339();
+    conn.execute(
+        "INSERT INTO subscription_deliveries(subscription_id, delivery_id, event, attempt, status_code, error, success, created_at) VALUES(?, ?, ?, ?, ?, ?, ?, ?)",
+        params![subscription_id, delivery_id, event, attempt, status_code, error, success as i64, now],
+    )?;
+    Ok(conn.last_insert_rowid())
+}
+
+/// Drops all but the newest `keep` attempts of the subscription
+pub fn prune(conn: &rusqlite::Connection, subscription_id: i64, keep: i64) -> rusqlite::Result<usize> {
+    conn.execute(
+        "DELETE FROM subscription_deliveries WHERE subscription_id = ? AND ID NOT IN (SELECT ID FROM subscription_deliveries WHERE subscription_id = ? ORDER BY ID DESC LIMIT ?)",
+        params![subscription_id, subscription_id, keep],
+    )
+}
@@ -16,12 +16,14 @@ pub struct UserRow {
     pub is_admin: i64,
     pub is_blocked: i64,
     pub token: Option<String>,
+    pub is_bot: i64,
 }
 
 #[allow(dead_code)]
 impl UserRow {
     pub fn blocked(&self) -> bool { self.is_blocked == 1 }
     pub fn admin(&self) -> bool { self.is_admin == 1 }
+    pub fn bot(&self) -> bool { self.is_bot == 1 }
 }
 
 fn map_row(row: &Row) -> rusqlite::Result<UserRow> {
@@ -36,6 +38,7 @@ fn map_row(row: &Row) -> rusqlite::Result<UserRow> {
         is_admin: row.get(7)?,
         is_blocked: row.get(8)?,
         token: row.get(9).ok(),
+        is_bot: row.get(10).unwrap_or(0),
     })
 }
 
@@ -52,6 +55,12 @@ pub fn find(conn: &rusqlite::Connection, id: i64) -> Option<UserRow> {
         .and_then(|mut st| st.query_row(params![id], map_row).ok())
 }
 
+pub fn find_by_username(conn: &rusqlite::Connection, username: &str) -> Option<UserRow> {
+    conn.prepare("SELECT * FROM users WHERE username = ?")
+        .ok()
+        .and_then(|mut st| st.query_row(params![username], map_row).ok())
+}
+
 pub fn find_by_credentials(conn: &rusqlite::Connection, username: &str, password: &str) -> Option<UserRow> {
     conn.prepare("SELECT * FROM users WHERE username = ? AND password = ?")
         .ok()
@@ -78,8 +87,8 @@ pub fn insert(conn: &rusqlite::Connection, username: &str, password: &str, regis
 pub fn update(conn: &rusqlite::Connection, row: &UserRow) -> Result<()> {
     let now = Utc::now().to_rfc3339();
     conn.execute(
-        "UPDATE users SET username = ?, password = ?, is_admin = ?, is_blocked = ?, updated_at = ?, token = ? WHERE ID = ?",
-        params![row.username, row.password, row.is_admin, row.is_blocked, now, row.token, row.id],
+        "UPDATE users SET username = ?, password = ?, is_admin = ?, is_blocked = ?, updated_at = ?, token = ?, is_bot = ? WHERE ID = ?",
+        params![row.username, row.password, row.is_admin, row.is_blocked, now, row.token, row.is_bot, row.id],
     )?;
     Ok(())
 }
@@ -33,6 +33,18 @@ fn map_row(row: &Row) -> rusqlite::Result<WebhookRow> {
 
 // find_by_credentials no longer used in the Rust port
 
+pub fn find(conn: &rusqlite::Connection, id: i64) -> Option<WebhookRow> {
+    conn.prepare("SELECT * FROM webhooks WHERE ID = ?")
+        .ok()
+        .and_then(|mut st| st.query_row(params![id], map_row).ok())
+}
+
+pub fn all(conn: &rusqlite::Connection) -> Vec<WebhookRow> {
+    let mut st = match conn.prepare("SELECT * FROM webhooks") { Ok(s) => s, Err(_) => return vec![] };
+    let rows = st.query_map([], map_row).ok();
+    match rows { Some(rows) => rows.filter_map(|r| r.ok()).collect(), None => vec![] }
+}
+
 pub fn where_eq(conn: &rusqlite::Connection, column: &str, value: i64) -> Vec<WebhookRow> {
     if !column.chars().all(|c| c.is_ascii_lowercase() || c == '_') { return vec![]; }
     let sql = format!("SELECT * FROM webhooks WHERE {} = ?", column);
@@ -41,6 +53,7 @@ pub fn where_eq(conn: &rusqlite::Connection, column: &str, value: i64) -> Vec<We
     match rows { Some(rows) => rows.filter_map(|r| r.ok()).collect(), None => vec![] }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn insert(
     conn: &rusqlite::Connection,
     name: &str,
@@ -58,3 +71,12 @@ pub fn insert(
     )?;
     Ok(conn.last_insert_rowid())
 }
+
+pub fn update(conn: &rusqlite::Connection, row: &WebhookRow) -> rusqlite::Result<()> {
+    let now = Utc::now().to_rfc3339();
+    conn.execute(
+        "UPDATE webhooks SET name = ?, token = ?, server_id = ?, channel_id = ?, last_use_ip = ?, updated_at = ?, owner_id = ? WHERE ID = ?",
+        params![row.name, row.token, row.server_id, row.channel_id, row.last_use_ip, now, row.owner_id, row.id],
+    )?;
+    Ok(())
+}
@@ -0,0 +1,110 @@
+//! Plain websocket endpoint on /ws for bots and clients without socket.io.
+//! The protocol is described in docs/websocket-protocol.md
+
+use axum::{
+    extract::{ws::{CloseFrame, Message, WebSocket, WebSocketUpgrade}, State},
+    response::Response,
+};
+use futures::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::{sync::mpsc, time::{timeout, Duration}};
+use tracing::{info, warn};
+
+use crate::{ack::{Ack, ErrorCode, EventError}, client::{envelope, Client, PlainSender}, state::AppState, ws};
+
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(30);
+// close code sent when no frame arrived for two heartbeat intervals
+const CLOSE_HEARTBEAT_TIMEOUT: u16 = 4000;
+
+/// Client to server frame
+#[derive(Debug, Deserialize)]
+struct Frame {
+    op: String,
+    #[serde(default)]
+    d: Value,
+    #[serde(default)]
+    seq: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+struct Hello {
+    #[serde(rename = "sessionId")]
+    session_id: String,
+    #[serde(rename = "heartbeatInterval")]
+    heartbeat_interval: u64,
+}
+
+pub async fn upgrade(ws: WebSocketUpgrade, State(state): State<AppState>) -> Response {
+    ws.on_upgrade(move |socket| run(socket, state))
+}
+
+fn reply<T: Serialize + ?Sized>(tx: &PlainSender, op: &str, d: &T, seq: Option<u64>) {
+    let _ = tx.send(envelope(op, d, seq));
+}
+
+fn invalid_frame(tx: &PlainSender, message: String) {
+    reply(tx, "error", &EventError::new(ErrorCode::InvalidFrame, message), None);
+}
+
+async fn run(socket: WebSocket, state: AppState) {
+    let id = format!("ws-{}", uuid::Uuid::new_v4());
+    let (mut sink, mut stream) = socket.split();
+    let (tx, mut rx) = mpsc::unbounded_channel::<Message>();
+    let writer = tokio::spawn(async move {
+        while let Some(msg) = rx.recv().await {
+            let close = matches!(msg, Message::Close(_));
+            if sink.send(msg).await.is_err() || close { break; }
+        }
+        // also flushes the reply to a close frame of the client
+        let _ = sink.close().await;
+    });
+
+    state.plain_clients.insert(id.clone(), tx.clone());
+    let client = Client::Plain { id: id.clone(), tx: tx.clone() };
+    ws::on_connect(&client, &state);
+    reply(&tx, "hello", &Hello { session_id: id.clone(), heartbeat_interval: HEARTBEAT_INTERVAL.as_millis() as u64 }, None);
+
+    loop {
+        let msg = match timeout(HEARTBEAT_INTERVAL * 2, stream.next()).await {
+            Err(_) => {
+                info!("[*] plain websocket {} missed its heartbeat", id);
+                let _ = tx.send(Message::Close(Some(CloseFrame { code: CLOSE_HEARTBEAT_TIMEOUT, reason: "heartbeat timeout".into() })));
+                break;
+            }
+            Ok(None) | Ok(Some(Err(_))) => break,
+            Ok(Some(Ok(msg))) => msg,
+        };
+        match msg {
+            Message::Text(text) => on_frame(&client, &state, &tx, &text).await,
+            Message::Binary(_) => invalid_frame(&tx, "only text frames are supported".into()),
+            Message::Close(_) => break,
+            // pings are answered by axum
+            Message::Ping(_) | Message::Pong(_) => {}
+        }
+    }
+
+    state.plain_clients.remove(&id);
+    ws::on_disconnect(&client, &state).await;
+    drop(client);
+    drop(tx);
+    let _ = writer.await;
+}
+
+async fn on_frame(client: &Client, state: &AppState, tx: &PlainSender, text: &str) {
+    let frame: Frame = match serde_json::from_str(text) {
+        Ok(frame) => frame,
+        Err(e) => return invalid_frame(tx, format!("invalid frame: {e}")),
+    };
+    if frame.op == "heartbeat" {
+        return reply(tx, "heartbeatAck", &Value::Null, frame.seq);
+    }
+    let res = ws::dispatch(client.clone(), state.clone(), &frame.op, frame.d).await;
+    if let Err(e) = &res { warn!("[!] plain websocket {} {}: {e}", client.id(), frame.op); }
+    match (res, frame.seq) {
+        // frames with a seq always get the result
+        (res, Some(_)) => reply(tx, "ack", &Ack::from(res), frame.seq),
+        (Err(e), None) => reply(tx, "error", &e, None),
+        (Ok(_), None) => {}
+    }
+}
@@ -0,0 +1,50 @@
+//! `!roll 2d6` rolls dice for irc and web users.
+//! Options: `max_dice` (default 10)
+
+use futures::future::BoxFuture;
+use rand::Rng;
+
+use super::{MessageHandler, PluginContext};
+use crate::types::IrcMessage;
+
+const MAX_SIDES: u32 = 1000;
+
+pub struct Dice;
+
+/// `NdM`, `dM` or nothing for one six sided die
+fn parse(spec: &str, max_dice: u32) -> Result<(u32, u32), String> {
+    if spec.is_empty() { return Ok((1, 6)); }
+    let Some((count, sides)) = spec.split_once(['d', 'D']) else { return Err(format!("'{spec}' is not like 2d6")); };
+    let count = if count.is_empty() { 1 } else { count.parse().map_err(|_| format!("'{count}' is not a number"))? };
+    let sides: u32 = sides.parse().map_err(|_| format!("'{sides}' is not a number"))?;
+    if count == 0 || count > max_dice { return Err(format!("you can roll 1 to {max_dice} dice")); }
+    if !(2..=MAX_SIDES).contains(&sides) { return Err(format!("dice have 2 to {MAX_SIDES} sides")); }
+    Ok((count, sides))
+}
+
+impl MessageHandler for Dice {
+    fn name(&self) -> &'static str { "dice" }
+
+    fn on_message<'a>(&'a self, ctx: &'a PluginContext, msg: &'a IrcMessage) -> BoxFuture<'a, anyhow::Result<()>> {
+        Box::pin(async move {
+            let Some(spec) = msg.message.strip_prefix("!roll") else { return Ok(()); };
+            if !spec.is_empty() && !spec.starts_with(' ') { return Ok(()); }
+            let max_dice = ctx.option("max_dice").and_then(|m| m.parse().ok()).unwrap_or(10);
+            let text = match parse(spec.trim(), max_dice) {
+                Ok((count, sides)) => {
+                    let rolls: Vec<u32> = {
+                        let mut rng = rand::thread_rng();
+                        (0..count).map(|_| rng.gen_range(1..=sides)).collect()
+                    };
+                    let total: u32 = rolls.iter().sum();
+                    let rolls: Vec<String> = rolls.iter().map(u32::to_string).collect();
+                    if count == 1 { format!("{} rolled {}", msg.from, total) }
+                    else { format!("{} rolled {}d{}: {} = {}", msg.from, count, sides, rolls.join(" + "), total) }
+                }
+                Err(e) => format!("{}: {}", msg.from, e),
+            };
+            ctx.reply(&text).await?;
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,198 @@
+//! In-process bots. A [`MessageHandler`] sees every message relayed between irc and
+//! the web in the channels its `[[plugins]]` entry enables it for. It can veto or
+//! rewrite a message before it is relayed and reply or react once it was stored.
+//! Errors, panics and timeouts of a plugin are logged and the message passes unchanged.
+
+mod dice;
+
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
+
+use futures::future::BoxFuture;
+use parking_lot::RwLock;
+use tracing::{info, warn};
+
+use crate::{
+    ack::{ErrorCode, EventError},
+    config::PluginConfig,
+    irc_bridge::ChannelMapping,
+    messages, metrics,
+    models::user::{self as user_model, UserRow},
+    reactions,
+    state::AppState,
+    types::IrcMessage,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    IrcToWeb,
+    WebToIrc,
+}
+
+/// What [`MessageHandler::filter`] decides
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    Pass,
+    // replaces the text of the message
+    Rewrite(String),
+    // drops the message. web senders get the reason as `vetoed` error
+    Veto(String),
+}
+
+/// What a plugin is run with
+pub struct PluginContext {
+    pub state: AppState,
+    pub mapping: ChannelMapping,
+    pub direction: Direction,
+    config: PluginConfig,
+}
+
+impl PluginContext {
+    pub fn option(&self, key: &str) -> Option<&str> {
+        self.config.options.get(key).map(String::as_str)
+    }
+
+    pub fn options(&self) -> &BTreeMap<String, String> {
+        &self.config.options
+    }
+
+    /// The configured account of the plugin
+    pub fn account(&self) -> Option<UserRow> {
+        user_model::find_by_username(&self.state.db.lock(), self.config.account.as_deref()?)
+    }
+
+    /// Posts `text` to the channel on irc and the web. Plugins do not see replies.
+    pub async fn reply(&self, text: &str) -> Result<IrcMessage, EventError> {
+        let account = self.account();
+        let from = account.as_ref().map_or(self.config.name.as_str(), |a| a.username.as_str());
+        messages::post(&self.state, &self.mapping, account.as_ref(), from, text).await
+    }
+
+    /// Adds `emoji` to a message of the channel. Needs an account.
+    pub async fn react(&self, message_id: i64, emoji: &str) -> Result<(), EventError> {
+        let Some(account) = self.account() else {
+            return Err(EventError::new(ErrorCode::InvalidInput, format!("plugin '{}' has no account to react with", self.config.name)));
+        };
+        reactions::set_reaction(&self.state, &account, &self.mapping.discord_server, &self.mapping.discord_channel, message_id, emoji, true).await?;
+        Ok(())
+    }
+}
+
+/// A plugin. Register it with [`PluginRegistry::register`] and enable it with a `[[plugins]]` entry.
+pub trait MessageHandler: Send + Sync {
+    /// Matches the `name` of the config entry
+    fn name(&self) -> &'static str;
+
+    /// Runs before the message is stored and relayed. Plugins run in config order
+    /// and each sees the rewrite of the one before.
+    fn filter<'a>(&'a self, _ctx: &'a PluginContext, _msg: &'a IrcMessage) -> BoxFuture<'a, anyhow::Result<Verdict>> {
+        Box::pin(async { Ok(Verdict::Pass) })
+    }
+
+    /// Runs in the background once the message was stored and relayed
+    fn on_message<'a>(&'a self, _ctx: &'a PluginContext, _msg: &'a IrcMessage) -> BoxFuture<'a, anyhow::Result<()>> {
+        Box::pin(async { Ok(()) })
+    }
+}
+
+/// The plugins compiled in. Only the ones in the config run.
+#[derive(Default)]
+pub struct PluginRegistry {
+    handlers: RwLock<Vec<Arc<dyn MessageHandler>>>,
+}
+
+impl PluginRegistry {
+    pub fn with_builtins() -> Self {
+        let registry = Self::default();
+        registry.register(dice::Dice);
+        registry
+    }
+
+    /// Replaces a plugin with the same name
+    pub fn register(&self, handler: impl MessageHandler + 'static) {
+        let mut handlers = self.handlers.write();
+        handlers.retain(|h| h.name() != handler.name());
+        handlers.push(Arc::new(handler));
+    }
+
+    pub fn find(&self, name: &str) -> Option<Arc<dyn MessageHandler>> {
+        self.handlers.read().iter().find(|h| h.name() == name).cloned()
+    }
+}
+
+/// Warns about config entries without a registered plugin
+pub fn check_config(state: &AppState) {
+    for plugin in state.config.lock().plugins.iter() {
+        if state.plugins.find(&plugin.name).is_none() {
+            warn!("[!][plugins] '{}' is configured but there is no such plugin", plugin.name);
+        }
+    }
+}
+
+/// The plugins enabled in the channel of `mapping` in config order
+fn enabled(state: &AppState, mapping: &ChannelMapping) -> Vec<(Arc<dyn MessageHandler>, PluginConfig)> {
+    state.config.lock().plugins.iter()
+        .filter(|p| p.is_enabled_in(&mapping.discord_server, &mapping.discord_channel))
+        .filter_map(|p| Some((state.plugins.find(&p.name)?, p.clone())))
+        .collect()
+}
+
+fn failed(plugin: &str, reason: &str, details: impl std::fmt::Display) {
+    warn!("[!][plugins] '{}' failed ({}): {}", plugin, reason, details);
+    metrics::METRICS.plugin_failures.with_label_values(&[plugin, reason]).inc();
+}
+
+/// Runs `f` in its own task so a panic or a timeout only loses this plugin
+async fn isolated<T: Send + 'static>(
+    state: &AppState,
+    handler: Arc<dyn MessageHandler>,
+    ctx: PluginContext,
+    msg: IrcMessage,
+    f: impl for<'a> FnOnce(&'a dyn MessageHandler, &'a PluginContext, &'a IrcMessage) -> BoxFuture<'a, anyhow::Result<T>> + Send + 'static,
+) -> Option<T> {
+    let timeout = Duration::from_millis(state.config.lock().plugin_timeout_ms);
+    let name = handler.name();
+    let mut task = tokio::spawn(async move { f(handler.as_ref(), &ctx, &msg).await });
+    match tokio::time::timeout(timeout, &mut task).await {
+        Ok(Ok(Ok(value))) => Some(value),
+        Ok(Ok(Err(e))) => { failed(name, "error", e); None }
+        Ok(Err(e)) => { failed(name, "panic", e); None }
+        Err(_) => {
+            task.abort();
+            failed(name, "timeout", format!("took longer than {}ms", timeout.as_millis()));
+            None
+        }
+    }
+}
+
+/// Lets the plugins of the channel veto or rewrite `msg`. Returns the reason of a veto.
+pub async fn filter(state: &AppState, mapping: &ChannelMapping, direction: Direction, msg: &mut IrcMessage) -> Result<(), String> {
+    for (handler, config) in enabled(state, mapping) {
+        let name = config.name.clone();
+        let ctx = PluginContext { state: state.clone(), mapping: mapping.clone(), direction, config };
+        let verdict = isolated(state, handler, ctx, msg.clone(), |h, ctx, msg| h.filter(ctx, msg)).await;
+        match verdict {
+            None | Some(Verdict::Pass) => {}
+            // the checks of messages::send ran on the text before the rewrite
+            Some(Verdict::Rewrite(text)) => match messages::check_text(&text) {
+                Ok(()) => msg.message = text,
+                Err(e) => failed(&name, "error", format!("invalid rewrite: {e}")),
+            },
+            Some(Verdict::Veto(reason)) => {
+                info!("[*][plugins] '{}' vetoed a message of '{}' in '{}#{}': {}", name, msg.from, mapping.discord_server, mapping.discord_channel, reason);
+                return Err(reason);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Hands the stored `msg` to the plugins of the channel in the background
+pub fn notify(state: &AppState, mapping: &ChannelMapping, direction: Direction, msg: &IrcMessage) {
+    for (handler, config) in enabled(state, mapping) {
+        let ctx = PluginContext { state: state.clone(), mapping: mapping.clone(), direction, config };
+        let (state, msg) = (state.clone(), msg.clone());
+        tokio::spawn(async move {
+            isolated(&state, handler, ctx, msg, |h, ctx, msg| h.on_message(ctx, msg)).await;
+        });
+    }
+}
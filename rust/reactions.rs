@@ -0,0 +1,105 @@
+use tracing::info;
+
+use crate::{
+    ack::{ErrorCode, EventError},
+    client, history, irc_bridge,
+    models::{channel as channel_model, channel_member as cm_model, reaction as reaction_model, user::UserRow},
+    state::AppState,
+    types::ReactionEvent,
+};
+
+const MAX_EMOJI_LEN: usize = 32;
+const ZWJ: char = '\u{200D}';
+const VS16: char = '\u{FE0F}';
+const KEYCAP: char = '\u{20E3}';
+const CANCEL_TAG: char = '\u{E007F}';
+
+// Extended_Pictographic of the unicode emoji data
+const PICTOGRAPHIC: &[(u32, u32)] = &[
+    (0x00A9, 0x00A9), (0x00AE, 0x00AE), (0x203C, 0x203C), (0x2049, 0x2049), (0x2122, 0x2122), (0x2139, 0x2139),
+    (0x2194, 0x2199), (0x21A9, 0x21AA), (0x231A, 0x231B), (0x2328, 0x2328), (0x2388, 0x2388), (0x23CF, 0x23CF),
+    (0x23E9, 0x23F3), (0x23F8, 0x23FA), (0x24C2, 0x24C2), (0x25AA, 0x25AB), (0x25B6, 0x25B6), (0x25C0, 0x25C0),
+    (0x25FB, 0x25FE), (0x2600, 0x2605), (0x2607, 0x2612), (0x2614, 0x2685), (0x2690, 0x2705), (0x2708, 0x2712),
+    (0x2714, 0x2714), (0x2716, 0x2716), (0x271D, 0x271D), (0x2721, 0x2721), (0x2728, 0x2728), (0x2733, 0x2734),
+    (0x2744, 0x2744), (0x2747, 0x2747), (0x274C, 0x274C), (0x274E, 0x274E), (0x2753, 0x2755), (0x2757, 0x2757),
+    (0x2763, 0x2767), (0x2795, 0x2797), (0x27A1, 0x27A1), (0x27B0, 0x27B0), (0x27BF, 0x27BF), (0x2934, 0x2935),
+    (0x2B05, 0x2B07), (0x2B1B, 0x2B1C), (0x2B50, 0x2B50), (0x2B55, 0x2B55), (0x3030, 0x3030), (0x303D, 0x303D),
+    (0x3297, 0x3297), (0x3299, 0x3299), (0x1F000, 0x1F0FF), (0x1F10D, 0x1F10F), (0x1F12F, 0x1F12F),
+    (0x1F16C, 0x1F171), (0x1F17E, 0x1F17F), (0x1F18E, 0x1F18E), (0x1F191, 0x1F19A), (0x1F1AD, 0x1F1E5),
+    (0x1F201, 0x1F20F), (0x1F21A, 0x1F21A), (0x1F22F, 0x1F22F), (0x1F232, 0x1F23A), (0x1F23C, 0x1F23F),
+    (0x1F249, 0x1F3FA), (0x1F400, 0x1F53D), (0x1F546, 0x1F64F), (0x1F680, 0x1F6FF), (0x1F774, 0x1F77F),
+    (0x1F7D5, 0x1F7FF), (0x1F80C, 0x1F80F), (0x1F848, 0x1F84F), (0x1F85A, 0x1F85F), (0x1F888, 0x1F88F),
+    (0x1F8AE, 0x1F8FF), (0x1F90C, 0x1F93A), (0x1F93C, 0x1F945), (0x1F947, 0x1FAFF), (0x1FC00, 0x1FFFD),
+];
+
+fn is_pictographic(c: char) -> bool {
+    PICTOGRAPHIC.iter().any(|&(lo, hi)| (lo..=hi).contains(&(c as u32)))
+}
+
+fn is_regional_indicator(c: char) -> bool { ('\u{1F1E6}'..='\u{1F1FF}').contains(&c) }
+fn is_skin_tone(c: char) -> bool { ('\u{1F3FB}'..='\u{1F3FF}').contains(&c) }
+fn is_tag(c: &char) -> bool { ('\u{E0020}'..='\u{E007E}').contains(c) }
+
+/// One emoji of a zwj sequence: a flag, a keycap or a pictograph with its modifiers
+fn is_emoji_element(element: &str) -> bool {
+    let chars: Vec<char> = element.chars().collect();
+    match chars.as_slice() {
+        [a, b] if is_regional_indicator(*a) && is_regional_indicator(*b) => true,
+        ['0'..='9' | '#' | '*', rest @ ..] => matches!(rest, [KEYCAP] | [VS16, KEYCAP]),
+        [base, rest @ ..] if is_pictographic(*base) => {
+            let rest = rest.strip_prefix(&[VS16]).unwrap_or(rest);
+            match rest {
+                [] => true,
+                [tone] => is_skin_tone(*tone),
+                // subdivision flags like 🏴󠁧󠁢󠁳󠁣󠁴󠁿
+                [tags @ .., CANCEL_TAG] => !tags.is_empty() && tags.iter().all(is_tag),
+                _ => false,
+            }
+        }
+        _ => false,
+    }
+}
+
+/// Only unicode emoji are allowed. Custom emoji like :kek: are not supported yet.
+pub fn is_valid_emoji(emoji: &str) -> bool {
+    !emoji.is_empty() && emoji.len() <= MAX_EMOJI_LEN && emoji.split(ZWJ).all(is_emoji_element)
+}
+
+/// Adds or removes the reaction of `user` and broadcasts the change.
+/// Returns None if the reaction was already in the requested state.
+pub async fn set_reaction(state: &AppState, user: &UserRow, server: &str, channel: &str, message_id: i64, emoji: &str, add: bool) -> Result<Option<ReactionEvent>, EventError> {
+    if !is_valid_emoji(emoji) { return Err(EventError::new(ErrorCode::InvalidInput, "invalid emoji")); }
+    let (ch, msg, changed) = {
+        let conn = state.db.lock();
+        let Some(ch) = channel_model::find_by_discord(&conn, server, channel) else { return Err(EventError::new(ErrorCode::ChannelNotFound, "channel not found")); };
+        if ch.is_private == 1 && cm_model::find_by_user_and_channel(&conn, user.id, ch.id).is_none() {
+            return Err(EventError::new(ErrorCode::PrivateChannel, "you are not a member of this private channel"));
+        }
+        let Some(msg) = state.history.find_message(server, channel, message_id).filter(|m| m.deleted_at.is_none()) else {
+            return Err(EventError::new(ErrorCode::MessageNotFound, "message not found"));
+        };
+        let res = if add {
+            reaction_model::insert(&conn, ch.id, message_id, user.id, emoji)
+        } else {
+            reaction_model::delete(&conn, ch.id, message_id, user.id, emoji)
+        };
+        let changed = res.map_err(|e| EventError::new(ErrorCode::Internal, format!("failed to save reaction: {e}")))?;
+        (ch, msg, changed)
+    };
+    if !changed { return Ok(None); }
+    info!("[*][{}][{}] '{}' {} reaction {} on msgid={}", server, channel, user.username, if add { "added" } else { "removed" }, emoji, message_id);
+
+    if add && ch.mirror_reactions == 1 {
+        let line = format!("* {} reacted {} to \"{}\"", user.username, emoji, history::irc_quote(&msg.message));
+        irc_bridge::send_irc(state, &ch.irc_server_name, &ch.irc_channel, &line).await;
+    }
+    let event = ReactionEvent {
+        id: message_id,
+        channel: ch.discord_channel,
+        server: ch.discord_server,
+        emoji: emoji.to_string(),
+        username: user.username.clone(),
+    };
+    client::emit_all(state, if add { "reactionAdd" } else { "reactionRemove" }, &event).await;
+    Ok(Some(event))
+}
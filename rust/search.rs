@@ -0,0 +1,35 @@
+use tracing::{info, warn};
+
+use crate::{
+    models::{channel as channel_model, channel::ChannelRow, message_search},
+    state::AppState,
+    types::IrcMessage,
+};
+
+pub fn index_message(conn: &rusqlite::Connection, ch: &ChannelRow, msg: &IrcMessage) {
+    if let Err(e) = message_search::insert(conn, ch.server_id, ch.id, msg) {
+        warn!("[!] failed to index msgid={} for search: {e}", msg.id);
+    }
+}
+
+pub fn update_message(conn: &rusqlite::Connection, msg: &IrcMessage) {
+    let res = if msg.deleted_at.is_some() {
+        message_search::delete(conn, msg.id)
+    } else {
+        message_search::update_content(conn, msg.id, &msg.message)
+    };
+    if let Err(e) = res { warn!("[!] failed to update search index for msgid={}: {e}", msg.id); }
+}
+
+/// Indexes messages from message_log.json that were logged before search existed
+pub fn backfill(state: &AppState) {
+    let conn = state.db.lock();
+    let mut indexed = 0;
+    for msg in state.history.all_messages() {
+        if msg.deleted_at.is_some() || message_search::exists(&conn, msg.id) { continue; }
+        let Some(ch) = channel_model::find_by_discord(&conn, &msg.server, &msg.channel) else { continue; };
+        index_message(&conn, &ch, &msg);
+        indexed += 1;
+    }
+    if indexed > 0 { info!("[*] indexed {} messages for search", indexed); }
+}
@@ -0,0 +1,149 @@
+//! Sample data for development databases. Port of src/db/seed.ts
+//!
+//! The users and servers the seed creates are recorded in `seed_rows` so they
+//! can be removed and recreated without touching anything else, even rows that
+//! happen to have the same names.
+
+use anyhow::{bail, Context, Result};
+use rusqlite::{params, Connection};
+
+use crate::models::{channel, channel_member, friend, server, user, webhook};
+
+const IRC_IP: &str = "stockholm.se.quakenet.org";
+const IRC_NAME: &str = "quakenet";
+const LOCALHOST: &str = "127.0.0.1";
+
+const SEED_USER_IDS: &str = "SELECT row_id FROM seed_rows WHERE table_name = 'users'";
+const SEED_SERVER_IDS: &str = "SELECT row_id FROM seed_rows WHERE table_name = 'servers'";
+
+#[derive(Debug, Default)]
+pub struct SeedSummary {
+    pub users: usize,
+    pub servers: usize,
+    pub channels: usize,
+    pub webhooks: usize,
+    pub channel_members: usize,
+    pub friends: usize,
+}
+
+fn count(conn: &Connection, sql: &str) -> Result<i64> {
+    Ok(conn.query_row(sql, [], |row| row.get(0))?)
+}
+
+fn mark(conn: &Connection, table: &str, id: i64) -> Result<i64> {
+    conn.execute("INSERT OR IGNORE INTO seed_rows(table_name, row_id) VALUES(?, ?)", params![table, id])?;
+    Ok(id)
+}
+
+/// Describes rows that were not created by the seed. Empty if there are none.
+pub fn foreign_data(conn: &Connection) -> Result<Vec<String>> {
+    let mut found = Vec::new();
+    let users = count(conn, &format!("SELECT COUNT(*) FROM users WHERE ID NOT IN ({SEED_USER_IDS})"))?;
+    if users > 0 { found.push(format!("{} users", users)); }
+    let servers = count(conn, &format!("SELECT COUNT(*) FROM servers WHERE ID NOT IN ({SEED_SERVER_IDS})"))?;
+    if servers > 0 { found.push(format!("{} servers", servers)); }
+    let channels = count(conn, &format!("SELECT COUNT(*) FROM channels WHERE server_id NOT IN ({SEED_SERVER_IDS})"))?;
+    if channels > 0 { found.push(format!("{} channels", channels)); }
+    Ok(found)
+}
+
+fn refuse_foreign_data(conn: &Connection, force: bool, what: &str) -> Result<()> {
+    let foreign = foreign_data(conn)?;
+    if !foreign.is_empty() && !force {
+        bail!("the database contains data that is not from the seed ({}). use --force if you really want to {}", foreign.join(", "), what);
+    }
+    Ok(())
+}
+
+/// Deletes the rows created by the seed and everything hanging off them
+fn delete_seed_rows(conn: &Connection) -> Result<()> {
+    let seed_channels = format!("SELECT ID FROM channels WHERE server_id IN ({SEED_SERVER_IDS})");
+    let statements = [
+        format!("DELETE FROM reactions WHERE channel_id IN ({seed_channels})"),
+        format!("DELETE FROM channel_members WHERE channel_id IN ({seed_channels})"),
+        format!("DELETE FROM webhooks WHERE server_id IN ({SEED_SERVER_IDS})"),
+        format!("DELETE FROM channels WHERE server_id IN ({SEED_SERVER_IDS})"),
+        format!("DELETE FROM servers WHERE ID IN ({SEED_SERVER_IDS})"),
+        format!("DELETE FROM friends WHERE user_a_id IN ({SEED_USER_IDS}) OR user_b_id IN ({SEED_USER_IDS})"),
+        format!("DELETE FROM channel_members WHERE user_id IN ({SEED_USER_IDS})"),
+        format!("DELETE FROM api_tokens WHERE user_id IN ({SEED_USER_IDS})"),
+        format!("DELETE FROM users WHERE ID IN ({SEED_USER_IDS})"),
+        "DELETE FROM seed_rows".to_string(),
+    ];
+    for sql in statements {
+        conn.execute(&sql, [])?;
+    }
+    Ok(())
+}
+
+/// Removes the seed rows in one transaction.
+/// Refuses if the database contains other data unless `force` is set.
+pub fn clean(conn: &Connection, force: bool) -> Result<()> {
+    refuse_foreign_data(conn, force, "clean it")?;
+    let tx = conn.unchecked_transaction()?;
+    delete_seed_rows(&tx)?;
+    tx.commit()?;
+    Ok(())
+}
+
+/// Replaces the seed rows in one transaction. Running it twice gives the same data.
+/// Refuses if the database contains other data unless `force` is set.
+pub fn seed(conn: &Connection, chiller_password: &str, force: bool) -> Result<SeedSummary> {
+    refuse_foreign_data(conn, force, "seed it")?;
+    let tx = conn.unchecked_transaction()?;
+    delete_seed_rows(&tx)?;
+    let summary = insert_rows(&tx, chiller_password)
+        .context("failed to insert the seed rows. a user or server with a seed name that the seed did not create is in the way")?;
+    tx.commit()?;
+    Ok(summary)
+}
+
+fn insert_rows(conn: &Connection, chiller_password: &str) -> Result<SeedSummary> {
+    let mut sum = SeedSummary::default();
+
+    let chiller = mark(conn, "users", user::insert(conn, "ChillerDragon", chiller_password, LOCALHOST)?)?;
+    let seed_user = mark(conn, "users", user::insert(conn, "seed", "123", LOCALHOST)?)?;
+    let qshar = mark(conn, "users", user::insert(conn, "QshaR_seed", "vodka", LOCALHOST)?)?;
+    let heinrich = mark(conn, "users", user::insert(conn, "heinrich5991_seed", "aiYi3va#bahreXa3TheaSh)eepolach7", LOCALHOST)?)?;
+    sum.users = 4;
+
+    friend::insert(conn, chiller, seed_user)?;
+    sum.friends = 1;
+
+    let ddnet = mark(conn, "servers", server::insert(conn, "ddnet", "ddnet", IRC_NAME, IRC_IP, "../img/ddnet-logo.png", LOCALHOST, 0)?)?;
+    let teeworlds = mark(conn, "servers", server::insert(conn, "teeworlds", "teeworlds", IRC_NAME, IRC_IP, "../img/teeworlds-logo.png", LOCALHOST, heinrich)?)?;
+    let kog = mark(conn, "servers", server::insert(conn, "kog", "kog", IRC_NAME, IRC_IP, "https://kog.tw/other/logo_black_short.svg", LOCALHOST, qshar)?)?;
+    sum.servers = 3;
+
+    // (name, description, discord server, irc channel, server id, owner id)
+    let channels = [
+        ("general", "test description", "teeworlds", "teeworlds-general", teeworlds, heinrich),
+        ("bridge", "bridged to discord.gg/teeworlds #bridge", "teeworlds", "teeworlds", teeworlds, heinrich),
+        ("general", "test description", "kog", "kog", kog, qshar),
+        ("memes", "test description", "kog", "kog", kog, qshar),
+        ("developer", "test description", "ddnet", "ddnet", ddnet, 0),
+        ("off-topic", "test description", "ddnet", "ddnet-off-topic", ddnet, 0),
+        ("seed", "test description", "ddnet", "ddnet-seed", ddnet, 0),
+    ];
+    let mut ids = Vec::new();
+    for (name, description, discord_server, irc_channel, server_id, owner_id) in channels {
+        ids.push(channel::insert(conn, name, description, discord_server, name, irc_channel, IRC_IP, IRC_NAME, server_id, owner_id)?);
+    }
+    sum.channels = ids.len();
+    let (kog_general, developer, offtopic) = (ids[2], ids[4], ids[5]);
+
+    channel_member::insert(conn, developer, chiller, None, None, None, 1)?;
+    sum.channel_members = 1;
+
+    let webhooks = [
+        ("kogi when add bridge omg", kog, kog_general),
+        ("offtopic web hooker", ddnet, offtopic),
+        ("offtopic weeb hook", ddnet, offtopic),
+        ("github news feed", ddnet, developer),
+    ];
+    for (name, server_id, channel_id) in webhooks {
+        webhook::insert(conn, name, "xxx", server_id, channel_id, LOCALHOST, LOCALHOST, seed_user)?;
+    }
+    sum.webhooks = webhooks.len();
+    Ok(sum)
+}
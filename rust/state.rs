@@ -1,12 +1,13 @@
-use std::sync::Arc;
+use std::{collections::HashMap, sync::{atomic::{AtomicUsize, Ordering}, Arc}};
 
 use dashmap::DashMap;
 use parking_lot::Mutex;
 use rusqlite::Connection;
+use socketioxide::SocketIo;
 
-use crate::{config::Config, history::HistoryStore, models};
+use crate::{client::PlainSender, commands::CommandRegistry, config::Config, db, history::HistoryStore, models, plugins::PluginRegistry};
 use tokio::sync::mpsc::UnboundedSender;
-use crate::irc_bridge::IrcCmd;
+use crate::irc_bridge::{IrcCmd, IrcStatus};
 
 #[derive(Clone)]
 pub struct SessionUser {
@@ -18,6 +19,46 @@ pub struct SessionUser {
     pub is_typing: bool,
     pub last_typing_ms: i64,
     pub db_user: Option<models::user::UserRow>,
+    // set with /nick. shown instead of the username on new messages
+    pub display_name: Option<String>,
+}
+
+/// Counts running socket handlers so shutdown can wait for them
+#[derive(Default)]
+pub struct InFlight {
+    count: AtomicUsize,
+    idle: tokio::sync::Notify,
+}
+
+pub struct InFlightGuard(Arc<InFlight>);
+
+impl InFlight {
+    pub fn start(self: &Arc<Self>) -> InFlightGuard {
+        self.count.fetch_add(1, Ordering::SeqCst);
+        InFlightGuard(self.clone())
+    }
+
+    pub fn count(&self) -> usize {
+        self.count.load(Ordering::SeqCst)
+    }
+
+    pub async fn wait_idle(&self) {
+        loop {
+            let notified = self.idle.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+            if self.count() == 0 { return; }
+            notified.await;
+        }
+    }
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        if self.0.count.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.0.idle.notify_waiters();
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -25,30 +66,53 @@ pub struct AppState {
     pub config: Arc<Mutex<Config>>,
     pub db: Arc<Mutex<Connection>>, // simple serialized access
     pub sessions: Arc<DashMap<String, SessionUser>>, // ws-session users
+    pub resumable: Arc<DashMap<String, (SessionUser, std::time::Instant)>>, // sessions of dropped connections by session token
+    pub logged_out_all_at: Arc<Mutex<Option<std::time::Instant>>>, // last /admin/logout_all. sessions that dropped before can not resume
     pub history: Arc<HistoryStore>,
-    pub irc_tx: Arc<Mutex<Option<UnboundedSender<IrcCmd>>>>,
+    pub irc_tx: Arc<Mutex<HashMap<String, UnboundedSender<IrcCmd>>>>, // by network name
+    pub io: Arc<Mutex<Option<SocketIo>>>, // set once the socket.io layer is built
+    pub plain_clients: Arc<DashMap<String, PlainSender>>, // plain websocket connections by session id
+    pub irc_status: Arc<Mutex<HashMap<String, IrcStatus>>>, // by network name
+    pub started_at: std::time::Instant,
+    pub inflight: Arc<InFlight>,
+    pub http: reqwest::Client, // outgoing requests of subscriptions
+    pub muted: Arc<DashMap<String, std::time::Instant>>, // lowercase username to end of the mute
+    pub commands: Arc<CommandRegistry>, // slash commands of web users
+    pub plugins: Arc<PluginRegistry>, // in-process message handlers, enabled in the config
 }
 
 impl AppState {
     pub fn new(config: &Config) -> anyhow::Result<Self> {
-        let conn = Connection::open("./db/main.db")?;
-        conn.pragma_update(None, "journal_mode", &"WAL")?;
+        let conn = db::open(&config.db_path)?;
         // emulate checkPendingMigrations by checking a core table exists
         if let Err(e) = conn.prepare("SELECT * FROM servers LIMIT 1") {
-            eprintln!("[!] Error: test select failed: {e}");
-            eprintln!("[!]        try running 'npm run db migrate' (or the Rust CLI equivalent)");
-            std::process::exit(1);
+            anyhow::bail!("test select failed: {e}. try running 'db-cli --db {} migrate'", config.db_path.display());
         }
         let db = Arc::new(Mutex::new(conn));
 
-        let history = Arc::new(HistoryStore::load(config.backlog_size));
+        let history = Arc::new(HistoryStore::load(&config.history_path, config.backlog_size));
+        {
+            let conn = db.lock();
+            history.skip_used_ids(models::message_search::max_id(&conn).max(models::reaction::max_message_id(&conn)));
+        }
 
         Ok(Self {
             config: Arc::new(Mutex::new(config.clone())),
             db,
             sessions: Arc::new(DashMap::new()),
+            resumable: Arc::new(DashMap::new()),
+            logged_out_all_at: Arc::new(Mutex::new(None)),
             history,
-            irc_tx: Arc::new(Mutex::new(None)),
+            irc_tx: Arc::new(Mutex::new(HashMap::new())),
+            io: Arc::new(Mutex::new(None)),
+            plain_clients: Arc::new(DashMap::new()),
+            irc_status: Arc::new(Mutex::new(HashMap::new())),
+            started_at: std::time::Instant::now(),
+            inflight: Arc::new(InFlight::default()),
+            muted: Arc::new(DashMap::new()),
+            commands: Arc::new(CommandRegistry::with_builtins()),
+            plugins: Arc::new(PluginRegistry::with_builtins()),
+            http: reqwest::Client::builder().user_agent(concat!("irc-websockets/", env!("CARGO_PKG_VERSION"))).build()?,
         })
     }
 }
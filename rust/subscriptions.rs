@@ -0,0 +1,257 @@
+//! Outgoing webhooks. Admins subscribe a url to chat events and the server
+//! POSTs every matching event as json signed with the shared secret.
+//! Not to be confused with the discord style incoming webhooks of channels.
+
+use std::time::Duration;
+
+use hmac::{Hmac, Mac};
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::Sha256;
+use tokio::time::sleep;
+use tracing::{info, warn};
+use utoipa::ToSchema;
+
+use crate::{
+    ack::{ErrorCode, EventError},
+    history,
+    metrics,
+    models::{
+        channel::{self as channel_model, ChannelRow},
+        channel_member as cm_model,
+        subscription::{self, SubscriptionRow},
+        subscription_delivery::{self, DeliveryRow},
+    },
+    state::AppState,
+    types::IrcMessage,
+};
+
+/// `sha256=<hex hmac of the body>`
+pub const SIGNATURE_HEADER: &str = "X-Signature-256";
+pub const EVENT_HEADER: &str = "X-Event";
+pub const DELIVERY_HEADER: &str = "X-Delivery";
+const MIN_SECRET_LEN: usize = 16;
+// attempts kept in the delivery log per subscription
+const LOG_SIZE: i64 = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum EventType {
+    MessageCreated,
+    // an account joined a channel for the first time
+    UserJoined,
+    // a message contains the name of a channel member
+    Mention,
+    Moderation,
+}
+
+impl EventType {
+    pub const ALL: [EventType; 4] = [EventType::MessageCreated, EventType::UserJoined, EventType::Mention, EventType::Moderation];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            EventType::MessageCreated => "message_created",
+            EventType::UserJoined => "user_joined",
+            EventType::Mention => "mention",
+            EventType::Moderation => "moderation",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|event| event.as_str() == s)
+    }
+}
+
+/// A subscription as admins see it. The secret is never returned.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct SubscriptionInfo {
+    pub id: i64,
+    pub url: String,
+    pub events: Vec<EventType>,
+    #[serde(rename = "channelId")] pub channel_id: Option<i64>,
+    #[serde(rename = "createdAt")] pub created_at: String,
+}
+
+impl From<SubscriptionRow> for SubscriptionInfo {
+    fn from(row: SubscriptionRow) -> Self {
+        Self { id: row.id, events: events(&row), url: row.url, channel_id: row.channel_id, created_at: row.created_at }
+    }
+}
+
+/// One attempt to deliver an event
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct DeliveryInfo {
+    pub id: i64,
+    #[serde(rename = "deliveryId")] pub delivery_id: String,
+    pub event: String,
+    pub attempt: i64,
+    #[serde(rename = "statusCode")] pub status_code: Option<i64>,
+    pub error: Option<String>,
+    pub success: bool,
+    #[serde(rename = "createdAt")] pub created_at: String,
+}
+
+impl From<DeliveryRow> for DeliveryInfo {
+    fn from(row: DeliveryRow) -> Self {
+        Self {
+            id: row.id,
+            delivery_id: row.delivery_id,
+            event: row.event,
+            attempt: row.attempt,
+            status_code: row.status_code,
+            error: row.error,
+            success: row.success == 1,
+            created_at: row.created_at,
+        }
+    }
+}
+
+/// Body of every delivery
+#[derive(Debug, Serialize)]
+struct Envelope<'a> {
+    id: &'a str,
+    event: EventType,
+    timestamp: String,
+    data: &'a Value,
+}
+
+/// `data` of user_joined
+#[derive(Debug, Serialize)]
+pub struct UserJoined<'a> {
+    pub username: &'a str,
+    #[serde(rename = "userId")] pub user_id: i64,
+    pub channel: &'a str,
+    pub server: &'a str,
+}
+
+/// `data` of mention
+#[derive(Debug, Serialize)]
+struct Mention<'a> {
+    username: &'a str,
+    message: &'a IrcMessage,
+}
+
+/// `data` of moderation
+#[derive(Debug, Default, Serialize)]
+pub struct ModerationAction<'a> {
+    // message_deleted, logout_all, user_kicked, user_blocked or user_muted
+    pub action: &'a str,
+    // username, or admin_token for requests with the admin token
+    pub moderator: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")] pub server: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")] pub channel: Option<&'a str>,
+    #[serde(rename = "messageId", skip_serializing_if = "Option::is_none")] pub message_id: Option<i64>,
+    // author of the affected message
+    #[serde(skip_serializing_if = "Option::is_none")] pub author: Option<&'a str>,
+    // the kicked, blocked or muted user
+    #[serde(skip_serializing_if = "Option::is_none")] pub target: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")] pub reason: Option<&'a str>,
+    // length of a mute, 0 lifts it
+    #[serde(skip_serializing_if = "Option::is_none")] pub minutes: Option<u64>,
+}
+
+pub fn events(row: &SubscriptionRow) -> Vec<EventType> {
+    row.events.split(',').filter_map(EventType::parse).collect()
+}
+
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("hmac accepts any key length");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+pub fn create(conn: &Connection, url: &str, secret: &str, events: &[EventType], channel_id: Option<i64>) -> Result<SubscriptionInfo, EventError> {
+    let invalid = |message: String| Err(EventError::new(ErrorCode::InvalidInput, message));
+    match reqwest::Url::parse(url) {
+        Ok(u) if matches!(u.scheme(), "http" | "https") => {}
+        _ => return invalid(format!("'{url}' is not a http or https url")),
+    }
+    if secret.len() < MIN_SECRET_LEN { return invalid(format!("the secret has to be at least {MIN_SECRET_LEN} characters long")); }
+    if events.is_empty() { return invalid("a subscription needs at least one event".into()); }
+    if let Some(id) = channel_id {
+        if channel_model::find(conn, id).is_none() { return Err(EventError::new(ErrorCode::ChannelNotFound, format!("channel {id} not found"))); }
+    }
+    let names: Vec<&str> = EventType::ALL.into_iter().filter(|e| events.contains(e)).map(EventType::as_str).collect();
+    let id = subscription::insert(conn, url, secret, &names.join(","), channel_id)
+        .map_err(|e| EventError::new(ErrorCode::Internal, format!("failed to store the subscription: {e}")))?;
+    let row = subscription::find(conn, id).ok_or_else(|| EventError::new(ErrorCode::Internal, "failed to store the subscription"))?;
+    info!("[*][subscriptions] created id={} url={} events={}", row.id, row.url, row.events);
+    Ok(row.into())
+}
+
+/// Delivers `data` to every subscription of `event` that matches the channel.
+/// Events without a channel only go to subscriptions without a channel filter.
+pub fn emit<T: Serialize>(state: &AppState, conn: &Connection, event: EventType, channel_id: Option<i64>, data: &T) {
+    let subs: Vec<SubscriptionRow> = subscription::all(conn).into_iter()
+        .filter(|s| events(s).contains(&event))
+        .filter(|s| s.channel_id.is_none() || s.channel_id == channel_id)
+        .collect();
+    if subs.is_empty() { return; }
+    let data = match serde_json::to_value(data) {
+        Ok(v) => v,
+        Err(e) => { warn!("[!][subscriptions] failed to serialize {}: {}", event.as_str(), e); return; }
+    };
+    for sub in subs {
+        tokio::spawn(deliver(state.clone(), sub, event, data.clone()));
+    }
+}
+
+/// Emits message_created and a mention for every channel member named in the message
+pub fn message_created(state: &AppState, conn: &Connection, ch: &ChannelRow, msg: &IrcMessage) {
+    let msg = IrcMessage { token: None, ..msg.clone() };
+    emit(state, conn, EventType::MessageCreated, Some(ch.id), &msg);
+    for username in cm_model::usernames(conn, ch.id) {
+        if username != msg.from && history::is_mention(&msg.message, &username) {
+            emit(state, conn, EventType::Mention, Some(ch.id), &Mention { username: &username, message: &msg });
+        }
+    }
+}
+
+async fn deliver(state: AppState, sub: SubscriptionRow, event: EventType, data: Value) {
+    let delivery_id = uuid::Uuid::new_v4().to_string();
+    let envelope = Envelope { id: &delivery_id, event, timestamp: chrono::Utc::now().to_rfc3339(), data: &data };
+    let body = match serde_json::to_vec(&envelope) {
+        Ok(b) => b,
+        Err(e) => { warn!("[!][subscriptions] failed to serialize {}: {}", event.as_str(), e); return; }
+    };
+    let signature = sign(&sub.secret, &body);
+    let mut attempt = 1;
+    loop {
+        let limits = state.config.lock().subscriptions.clone();
+        let res = state.http.post(&sub.url)
+            .timeout(Duration::from_millis(limits.timeout_ms))
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header(EVENT_HEADER, event.as_str())
+            .header(DELIVERY_HEADER, &delivery_id)
+            .header(SIGNATURE_HEADER, &signature)
+            .body(body.clone())
+            .send()
+            .await;
+        let (status_code, error) = match res {
+            Ok(r) if r.status().is_success() => (Some(r.status().as_u16() as i64), None),
+            Ok(r) => (Some(r.status().as_u16() as i64), Some(format!("status {}", r.status()))),
+            Err(e) => (None, Some(e.to_string())),
+        };
+        let success = error.is_none();
+        {
+            let conn = state.db.lock();
+            let _ = subscription_delivery::insert(&conn, sub.id, &delivery_id, event.as_str(), attempt as i64, status_code, error.as_deref(), success);
+            let _ = subscription_delivery::prune(&conn, sub.id, LOG_SIZE);
+        }
+        if success {
+            metrics::METRICS.subscription_deliveries.with_label_values(&["delivered"]).inc();
+            return;
+        }
+        if attempt >= limits.max_attempts {
+            warn!("[!][subscriptions] giving up on {} {} to {} after {} attempts: {}", event.as_str(), delivery_id, sub.url, attempt, error.unwrap_or_default());
+            metrics::METRICS.subscription_deliveries.with_label_values(&["failed"]).inc();
+            return;
+        }
+        metrics::METRICS.subscription_deliveries.with_label_values(&["retried"]).inc();
+        sleep(Duration::from_millis(limits.retry_delay_ms.saturating_mul(1 << (attempt - 1).min(16)))).await;
+        attempt += 1;
+        // deleted while retrying
+        if subscription::find(&state.db.lock(), sub.id).is_none() { return; }
+    }
+}
@@ -0,0 +1,157 @@
+use std::{collections::VecDeque, path::{Path, PathBuf}, sync::Arc, time::{Duration, Instant}};
+
+use axum::{body::Bytes, extract::State, http::{HeaderMap, StatusCode}, routing::post, Router};
+use parking_lot::Mutex;
+use tokio::task::JoinHandle;
+
+use crate::{
+    config::{Config, IrcNetwork, SubscriptionConfig},
+    db, seed,
+    state::AppState,
+    subscriptions,
+    util::generate_token,
+};
+
+/// A full AppState on a freshly migrated database in a temporary directory.
+/// IRC runs in dry mode. The directory is removed when this is dropped.
+pub struct TestEnv {
+    pub state: AppState,
+    pub dir: PathBuf,
+}
+
+impl TestEnv {
+    pub fn new() -> anyhow::Result<Self> {
+        Self::with_config(|_| {})
+    }
+
+    /// Lets the caller adjust the test config before the state is created
+    pub fn with_config(adjust: impl FnOnce(&mut Config)) -> anyhow::Result<Self> {
+        let dir = std::env::temp_dir().join(format!("irc-websockets-test-{}", generate_token(12)));
+        std::fs::create_dir_all(&dir)?;
+
+        let mut config = Config {
+            dry_irc: true,
+            accounts_password: "test".into(),
+            admin_token: generate_token(32),
+            db_path: dir.join("main.db"),
+            history_path: dir.join("message_log.json"),
+            irc_networks: vec![IrcNetwork {
+                name: "quakenet".into(),
+                server: "irc.quakenet.org".into(),
+                port: 6667,
+                tls: true,
+                nickname: "ws-client".into(),
+                login_channel: None,
+                login_msg: None,
+            }],
+            // retries should not slow down tests
+            subscriptions: SubscriptionConfig { retry_delay_ms: 10, timeout_ms: 2000, ..SubscriptionConfig::default() },
+            ..Config::default()
+        };
+        adjust(&mut config);
+
+        let conn = db::open(&config.db_path)?;
+        db::migrate(&conn, &Self::migrations_dir(), true)?;
+        drop(conn);
+
+        let state = AppState::new(&config)?;
+        Ok(Self { state, dir })
+    }
+
+    /// Adds the sample data of `db-cli seed`
+    pub fn seeded(self) -> anyhow::Result<Self> {
+        seed::seed(&self.state.db.lock(), "xxx", false)?;
+        Ok(self)
+    }
+
+    pub fn migrations_dir() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("db/migrations")
+    }
+}
+
+impl Drop for TestEnv {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// A request received by [`HookReceiver`]
+#[derive(Debug, Clone)]
+pub struct ReceivedHook {
+    pub headers: HeaderMap,
+    pub body: Bytes,
+    pub received_at: Instant,
+}
+
+impl ReceivedHook {
+    pub fn json(&self) -> serde_json::Value {
+        serde_json::from_slice(&self.body).unwrap_or_default()
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).and_then(|v| v.to_str().ok())
+    }
+
+    /// The signature header matches the body signed with `secret`
+    pub fn is_signed_with(&self, secret: &str) -> bool {
+        self.header(subscriptions::SIGNATURE_HEADER) == Some(subscriptions::sign(secret, &self.body).as_str())
+    }
+}
+
+#[derive(Default)]
+struct Received {
+    hooks: Vec<ReceivedHook>,
+    // answers for the next requests, 200 once empty
+    statuses: VecDeque<u16>,
+}
+
+/// A local http server standing in for the receiver of subscriptions.
+/// Every request to any path is recorded. Stops when dropped.
+pub struct HookReceiver {
+    pub url: String,
+    received: Arc<Mutex<Received>>,
+    task: JoinHandle<()>,
+}
+
+impl HookReceiver {
+    pub async fn start() -> anyhow::Result<Self> {
+        let received = Arc::new(Mutex::new(Received::default()));
+        let app = Router::new()
+            .route("/*path", post(Self::handle))
+            .with_state(received.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let url = format!("http://{}/hook", listener.local_addr()?);
+        let task = tokio::spawn(async move { let _ = axum::serve(listener, app).await; });
+        Ok(Self { url, received, task })
+    }
+
+    async fn handle(State(received): State<Arc<Mutex<Received>>>, headers: HeaderMap, body: Bytes) -> StatusCode {
+        let mut received = received.lock();
+        received.hooks.push(ReceivedHook { headers, body, received_at: Instant::now() });
+        received.statuses.pop_front().and_then(|s| StatusCode::from_u16(s).ok()).unwrap_or(StatusCode::OK)
+    }
+
+    /// Answers the next requests with these status codes
+    pub fn respond_with(&self, statuses: &[u16]) {
+        self.received.lock().statuses.extend(statuses);
+    }
+
+    pub fn requests(&self) -> Vec<ReceivedHook> {
+        self.received.lock().hooks.clone()
+    }
+
+    /// Waits until `count` requests arrived or `timeout` passed
+    pub async fn wait_for(&self, count: usize, timeout: Duration) -> Vec<ReceivedHook> {
+        let start = Instant::now();
+        while self.received.lock().hooks.len() < count && start.elapsed() < timeout {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        self.requests()
+    }
+}
+
+impl Drop for HookReceiver {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
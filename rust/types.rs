@@ -1,8 +1,9 @@
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 
 // Shared types matching TS interfaces
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct WebhookObject {
     pub id: i64,
     pub token: String,
@@ -13,15 +14,19 @@ pub struct WebhookObject {
     pub application_id: Option<i64>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct ChannelInfo {
     pub id: i64,
     #[serde(rename = "serverId")] pub server_id: i64,
     pub name: String,
     pub description: String,
+    #[serde(rename = "unreadCount", default, skip_serializing_if = "Option::is_none")]
+    pub unread_count: Option<i64>,
+    #[serde(rename = "mentionCount", default, skip_serializing_if = "Option::is_none")]
+    pub mention_count: Option<i64>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
 pub struct IrcMessage {
     pub id: i64,
     pub from: String,
@@ -31,6 +36,119 @@ pub struct IrcMessage {
     pub date: String,
     #[serde(skip_serializing_if = "Option::is_none")] 
     pub token: Option<String>,
+    #[serde(rename = "replyTo", default, skip_serializing_if = "Option::is_none")]
+    pub reply_to: Option<i64>,
+    // account of the web author. None for irc messages and master password logins
+    #[serde(rename = "userId", default, skip_serializing_if = "Option::is_none")]
+    pub user_id: Option<i64>,
+    #[serde(rename = "editedAt", default, skip_serializing_if = "Option::is_none")]
+    pub edited_at: Option<String>,
+    // previous versions oldest first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub edits: Vec<MessageEdit>,
+    // tombstone: message and edits are wiped when this is set
+    #[serde(rename = "deletedAt", default, skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<String>,
+    // filled from the database when messages are requested, never stored in the history
+    #[serde(default, skip_deserializing, skip_serializing_if = "Vec::is_empty")]
+    pub reactions: Vec<ReactionCount>,
+    // chosen by the sending client to find its message in the reply, never stored
+    #[serde(default, skip_serializing)]
+    pub nonce: Option<String>,
+    // sent with /me. irc shows it as "* nick message"
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub action: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum DeliveryStatus {
+    Sent,
+    Failed,
+    // waiting for the irc connection, a messageStatus follows
+    Queued,
+}
+
+/// Reply to `message` and payload of `messageStatus`
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct MessageStatus {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<String>,
+    pub id: i64,
+    pub channel: String,
+    pub server: String,
+    pub date: String,
+    pub status: DeliveryStatus,
+}
+
+/// Reply to `message` when it was a slash command
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct CommandStatus {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<String>,
+    pub command: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(untagged)]
+pub enum MessageReply {
+    Sent(MessageStatus),
+    Command(CommandStatus),
+}
+
+/// Output of a slash command. Only sent to the socket that ran it and never stored.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct SystemMessage {
+    pub channel: String,
+    pub server: String,
+    pub command: String,
+    pub message: String,
+    pub error: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ReactionCount {
+    pub emoji: String,
+    pub count: i64,
+    pub me: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReactionRequest {
+    pub id: i64,
+    pub channel: String,
+    pub server: String,
+    pub emoji: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReactionEvent {
+    pub id: i64,
+    pub channel: String,
+    pub server: String,
+    pub emoji: String,
+    pub username: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct MessageEdit {
+    pub message: String,
+    pub date: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EditMessageRequest {
+    pub id: i64,
+    pub channel: String,
+    pub server: String,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MessageRef {
+    pub id: i64,
+    pub channel: String,
+    pub server: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -53,14 +171,12 @@ pub struct AuthResponse {
     pub username: String,
     pub admin: bool,
     pub token: String,
-    pub message: String,
-    pub success: bool,
+    // the channel from the auth request that was joined
+    pub join: JoinChannelResponse,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct JoinChannelResponse {
-    pub message: String,
-    pub success: bool,
     pub channel: String,
     pub server: String,
     #[serde(rename = "unredMsgId")] pub unred_msg_id: Option<i64>,
@@ -68,6 +184,35 @@ pub struct JoinChannelResponse {
     #[serde(rename = "serverId")] pub server_id: i64,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResumeRequest {
+    // session token of the dropped connection
+    pub token: String,
+    #[serde(rename = "lastSeen", default)]
+    pub last_seen: Vec<LastSeen>,
+}
+
+/// Newest message a client has of a channel
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LastSeen {
+    pub channel: String,
+    pub server: String,
+    #[serde(rename = "messageId")] pub message_id: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResumeResponse {
+    pub username: String,
+    pub admin: bool,
+    pub token: String,
+    // the channel the session was in
+    pub join: JoinChannelResponse,
+    // oldest first
+    pub missed: Vec<IrcMessage>,
+    // some channels missed more messages than are replayed
+    pub truncated: bool,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LogoutMessage {
     pub message: String,
@@ -94,10 +239,8 @@ pub struct TypingState {
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct AlertMessage {
-    pub success: bool,
+pub struct ServerShutdown {
     pub message: String,
-    pub expire: i64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -108,3 +251,41 @@ pub struct ServerInfo {
     #[serde(rename = "iconUrl")] pub icon_url: String,
     #[serde(rename = "bannerUrl")] pub banner_url: String,
 }
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MarkReadRequest {
+    pub channel: String,
+    pub server: String,
+    #[serde(rename = "messageId")] pub message_id: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct UnreadInfo {
+    pub channel: String,
+    pub server: String,
+    #[serde(rename = "channelId")] pub channel_id: i64,
+    #[serde(rename = "serverId")] pub server_id: i64,
+    #[serde(rename = "unredMsgId")] pub unred_msg_id: Option<i64>,
+    #[serde(rename = "unreadCount")] pub unread_count: i64,
+    #[serde(rename = "mentionCount")] pub mention_count: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct SearchResult {
+    pub id: i64,
+    pub from: String,
+    pub channel: String,
+    pub server: String,
+    pub date: String,
+    // message content around the matches with <mark> tags around them
+    pub snippet: String,
+    pub rank: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct SearchResponse {
+    pub results: Vec<SearchResult>,
+    pub total: i64,
+    pub offset: i64,
+    pub limit: i64,
+}
@@ -0,0 +1,83 @@
+use std::collections::HashMap;
+
+use rusqlite::Connection;
+use tracing::info;
+
+use crate::{
+    ack::{ErrorCode, EventError},
+    client,
+    models::{channel::ChannelRow, channel_member::{self as cm_model, ChannelMemberRow}, user::UserRow},
+    state::AppState,
+    types::UnreadInfo,
+};
+
+pub fn unread_info(state: &AppState, conn: &Connection, user: &UserRow, ch: &ChannelRow) -> UnreadInfo {
+    let member = cm_model::find_by_user_and_channel(conn, user.id, ch.id);
+    member_info(state, user, ch, member.as_ref())
+}
+
+fn member_info(state: &AppState, user: &UserRow, ch: &ChannelRow, member: Option<&ChannelMemberRow>) -> UnreadInfo {
+    let unred_msg_id = member.and_then(|m| m.last_red_msg_id());
+    // non members did not miss anything
+    let (unread_count, mention_count) = match member {
+        Some(_) => state.history.count_unread(&ch.discord_server, &ch.discord_channel, unred_msg_id, &user.username),
+        None => (0, 0),
+    };
+    UnreadInfo {
+        channel: ch.discord_channel.clone(),
+        server: ch.discord_server.clone(),
+        channel_id: ch.id,
+        server_id: ch.server_id,
+        unred_msg_id,
+        unread_count,
+        mention_count,
+    }
+}
+
+/// Everything up to and including `msg_id` counts as read.
+pub fn mark_read(state: &AppState, conn: &Connection, user: &UserRow, ch: &ChannelRow, msg_id: i64) -> Result<(), EventError> {
+    set_unred_msg_id(state, conn, user, ch, msg_id)?;
+    info!("[*][unread] user='{}' marked '{}#{}' read up to msgid={}", user.username, ch.discord_server, ch.discord_channel, msg_id);
+    Ok(())
+}
+
+/// `msg_id` and everything after it counts as unread.
+pub fn mark_unread(state: &AppState, conn: &Connection, user: &UserRow, ch: &ChannelRow, msg_id: i64) -> Result<(), EventError> {
+    if msg_id < 1 {
+        return Err(EventError::new(ErrorCode::InvalidInput, format!("invalid message id {}", msg_id)));
+    }
+    set_unred_msg_id(state, conn, user, ch, msg_id - 1)?;
+    info!("[*][unread] user='{}' marked '{}#{}' unread from msgid={}", user.username, ch.discord_server, ch.discord_channel, msg_id);
+    Ok(())
+}
+
+fn set_unred_msg_id(state: &AppState, conn: &Connection, user: &UserRow, ch: &ChannelRow, msg_id: i64) -> Result<(), EventError> {
+    if msg_id < 0 || msg_id > state.history.latest_id() {
+        return Err(EventError::new(ErrorCode::InvalidInput, format!("invalid message id {}", msg_id)));
+    }
+    let Some(mut member) = cm_model::find_by_user_and_channel(conn, user.id, ch.id) else {
+        return Err(EventError::new(ErrorCode::PermissionDenied, "you are not a member of this channel"));
+    };
+    member.unred_msg_id = Some(msg_id);
+    cm_model::update(conn, &member).map_err(|e| EventError::new(ErrorCode::Internal, format!("failed to update membership: {e}")))
+}
+
+/// Sends fresh counts for `ch` to the sockets of all logged in members
+/// or only to the sockets of `only_user` if set.
+pub fn notify(state: &AppState, conn: &Connection, ch: &ChannelRow, only_user: Option<i64>) {
+    // one query for the whole channel, this runs for every relayed message
+    let members: HashMap<i64, ChannelMemberRow> = cm_model::for_channel(conn, ch.id).into_iter()
+        .filter(|m| only_user.is_none_or(|id| id == m.user_id))
+        .map(|m| (m.user_id, m))
+        .collect();
+    if members.is_empty() { return; }
+    let targets: Vec<(String, UserRow)> = state.sessions.iter()
+        .filter(|u| u.value().logged_in)
+        .filter_map(|u| u.value().db_user.clone().map(|db_user| (u.key().clone(), db_user)))
+        .filter(|(_, db_user)| members.contains_key(&db_user.id))
+        .collect();
+    for (sid, db_user) in targets {
+        let info = member_info(state, &db_user, ch, members.get(&db_user.id));
+        client::emit_to(state, &sid, "unreadCounts", &info);
+    }
+}
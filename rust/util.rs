@@ -7,6 +7,10 @@ pub fn generate_token(len: usize) -> String {
         .collect()
 }
 
+pub fn generate_webhook_token() -> String {
+    random_int(100000000000000, 3592180204621707).to_string()
+}
+
 pub fn random_int(min: i64, max: i64) -> i64 {
     let mut rng = rand::thread_rng();
     rng.gen_range(min..=max)
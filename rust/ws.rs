@@ -1,109 +1,124 @@
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use regex::Regex;
-use once_cell::sync::Lazy;
-use socketioxide::{extract::{Data, SocketRef, State}, SocketIo};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use serde::Serialize;
+use socketioxide::{extract::{AckSender, SocketRef, State, TryData}, SocketIo};
 use tracing::{info, warn};
 
 use crate::{
-    irc_bridge::{self, ChannelMapping},
-    models::{channel as channel_model, channel_member as cm_model, server as server_model, user as user_model, webhook as webhook_model},
+    ack::{Ack, ErrorCode, EventError, EventResult},
+    client::{self, Client},
+    commands,
+    history::MessageLogOptions,
+    irc_bridge,
+    metrics,
+    models::{channel as channel_model, channel_member as cm_model, server as server_model, user::{self as user_model, UserRow}, webhook as webhook_model},
+    messages::{self, rejected},
+    reactions, search,
+    subscriptions::{self, EventType, ModerationAction, UserJoined},
     state::{AppState, SessionUser},
     types::*,
+    unread,
     util,
 };
 
-fn username_pattern() -> Regex { Regex::new(r"^[a-zA-Z0-9_]{1,20}$").unwrap() }
+pub fn username_pattern() -> Regex { Regex::new(r"^[a-zA-Z0-9_]{1,20}$").unwrap() }
 
 fn now_ms() -> i64 { SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as i64 }
 
-struct RateLimiter {
-    last_sent_ms: i64,
-    log: Vec<i64>,
-}
+/// How long the session of a dropped connection can be resumed
+const RESUME_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+// missed messages replayed per channel, clients fetch the rest over http
+const MAX_REPLAY: usize = 500;
 
-impl RateLimiter {
-    fn new() -> Self { Self { last_sent_ms: 0, log: Vec::new() } }
-    fn is_ratelimited(&mut self, content: &str) -> bool {
-        if content.contains('\n') { return true; }
-        let now = now_ms();
-        self.log.retain(|&t| now - t <= 8000);
-        if self.log.len() > 5 { return true; }
-        let diff = now - self.last_sent_ms;
-        self.last_sent_ms = now;
-        if diff < 3000 { self.log.push(now); }
-        false
-    }
-}
+/// Every event a client can send. Plain websocket clients use them as `op`.
+pub const EVENTS: [&str; 15] = [
+    "joinChannel", "typingInfo", "registerRequest", "authRequest", "resumeRequest", "webhooksRequest", "newWebhookRequest",
+    "connectedServerListRequest", "editMessage", "deleteMessage", "addReaction", "removeReaction",
+    "markChannelRead", "markMessageUnread", "message",
+];
 
 pub fn register_handlers(io: SocketIo) {
     io.ns("/", |s: SocketRef, State(state): State<AppState>| {
-        // On connect
-        let sid = s.id.to_string();
-        let user = SessionUser {
-            username: "connecting".into(),
-            session_token: util::generate_token(32),
-            logged_in: false,
-            active_channel: "_connecting".into(),
-            active_server: "_connecting".into(),
-            is_typing: false,
-            last_typing_ms: now_ms(),
-            db_user: None,
-        };
-        state.sessions.insert(sid.clone(), user);
-        info!("[*] connect sid={:?}", sid);
-
-        s.on_disconnect(|s: SocketRef, State(state): State<AppState>| {
-            if let Some((_sid, user)) = state.sessions.remove(&s.id.to_string()) {
-                info!("[*] '{}' left", user.username);
-                let _ = s.broadcast().emit("userLeave", &user.username);
-            } else {
-                info!("[*] leave before login");
-            }
-        });
-
-        // joinChannel
-        s.on("joinChannel", |s: SocketRef, State(state): State<AppState>, Data(join): Data<JoinChannel>| async move {
-            let _ = on_join_channel(s, state, join).await;
-        });
+        on_connect(&Client::SocketIo(s.clone()), &state);
 
-        // typingInfo
-        s.on("typingInfo", |s: SocketRef, State(state): State<AppState>, Data(typing): Data<TypingInfo>| {
-            on_typing_info(s, state, typing);
+        s.on_disconnect(|s: SocketRef, State(state): State<AppState>| async move {
+            on_disconnect(&Client::SocketIo(s), &state).await;
         });
 
-        // registerRequest
-        s.on("registerRequest", |s: SocketRef, State(state): State<AppState>, Data(req): Data<RegisterRequest>| {
-            on_register_request(s, state, req);
-        });
+        for event in EVENTS {
+            s.on(event, move |s: SocketRef, State(state): State<AppState>, TryData(data): TryData<Value>, ack: AckSender| async move {
+                // connectedServerListRequest comes without data
+                let data = data.unwrap_or(Value::Null);
+                let res = dispatch(Client::SocketIo(s), state, event, data).await;
+                if let Err(e) = &res { warn!("[!] {event}: {e}"); }
+                // does nothing if the client did not ask for an ack
+                let _ = ack.send(&Ack::from(res));
+            });
+        }
+    });
+}
 
-        // authRequest
-        s.on("authRequest", |s: SocketRef, State(state): State<AppState>, Data(req): Data<AuthRequest>| {
-            let s2 = s.clone();
-            let state2 = state.clone();
-            tokio::spawn(async move { on_auth_request(s2, state2, req).await; });
-        });
+pub fn on_connect(c: &Client, state: &AppState) {
+    let sid = c.id();
+    let user = SessionUser {
+        username: "connecting".into(),
+        session_token: util::generate_token(32),
+        logged_in: false,
+        active_channel: "_connecting".into(),
+        active_server: "_connecting".into(),
+        is_typing: false,
+        last_typing_ms: now_ms(),
+        db_user: None,
+        display_name: None,
+    };
+    state.sessions.insert(sid.clone(), user);
+    info!("[*] connect sid={:?}", sid);
+}
 
-        // webhooksRequest
-        s.on("webhooksRequest", |s: SocketRef, State(state): State<AppState>, Data(server_id): Data<i64>| {
-            on_webhooks_request(s, state, server_id);
-        });
+pub async fn on_disconnect(c: &Client, state: &AppState) {
+    if let Some((_sid, user)) = state.sessions.remove(&c.id()) {
+        info!("[*] '{}' left", user.username);
+        c.broadcast(state, "userLeave", &user.username).await;
+        if user.logged_in {
+            state.resumable.retain(|_, (_, left_at)| left_at.elapsed() < RESUME_TIMEOUT);
+            state.resumable.insert(user.session_token.clone(), (SessionUser{ is_typing: false, ..user }, Instant::now()));
+        }
+    } else {
+        info!("[*] leave before login");
+    }
+}
 
-        // newWebhookRequest
-        s.on("newWebhookRequest", |s: SocketRef, State(state): State<AppState>, Data(obj): Data<WebhookObject>| {
-            on_new_webhook_request(s, state, obj);
-        });
+fn parse<T: DeserializeOwned>(event: &str, data: Value) -> Result<T, EventError> {
+    serde_json::from_value(data).map_err(|e| EventError::new(ErrorCode::InvalidPayload, format!("invalid payload for '{event}': {e}")))
+}
 
-        // connectedServerListRequest
-        s.on("connectedServerListRequest", |s: SocketRef, State(state): State<AppState>| {
-            on_connected_server_list_request(s, state);
-        });
+fn reply<T: Serialize>(res: Result<T, EventError>) -> EventResult {
+    serde_json::to_value(res?).map_err(|e| EventError::new(ErrorCode::Internal, e.to_string()))
+}
 
-        // message
-        s.on("message", |s: SocketRef, State(state): State<AppState>, Data(msg): Data<IrcMessage>| async move {
-            on_message(s, state, msg).await;
-        });
-    });
+/// Runs the handler of `event`. Shared by socket.io and the plain websocket.
+pub async fn dispatch(c: Client, state: AppState, event: &str, data: Value) -> EventResult {
+    match event {
+        "joinChannel" => reply(on_join_channel(c, state, parse(event, data)?).await),
+        "typingInfo" => reply(on_typing_info(c, state, parse(event, data)?).await),
+        "registerRequest" => reply(on_register_request(state, parse(event, data)?)),
+        "authRequest" => reply(on_auth_request(c, state, parse(event, data)?).await),
+        "resumeRequest" => reply(on_resume_request(c, state, parse(event, data)?).await),
+        "webhooksRequest" => reply(on_webhooks_request(c, state, parse(event, data)?)),
+        "newWebhookRequest" => reply(on_new_webhook_request(c, state, parse(event, data)?)),
+        "connectedServerListRequest" => reply(on_connected_server_list_request(c, state)),
+        "editMessage" => reply(on_edit_message(c, state, parse(event, data)?).await),
+        "deleteMessage" => reply(on_delete_message(c, state, parse(event, data)?).await),
+        "addReaction" => reply(on_reaction(c, state, parse(event, data)?, true).await),
+        "removeReaction" => reply(on_reaction(c, state, parse(event, data)?, false).await),
+        "markChannelRead" => reply(on_mark_read(c, state, parse(event, data)?, false)),
+        "markMessageUnread" => reply(on_mark_read(c, state, parse(event, data)?, true)),
+        "message" => reply(on_message(c, state, parse(event, data)?).await),
+        _ => Err(EventError::new(ErrorCode::UnknownOp, format!("unknown event '{event}'"))),
+    }
 }
 
 fn use_accounts(state: &AppState) -> bool { state.config.lock().require_passwords }
@@ -117,34 +132,31 @@ fn check_auth(state: &AppState, msg: &IrcMessage) -> bool {
     false
 }
 
-fn get_mapping_by_discord(state: &AppState, server: &str, channel: &str) -> Option<ChannelMapping> {
-    irc_bridge::get_connected_irc_channels(state)
-        .into_iter()
-        .find(|m| m.discord_server == server && m.discord_channel == channel)
-}
-
-fn on_register_request(s: SocketRef, state: AppState, reg: RegisterRequest) {
+fn on_register_request(state: AppState, reg: RegisterRequest) -> Result<(), EventError> {
     info!("[*] register request username={} password={} token={}", reg.username, reg.password, reg.token);
-    let invalid = |message: &str| {
-        let resp = AuthResponse { username: "".into(), admin: false, token: "".into(), message: message.into(), success: false };
-        let _ = s.emit("authResponse", &resp);
-    };
-    if reg.username.is_empty() { return invalid("invalid username"); }
-    if reg.password.is_empty() { return invalid("invalid password"); }
-    if !username_pattern().is_match(&reg.username) { return invalid(&format!("username has to match {}", username_pattern())); }
-    if reg.password.len() < 3 || reg.password.len() > 1024 { return invalid("password has to be between 3 and 1024 characters long"); }
-    if let Some(sign_up_token) = std::env::var("SIGN_UP_TOKEN").ok() {
-        if reg.password == sign_up_token || reg.password == state.config.lock().accounts_password { return invalid("please choose a different password"); }
+    let invalid = |message: String| Err(EventError::new(ErrorCode::InvalidInput, message));
+    if reg.username.is_empty() { return invalid("invalid username".into()); }
+    if reg.password.is_empty() { return invalid("invalid password".into()); }
+    if !username_pattern().is_match(&reg.username) { return invalid(format!("username has to match {}", username_pattern())); }
+    if reg.password.len() < 3 || reg.password.len() > 1024 { return invalid("password has to be between 3 and 1024 characters long".into()); }
+    let (sign_up_token, accounts_password) = { let cfg = state.config.lock(); (cfg.sign_up_token.clone(), cfg.accounts_password.clone()) };
+    if let Some(sign_up_token) = &sign_up_token {
+        if reg.password == *sign_up_token || reg.password == accounts_password { return invalid("please choose a different password".into()); }
     }
     let conn = state.db.lock();
-    if user_model::is_username_taken(&conn, &reg.username) { return invalid("this username is already taken"); }
-    if let Ok(token) = std::env::var("SIGN_UP_TOKEN") { if reg.token != token { return invalid("invalid sign up token"); } }
-    let _ = user_model::insert(&conn, &reg.username, &reg.password, "0.0.0.0");
-    let resp = AuthResponse { username: reg.username, admin: false, token: "".into(), message: "Successfully registered! You can now log in!".into(), success: true };
-    let _ = s.emit("authResponse", &resp);
+    if user_model::is_username_taken(&conn, &reg.username) {
+        return Err(EventError::new(ErrorCode::UsernameTaken, "this username is already taken"));
+    }
+    if let Some(token) = &sign_up_token {
+        if reg.token != *token { return Err(EventError::new(ErrorCode::WrongCredentials, "invalid sign up token")); }
+    }
+    user_model::insert(&conn, &reg.username, &reg.password, "0.0.0.0")
+        .map_err(|e| EventError::new(ErrorCode::Internal, format!("failed to register: {e}")))?;
+    info!("[*] registered user '{}'", reg.username);
+    Ok(())
 }
 
-async fn on_auth_request(s: SocketRef, state: AppState, auth: AuthRequest) {
+async fn on_auth_request(c: Client, state: AppState, auth: AuthRequest) -> Result<AuthResponse, EventError> {
     let db_user = {
         let conn = state.db.lock();
         user_model::find_by_credentials(&conn, &auth.username, &auth.password)
@@ -153,72 +165,148 @@ async fn on_auth_request(s: SocketRef, state: AppState, auth: AuthRequest) {
     if state.sessions.iter().any(|u| u.value().username == auth.username) {
         // send logout to that socket if present
         // best-effort: emit broadcast; specific targeting not implemented yet
-        let _ = s.broadcast().emit("logout", &LogoutMessage{ message: "logged in from another location".into() });
+        c.broadcast(&state, "logout", &LogoutMessage{ message: "logged in from another location".into() }).await;
     }
     let valid = if !use_accounts(&state) { true } else { db_user.is_some() || state.config.lock().accounts_password == auth.password };
-    if !valid { let resp = AuthResponse{ username: "".into(), admin: false, token: "".into(), message: "wrong credentials".into(), success: false }; let _= s.emit("authResponse", &resp); return; }
-    if let Some(ref row) = db_user { if row.is_blocked == 1 { let resp = AuthResponse{ username: "".into(), admin: false, token: "".into(), message: "this account is blocked".into(), success: false }; let _ = s.emit("authResponse", &resp); return; } }
-    if db_user.is_none() && { let c = state.db.lock(); user_model::is_username_taken(&c, &auth.username) } { let resp = AuthResponse{ username: "".into(), admin: false, token: "".into(), message: "this username needs a different password".into(), success: false }; let _= s.emit("authResponse", &resp); return; }
+    if !valid { return Err(EventError::new(ErrorCode::WrongCredentials, "wrong credentials")); }
+    if let Some(ref row) = db_user { if row.is_blocked == 1 { return Err(EventError::new(ErrorCode::AccountBlocked, "this account is blocked")); } }
+    if db_user.as_ref().is_some_and(|u| u.bot()) { return Err(EventError::new(ErrorCode::WrongCredentials, "bot accounts can only use api tokens")); }
+    if db_user.is_none() && { let c = state.db.lock(); user_model::is_username_taken(&c, &auth.username) } {
+        return Err(EventError::new(ErrorCode::WrongCredentials, "this username needs a different password"));
+    }
 
     // update session
-    if let Some(mut entry) = state.sessions.get_mut(&s.id.to_string()) {
+    if let Some(mut entry) = state.sessions.get_mut(&c.id()) {
         entry.username = auth.username.clone();
         entry.logged_in = true;
         entry.db_user = db_user.clone();
     }
-    if on_join_channel(s.clone(), state.clone(), JoinChannel{ channel: auth.channel.clone(), server: auth.server.clone(), password: "".into() }).await.is_err() {
-        let resp = AuthResponse{ username: "".into(), admin: false, token: "".into(), message: "failed to join channel".into(), success: false };
-        let _ = s.emit("authResponse", &resp);
-        if let Some(mut entry) = state.sessions.get_mut(&s.id.to_string()) { entry.logged_in = false; }
-        return;
-    }
+    let join = match on_join_channel(c.clone(), state.clone(), JoinChannel{ channel: auth.channel.clone(), server: auth.server.clone(), password: "".into() }).await {
+        Ok(join) => join,
+        Err(e) => {
+            if let Some(mut entry) = state.sessions.get_mut(&c.id()) { entry.logged_in = false; }
+            return Err(e);
+        }
+    };
     info!("[*] '{}' logged in {}", auth.username, if db_user.is_some() { "to account" } else { "with master password" });
-    let _ = s.broadcast().emit("userJoin", &auth.username);
+    c.broadcast(&state, "userJoin", &auth.username).await;
     let admin = db_user.as_ref().map(|u| u.is_admin == 1).unwrap_or(false);
-    let token = state.sessions.get(&s.id.to_string()).map(|u| u.session_token.clone()).unwrap_or_default();
-    let resp = AuthResponse{ username: auth.username, admin, token, success: true, message: "logged in".into() };
-    let _ = s.emit("authResponse", &resp);
+    let token = state.sessions.get(&c.id()).map(|u| u.session_token.clone()).unwrap_or_default();
+    Ok(AuthResponse{ username: auth.username, admin, token, join })
+}
+
+/// Takes the session `token` of a dropped connection. A live connection with that
+/// session is closed, the server might not have noticed that it dropped yet.
+fn take_session(c: &Client, state: &AppState, token: &str) -> Option<(SessionUser, bool)> {
+    if let Some((_, (user, left_at))) = state.resumable.remove(token) {
+        let logged_out = state.logged_out_all_at.lock().is_some_and(|at| left_at <= at);
+        return (left_at.elapsed() < RESUME_TIMEOUT && !logged_out).then_some((user, false));
+    }
+    let id = c.id();
+    let old_id = state.sessions.iter()
+        .find(|u| *u.key() != id && u.value().logged_in && u.value().session_token == token)
+        .map(|u| u.key().clone())?;
+    let (_, user) = state.sessions.remove(&old_id)?;
+    client::disconnect(state, &old_id);
+    Some((user, true))
+}
+
+/// Messages after the last seen ones of every channel the account can read
+fn missed_messages(state: &AppState, user: &UserRow, last_seen: &[LastSeen]) -> (Vec<IrcMessage>, bool) {
+    let mut missed = Vec::new();
+    let mut truncated = false;
+    for seen in last_seen {
+        let readable = {
+            let conn = state.db.lock();
+            channel_model::find_by_discord(&conn, &seen.server, &seen.channel)
+                .is_some_and(|ch| ch.is_private == 0 || cm_model::find_by_user_and_channel(&conn, user.id, ch.id).is_some())
+        };
+        if !readable {
+            info!("[*][resume] user='{}' skipped '{}#{}'", user.username, seen.server, seen.channel);
+            continue;
+        }
+        let opts = MessageLogOptions { from_id: seen.message_id + 1, count: MAX_REPLAY as i64 + 1, ..Default::default() };
+        let Ok(mut msgs) = state.history.get_messages(&seen.server, &seen.channel, opts) else { continue; };
+        if msgs.len() > MAX_REPLAY {
+            msgs.truncate(MAX_REPLAY);
+            truncated = true;
+        }
+        missed.extend(msgs);
+    }
+    // ids are unique across channels
+    missed.sort_by_key(|m| m.id);
+    (missed, truncated)
 }
 
-async fn on_join_channel(s: SocketRef, state: AppState, join: JoinChannel) -> Result<(), ()> {
+async fn on_resume_request(c: Client, state: AppState, req: ResumeRequest) -> Result<ResumeResponse, EventError> {
+    let Some((user, was_connected)) = take_session(&c, &state, &req.token) else {
+        return Err(EventError::new(ErrorCode::InvalidToken, "the session can not be resumed please login again"));
+    };
+    // the account might have changed while the connection was gone
+    let db_user = user.db_user.as_ref().and_then(|u| { let conn = state.db.lock(); user_model::find(&conn, u.id) });
+    let Some(db_user) = db_user else { return Err(EventError::not_logged_in()); };
+    if db_user.blocked() { return Err(EventError::new(ErrorCode::AccountBlocked, "this account is blocked")); }
+
+    let join = JoinChannel{ channel: user.active_channel.clone(), server: user.active_server.clone(), password: "".into() };
+    let username = user.username.clone();
+    state.sessions.insert(c.id(), SessionUser{ db_user: Some(db_user.clone()), ..user });
+    let join = match on_join_channel(c.clone(), state.clone(), join).await {
+        Ok(join) => join,
+        Err(e) => {
+            if let Some(mut entry) = state.sessions.get_mut(&c.id()) { entry.logged_in = false; }
+            return Err(e);
+        }
+    };
+    let (missed, truncated) = missed_messages(&state, &db_user, &req.last_seen);
+    info!("[*][resume] user='{}' resumed and missed {} messages", username, missed.len());
+    // a dropped connection already announced the leave
+    if !was_connected { c.broadcast(&state, "userJoin", &username).await; }
+    Ok(ResumeResponse{ username, admin: db_user.admin(), token: req.token, join, missed, truncated })
+}
+
+async fn on_join_channel(c: Client, state: AppState, join: JoinChannel) -> Result<JoinChannelResponse, EventError> {
     let conn = state.db.lock();
     // check channel exists
     let Some(ch) = channel_model::find_by_discord(&conn, &join.server, &join.channel) else {
-        warn!("{} tried to join server='{}' channel='{}' but that is not in the db", s.id, join.server, join.channel);
-        let _ = s.emit("joinChannelResponse", &JoinChannelResponse{ message: "channel is not in database".into(), success: false, server: join.server, channel: join.channel, unred_msg_id: None, channel_id: 0, server_id: 0 });
-        return Err(());
+        warn!("{} tried to join server='{}' channel='{}' but that is not in the db", c.id(), join.server, join.channel);
+        return Err(EventError::new(ErrorCode::ChannelNotFound, "channel is not in database"));
     };
     // update membership
-    let session = match state.sessions.get(&s.id.to_string()) { Some(u) => u, None => return Err(()) };
-    if !session.logged_in { return Err(()); }
-    if session.db_user.is_none() { return Err(()); }
-    let db_user = session.db_user.as_ref().unwrap();
+    // cloned so the sessions map is not locked while the entry is updated below
+    let Some(session) = state.sessions.get(&c.id()).map(|u| u.clone()) else { return Err(EventError::not_logged_in()); };
+    if !session.logged_in { return Err(EventError::not_logged_in()); }
+    let Some(db_user) = &session.db_user else { return Err(EventError::not_logged_in()); };
     let mut member = cm_model::find_by_user_and_channel(&conn, db_user.id, ch.id);
     if member.is_none() {
-        let _ = cm_model::insert(&conn, ch.id, db_user.id, None, None, None, 1).map_err(|_|())?;
+        cm_model::insert(&conn, ch.id, db_user.id, None, None, None, 1)
+            .map_err(|e| EventError::new(ErrorCode::Internal, format!("failed to join channel: {e}")))?;
         member = cm_model::find_by_user_and_channel(&conn, db_user.id, ch.id);
         info!("[*][join-channel] user='{}' joined channel '{}#{}'", session.username, ch.discord_server, ch.discord_channel);
+        let joined = UserJoined { username: &db_user.username, user_id: db_user.id, channel: &ch.discord_channel, server: &ch.discord_server };
+        subscriptions::emit(&state, &conn, EventType::UserJoined, Some(ch.id), &joined);
     } else {
         info!("[*][join-channel] user='{}' visited channel with old membership '{}#{}'", session.username, ch.discord_server, ch.discord_channel);
     }
     // update session active
-    if let Some(mut entry) = state.sessions.get_mut(&s.id.to_string()) {
+    if let Some(mut entry) = state.sessions.get_mut(&c.id()) {
         entry.active_channel = ch.discord_channel.clone();
         entry.active_server = ch.discord_server.clone();
     }
     // Join rooms for typing and server broadcasts
     let room_channel = format!("{}#{}", ch.discord_server, ch.discord_channel);
     let rooms: Vec<String> = vec![room_channel, ch.discord_server.clone()];
-    s.join(rooms);
-    let resp = JoinChannelResponse{ message: "".into(), success: true, server: ch.discord_server.clone(), channel: ch.discord_channel.clone(), unred_msg_id: member.and_then(|m| m.highest_requested_msg_id), channel_id: ch.id, server_id: ch.server_id };
-    let _ = s.emit("joinChannelResponse", &resp);
-    Ok(())
+    c.join(rooms);
+    Ok(JoinChannelResponse{ server: ch.discord_server.clone(), channel: ch.discord_channel.clone(), unred_msg_id: member.and_then(|m| m.last_red_msg_id()), channel_id: ch.id, server_id: ch.server_id })
+}
+
+fn wrong_channel(server: &str, channel: &str) -> EventError {
+    EventError::new(ErrorCode::WrongChannel, format!("you did not join '{server}#{channel}'"))
 }
 
-fn on_typing_info(s: SocketRef, state: AppState, info: TypingInfo) {
-    let Some(mut user) = state.sessions.get_mut(&s.id.to_string()) else { return; };
-    if user.active_channel != info.channel || user.active_server != info.server { return; }
-    if use_accounts(&state) && !user.logged_in { return; }
+async fn on_typing_info(c: Client, state: AppState, info: TypingInfo) -> Result<(), EventError> {
+    let Some(mut user) = state.sessions.get_mut(&c.id()) else { return Err(EventError::not_logged_in()); };
+    if user.active_channel != info.channel || user.active_server != info.server { return Err(wrong_channel(&info.server, &info.channel)); }
+    if use_accounts(&state) && !user.logged_in { return Err(EventError::not_logged_in()); }
     user.is_typing = info.is_typing;
     if info.is_typing { user.last_typing_ms = now_ms(); }
     drop(user);
@@ -229,93 +317,211 @@ fn on_typing_info(s: SocketRef, state: AppState, info: TypingInfo) {
         .map(|u| u.value().username.clone())
         .collect();
     let typing_state = TypingState{ names, channel: info.channel };
-    let _ = s.broadcast().emit("typingUsers", &typing_state);
-}
-
-fn add_message(s: &SocketRef, state: &AppState, mapping: &ChannelMapping, mut msg: IrcMessage) {
-    msg.token = Some("xxx".into());
-    state.history.log_message(&mapping.discord_server, &mapping.discord_channel, msg.clone());
-    let _ = s.broadcast().emit("message", &msg);
+    c.broadcast(&state, "typingUsers", &typing_state).await;
+    Ok(())
 }
 
-async fn on_message(s: SocketRef, state: AppState, mut msg: IrcMessage) {
+async fn on_message(c: Client, state: AppState, mut msg: IrcMessage) -> Result<MessageReply, EventError> {
+    let _busy = state.inflight.start();
     if use_accounts(&state) && !check_auth(&state, &msg) {
         warn!("[!] WARNING invalid token");
-        return;
+        return Err(rejected("invalid_token", ErrorCode::InvalidToken, "invalid session token"));
+    }
+    let Some(user) = state.sessions.get(&c.id()) else { return Err(rejected("no_session", ErrorCode::NotLoggedIn, "no session")); };
+    if user.active_channel != msg.channel || user.active_server != msg.server {
+        warn!("[!] user '{}' tried to send in '{}#{}' but is in '{}#{}'", user.username, msg.server, msg.channel, user.active_server, user.active_channel);
+        drop(user);
+        metrics::rejected("wrong_channel");
+        return Err(wrong_channel(&msg.server, &msg.channel));
     }
-    let Some(user) = state.sessions.get(&s.id.to_string()) else { return; };
-    if user.active_channel != msg.channel { warn!("[!] user '{}' tried to send in channel '{}' but is in '{}'", user.username, msg.channel, user.active_channel); return; }
-    if user.active_server != msg.server { warn!("[!] user '{}' tried to send in server '{}' but is in '{}'", user.username, msg.server, user.active_server); return; }
+    let session = user.clone();
     drop(user);
-    let Some(mapping) = get_mapping_by_discord(&state, &msg.server, &msg.channel) else { warn!("[!] invalid discord mapping '{}#{}'", msg.server, msg.channel); return; };
-
-    // rate limit
-    static RL: Lazy<parking_lot::Mutex<RateLimiter>> = Lazy::new(|| parking_lot::Mutex::new(RateLimiter::new()));
-    if RL.lock().is_ratelimited(&msg.message) {
-        let alert = AlertMessage{ success: false, message: "Ratelimited message sending".into(), expire: 8000 };
-        let _ = s.emit("alert", &alert);
-        return;
+    if let Some(line) = msg.message.strip_prefix('/') {
+        // a message starting with // is sent with one slash
+        if !line.starts_with('/') {
+            let Some(mapping) = messages::find_mapping(&state, &msg.server, &msg.channel) else {
+                return Err(rejected("unknown_channel", ErrorCode::ChannelNotFound, "channel is not connected to irc"));
+            };
+            let line = line.to_string();
+            let status = commands::execute(&c, &state, session, mapping, &line, msg.nonce.take()).await?;
+            return Ok(MessageReply::Command(status));
+        }
+        msg.message.remove(0);
     }
-    // private channel check: if private and no db user
-    let is_private = mapping.is_private;
-    if is_private {
-        let user = state.sessions.get(&s.id.to_string()).unwrap();
-        if user.db_user.is_none() {
-            let alert = AlertMessage{ success: false, message: "This is a private channel please login to your account".into(), expire: 8000 };
-            let _ = s.emit("alert", &alert);
-            return;
+    messages::check_muted(&state, &session.username)?;
+    if let Some(name) = session.display_name { msg.from = name; }
+    let (_, status) = messages::send(&state, Some(&c), session.db_user.as_ref(), msg).await?;
+    Ok(MessageReply::Sent(status))
+}
+
+/// Authors can edit and delete their messages. Admins can delete any message.
+fn check_can_modify(c: &Client, state: &AppState, server: &str, channel: &str, id: i64, is_delete: bool) -> Result<IrcMessage, EventError> {
+    let Some(db_user) = state.sessions.get(&c.id()).filter(|u| u.logged_in).and_then(|u| u.db_user.clone()) else {
+        return Err(EventError::not_logged_in());
+    };
+    let Some(msg) = state.history.find_message(server, channel, id) else {
+        return Err(EventError::new(ErrorCode::MessageNotFound, "message not found"));
+    };
+    if msg.deleted_at.is_some() { return Err(EventError::new(ErrorCode::MessageNotFound, "message was deleted")); }
+    if msg.user_id == Some(db_user.id) { return Ok(msg); }
+    if is_delete {
+        let is_admin = { let conn = state.db.lock(); user_model::find(&conn, db_user.id).is_some_and(|u| u.admin() && !u.blocked()) };
+        if is_admin { return Ok(msg); }
+    }
+    Err(EventError::new(ErrorCode::PermissionDenied, "you can only change your own messages"))
+}
+
+fn format_irc_correction(format: &str, nick: &str, message: &str) -> String {
+    format.replace("{nick}", nick).replace("{message}", message)
+}
+
+fn channel_not_found() -> EventError {
+    EventError::new(ErrorCode::ChannelNotFound, "channel not found")
+}
+
+async fn on_edit_message(c: Client, state: AppState, req: EditMessageRequest) -> Result<IrcMessage, EventError> {
+    let _busy = state.inflight.start();
+    if req.message.is_empty() { return Err(EventError::new(ErrorCode::InvalidInput, "message can not be empty")); }
+    let Some(mapping) = messages::find_mapping(&state, &req.server, &req.channel) else { return Err(channel_not_found()); };
+    check_can_modify(&c, &state, &req.server, &req.channel, req.id, false)?;
+    messages::check_text(&req.message).map_err(|e| EventError::new(ErrorCode::InvalidInput, e))?;
+    messages::check_rate_limit(&state, &req.message)?;
+    let Some(msg) = state.history.edit_message(&req.server, &req.channel, req.id, &req.message) else {
+        return Err(EventError::new(ErrorCode::MessageNotFound, "message not found"));
+    };
+    info!("[*][{}][{}] '{}' edited msgid={}", msg.server, msg.channel, msg.from, msg.id);
+    search::update_message(&state.db.lock(), &msg);
+
+    let line = format_irc_correction(&state.config.lock().irc_edit_format, &msg.from, &msg.message);
+    if !line.is_empty() {
+        irc_bridge::send_irc(&state, &mapping.irc_server_name, &mapping.irc_channel, &line).await;
+    }
+    c.emit("messageUpdate", &msg);
+    c.broadcast(&state, "messageUpdate", &msg).await;
+    Ok(msg)
+}
+
+async fn on_delete_message(c: Client, state: AppState, req: MessageRef) -> Result<(), EventError> {
+    let _busy = state.inflight.start();
+    let Some(mapping) = messages::find_mapping(&state, &req.server, &req.channel) else { return Err(channel_not_found()); };
+    let original = check_can_modify(&c, &state, &req.server, &req.channel, req.id, true)?;
+    let Some(tombstone) = state.history.delete_message(&req.server, &req.channel, req.id) else {
+        return Err(EventError::new(ErrorCode::MessageNotFound, "message not found"));
+    };
+    info!("[*][{}][{}] msgid={} by '{}' was deleted", req.server, req.channel, req.id, original.from);
+    {
+        let conn = state.db.lock();
+        search::update_message(&conn, &tombstone);
+        // admins deleting someone else's message
+        let moderator = state.sessions.get(&c.id()).and_then(|u| u.db_user.clone()).filter(|u| original.user_id != Some(u.id));
+        if let Some(moderator) = moderator {
+            let action = ModerationAction {
+                action: "message_deleted",
+                moderator: &moderator.username,
+                server: Some(&req.server),
+                channel: Some(&req.channel),
+                message_id: Some(req.id),
+                author: Some(&original.from),
+                ..Default::default()
+            };
+            subscriptions::emit(&state, &conn, EventType::Moderation, Some(mapping.id), &action);
         }
     }
-    let new_id = state.history.next_id();
-    if msg.id != new_id { warn!("[!] The client expected to get msgid={} but got msgid={}", msg.id, new_id); }
-    msg.id = new_id;
-    let message_str = format!("**<{}>** {}", msg.from, msg.message);
-    info!("[*][{}][{}] {}", msg.server, msg.channel, message_str);
-    // send to irc
-    if !irc_bridge::send_irc(&state, &mapping.irc_server_name, &mapping.irc_channel, &message_str).await {
-        return;
+
+    let line = format_irc_correction(&state.config.lock().irc_delete_format, &original.from, &original.message);
+    if !line.is_empty() {
+        irc_bridge::send_irc(&state, &mapping.irc_server_name, &mapping.irc_channel, &line).await;
     }
-    add_message(&s, &state, &mapping, msg);
+    c.emit("messageDelete", &req);
+    c.broadcast(&state, "messageDelete", &req).await;
+    Ok(())
+}
+
+async fn on_reaction(c: Client, state: AppState, req: ReactionRequest, add: bool) -> Result<(), EventError> {
+    let _busy = state.inflight.start();
+    let Some(db_user) = state.sessions.get(&c.id()).filter(|u| u.logged_in).and_then(|u| u.db_user.clone()) else {
+        return Err(EventError::not_logged_in());
+    };
+    reactions::set_reaction(&state, &db_user, &req.server, &req.channel, req.id, &req.emoji, add).await?;
+    Ok(())
 }
 
-fn on_webhooks_request(s: SocketRef, state: AppState, server_id: i64) {
+/// Admins see all webhooks of the server, channel owners the ones of their channels
+fn on_webhooks_request(c: Client, state: AppState, server_id: i64) -> Result<Vec<WebhookObject>, EventError> {
+    let Some(db_user) = state.sessions.get(&c.id()).filter(|u| u.logged_in).and_then(|u| u.db_user.clone()) else {
+        return Err(EventError::not_logged_in());
+    };
     let conn = state.db.lock();
-    let Some(srv) = server_model::find(&conn, server_id) else { let _= s.emit("webhooks", &Vec::<WebhookObject>::new()); return; };
-    let webhooks = webhook_model::where_eq(&conn, "server_id", srv.id).into_iter().map(|w| WebhookObject{
-        id: w.id, token: w.token, r#type: 0, channel_id: w.channel_id, name: w.name, avatar: None, application_id: None
-    }).collect::<Vec<_>>();
-    let _ = s.emit("webhooks", &webhooks);
+    let Some(user) = user_model::find(&conn, db_user.id) else { return Err(EventError::not_logged_in()); };
+    if user.blocked() { return Err(EventError::new(ErrorCode::AccountBlocked, "this account is blocked")); }
+    let Some(srv) = server_model::find(&conn, server_id) else { return Err(EventError::new(ErrorCode::ServerNotFound, "server not found")); };
+    let owned: Vec<i64> = channel_model::where_eq(&conn, "server_id", &srv.id.to_string())
+        .into_iter()
+        .filter(|ch| ch.owner_id == user.id)
+        .map(|ch| ch.id)
+        .collect();
+    if !user.admin() && owned.is_empty() {
+        return Err(EventError::new(ErrorCode::PermissionDenied, "only admins and channel owners can see webhooks"));
+    }
+    let webhooks = webhook_model::where_eq(&conn, "server_id", srv.id).into_iter()
+        .filter(|w| user.admin() || owned.contains(&w.channel_id))
+        .map(|w| WebhookObject{
+            id: w.id, token: w.token, r#type: 0, channel_id: w.channel_id, name: w.name, avatar: None, application_id: None
+        })
+        .collect::<Vec<_>>();
+    Ok(webhooks)
 }
 
-fn on_new_webhook_request(s: SocketRef, state: AppState, obj: WebhookObject) {
+fn on_new_webhook_request(c: Client, state: AppState, obj: WebhookObject) -> Result<WebhookObject, EventError> {
     let conn = state.db.lock();
-    let Some(channel) = channel_model::find(&conn, obj.channel_id) else { warn!("[!] failed to create webhook. Channel not found"); return; };
-    let Some(server) = server_model::find(&conn, channel.server_id) else { warn!("[!] failed to create webhook. Server not found"); return; };
-    let Some(session) = state.sessions.get(&s.id.to_string()) else { warn!("[!] failed to create webhook. Session user not found!"); return; };
-    let Some(db_user) = session.db_user.clone() else { warn!("[!] failed to create webhook. User is not logged in!"); return; };
-    let Some(user) = user_model::find(&conn, db_user.id) else { warn!("[!] failed to create webhook. User not found in database!"); return; };
-    if user.is_blocked == 1 { warn!("[!] failed to create webhook. User is blocked!"); return; }
-    if user.is_admin != 1 { warn!("[!] failed to create webhook. User is missing permissions!"); return; }
-    let token = util::random_int(100000000000000, 3592180204621707).to_string();
-    let _ = webhook_model::insert(&conn, &obj.name, &token, server.id, channel.id, &session.username, &session.username, user.id);
+    let Some(channel) = channel_model::find(&conn, obj.channel_id) else { return Err(channel_not_found()); };
+    let Some(server) = server_model::find(&conn, channel.server_id) else { return Err(EventError::new(ErrorCode::ServerNotFound, "server not found")); };
+    let Some(session) = state.sessions.get(&c.id()).map(|u| u.clone()) else { return Err(EventError::not_logged_in()); };
+    let Some(db_user) = session.db_user else { return Err(EventError::not_logged_in()); };
+    let Some(user) = user_model::find(&conn, db_user.id) else { return Err(EventError::not_logged_in()); };
+    if user.is_blocked == 1 { return Err(EventError::new(ErrorCode::AccountBlocked, "this account is blocked")); }
+    if user.is_admin != 1 { return Err(EventError::new(ErrorCode::PermissionDenied, "only admins can create webhooks")); }
+    let token = util::generate_webhook_token();
+    let id = webhook_model::insert(&conn, &obj.name, &token, server.id, channel.id, &session.username, &session.username, user.id)
+        .map_err(|e| EventError::new(ErrorCode::Internal, format!("failed to create webhook: {e}")))?;
     info!("[*] created new webhook! server='{}' channel='{}' name='{}'", server.name, channel.name, obj.name);
+    Ok(WebhookObject{ id, token, r#type: 0, channel_id: channel.id, name: obj.name, avatar: None, application_id: None })
 }
 
-fn on_connected_server_list_request(s: SocketRef, state: AppState) {
+fn on_connected_server_list_request(c: Client, state: AppState) -> Result<Vec<ServerInfo>, EventError> {
     let conn = state.db.lock();
-    let Some(session) = state.sessions.get(&s.id.to_string()) else { return; };
-    let Some(db_user) = session.db_user.clone() else { return; };
-    let Some(user) = user_model::find(&conn, db_user.id) else { return; };
-    if user.is_blocked == 1 { return; }
+    let Some(db_user) = state.sessions.get(&c.id()).and_then(|u| u.db_user.clone()) else { return Err(EventError::not_logged_in()); };
+    let Some(user) = user_model::find(&conn, db_user.id) else { return Err(EventError::not_logged_in()); };
+    if user.is_blocked == 1 { return Err(EventError::new(ErrorCode::AccountBlocked, "this account is blocked")); }
     let servers = server_model::all(&conn);
     let mut out = Vec::new();
     for srv in servers {
         // channels
         let channels = channel_model::where_eq(&conn, "server_id", &srv.id.to_string())
             .into_iter()
-            .map(|ch| ChannelInfo{ id: ch.id, server_id: ch.server_id, name: ch.name, description: ch.description })
+            .map(|ch| {
+                let counts = unread::unread_info(&state, &conn, &user, &ch);
+                ChannelInfo{ id: ch.id, server_id: ch.server_id, name: ch.name, description: ch.description, unread_count: Some(counts.unread_count), mention_count: Some(counts.mention_count) }
+            })
             .collect::<Vec<_>>();
         out.push(ServerInfo{ id: srv.id, name: srv.name, icon_url: srv.icon_url, banner_url: srv.banner_url, channels });
     }
-    let _ = s.emit("connectedServerListResponse", &out);
+    Ok(out)
+}
+
+fn on_mark_read(c: Client, state: AppState, req: MarkReadRequest, unread: bool) -> Result<UnreadInfo, EventError> {
+    let Some(db_user) = state.sessions.get(&c.id()).filter(|u| u.logged_in).and_then(|u| u.db_user.clone()) else {
+        return Err(EventError::not_logged_in());
+    };
+    let conn = state.db.lock();
+    let Some(ch) = channel_model::find_by_discord(&conn, &req.server, &req.channel) else {
+        return Err(EventError::new(ErrorCode::ChannelNotFound, "channel is not in database"));
+    };
+    if unread {
+        unread::mark_unread(&state, &conn, &db_user, &ch, req.message_id)?;
+    } else {
+        unread::mark_read(&state, &conn, &db_user, &ch, req.message_id)?;
+    }
+    unread::notify(&state, &conn, &ch, Some(db_user.id));
+    Ok(unread::unread_info(&state, &conn, &db_user, &ch))
 }
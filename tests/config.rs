@@ -0,0 +1,93 @@
+use std::{env, fs, path::PathBuf};
+
+use irc_websockets::{
+    cli::CliArgs,
+    config::{Config, ConfigError},
+    testing::TestEnv,
+};
+use parking_lot::Mutex;
+
+// env vars are shared by all tests of this binary
+static ENV: Mutex<()> = Mutex::new(());
+
+const VARS: [&str; 6] = ["CONFIG_FILE", "ADMIN_TOKEN", "BACKLOG_SIZE", "DB_PATH", "RATE_LIMIT_BURST", "IRC_SERVER"];
+
+const FILE: &str = r#"
+admin_token = "from-file"
+accounts_password = "secret"
+backlog_size = 10
+
+[database]
+path = "file.db"
+
+[rate_limit]
+burst = 2
+
+[[irc.networks]]
+name = "quakenet"
+server = "irc.quakenet.org"
+"#;
+
+fn write_config(env: &TestEnv, content: &str) -> PathBuf {
+    let path = env.dir.join("config.toml");
+    fs::write(&path, content).unwrap();
+    path
+}
+
+fn cli(config: PathBuf, db: Option<&str>) -> CliArgs {
+    CliArgs { config_path: Some(config), db_path: db.map(PathBuf::from), ..CliArgs::default() }
+}
+
+#[test]
+fn file_env_and_flags_are_layered() {
+    let _guard = ENV.lock();
+    let test = TestEnv::new().unwrap();
+    let path = write_config(&test, FILE);
+    for var in VARS { env::remove_var(var); }
+
+    let config = Config::load(&cli(path.clone(), None)).unwrap();
+    assert_eq!(config.admin_token, "from-file");
+    assert_eq!(config.backlog_size, 10);
+    assert_eq!(config.db_path, PathBuf::from("file.db"));
+    assert_eq!(config.rate_limit.burst, 2);
+    // not in the file
+    assert_eq!(config.shutdown_timeout_secs, Config::default().shutdown_timeout_secs);
+
+    env::set_var("BACKLOG_SIZE", "20");
+    env::set_var("DB_PATH", "env.db");
+    let config = Config::load(&cli(path.clone(), None)).unwrap();
+    assert_eq!(config.backlog_size, 20);
+    assert_eq!(config.db_path, PathBuf::from("env.db"));
+    assert_eq!(config.admin_token, "from-file");
+
+    let config = Config::load(&cli(path, Some("flag.db"))).unwrap();
+    assert_eq!(config.db_path, PathBuf::from("flag.db"));
+    for var in VARS { env::remove_var(var); }
+}
+
+#[test]
+fn all_problems_are_reported() {
+    let _guard = ENV.lock();
+    let test = TestEnv::new().unwrap();
+    let path = write_config(&test, "accounts_password = \"secret\"\nbacklog_size = 0\n");
+    for var in VARS { env::remove_var(var); }
+    env::set_var("RATE_LIMIT_BURST", "many");
+
+    let errors = Config::load(&cli(path, None)).unwrap_err().0;
+    assert!(errors.iter().any(|e| matches!(e, ConfigError::InvalidValue { name, .. } if name == "RATE_LIMIT_BURST")));
+    assert!(errors.iter().any(|e| matches!(e, ConfigError::Missing("ADMIN_TOKEN"))));
+    assert!(errors.iter().any(|e| matches!(e, ConfigError::Missing("IRC_SERVER"))));
+    assert!(errors.iter().any(|e| matches!(e, ConfigError::Invalid(m) if m.contains("backlog_size"))));
+    for var in VARS { env::remove_var(var); }
+}
+
+#[test]
+fn unknown_keys_are_rejected() {
+    let _guard = ENV.lock();
+    let test = TestEnv::new().unwrap();
+    let path = write_config(&test, &format!("admin_tokn = \"typo\"\n{FILE}"));
+    for var in VARS { env::remove_var(var); }
+
+    let errors = Config::load(&cli(path, None)).unwrap_err().0;
+    assert!(errors.iter().any(|e| matches!(e, ConfigError::Parse { message, .. } if message.contains("admin_tokn"))));
+}
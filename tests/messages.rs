@@ -0,0 +1,85 @@
+use irc_websockets::{
+    ack::ErrorCode,
+    messages,
+    models::{channel, channel_member, message_search, user},
+    testing::TestEnv,
+    types::{DeliveryStatus, IrcMessage},
+};
+
+fn env() -> TestEnv {
+    // the rate limiter is shared by all tests of this binary
+    TestEnv::with_config(|c| c.rate_limit.min_interval_ms = 0).unwrap().seeded().unwrap()
+}
+
+fn message(server: &str, channel: &str, text: &str) -> IrcMessage {
+    IrcMessage {
+        from: "ChillerDragon".into(),
+        message: text.into(),
+        server: server.into(),
+        channel: channel.into(),
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn send_stores_and_indexes_the_message() {
+    let env = env();
+    let author = user::find_by_username(&env.state.db.lock(), "ChillerDragon").unwrap();
+    let mut msg = message("ddnet", "developer", "hello irc");
+    // the server picks id, date and author
+    msg.id = 999;
+    msg.user_id = Some(12345);
+    msg.nonce = Some("n1".into());
+
+    let (stored, status) = messages::send(&env.state, None, Some(&author), msg).await.unwrap();
+    assert_eq!(stored.id, env.state.history.latest_id());
+    assert_eq!(stored.user_id, Some(author.id));
+    assert!(!stored.date.is_empty());
+    assert_eq!(status.status, DeliveryStatus::Sent);
+    assert_eq!(status.nonce.as_deref(), Some("n1"));
+    assert_eq!(status.id, stored.id);
+
+    let found = env.state.history.find_message("ddnet", "developer", stored.id).unwrap();
+    assert_eq!(found.message, "hello irc");
+    assert!(message_search::exists(&env.state.db.lock(), stored.id));
+
+    let (next, _) = messages::send(&env.state, None, Some(&author), message("ddnet", "developer", "again")).await.unwrap();
+    assert!(next.id > stored.id);
+}
+
+#[tokio::test]
+async fn invalid_messages_are_rejected() {
+    let env = env();
+    let send = |msg| messages::send(&env.state, None, None, msg);
+
+    assert_eq!(send(message("ddnet", "developer", "")).await.unwrap_err().code, ErrorCode::InvalidInput);
+    assert_eq!(send(message("ddnet", "developer", "two\nlines")).await.unwrap_err().code, ErrorCode::InvalidInput);
+    assert_eq!(send(message("ddnet", "developer", &"x".repeat(messages::MAX_MESSAGE_LEN + 1))).await.unwrap_err().code, ErrorCode::InvalidInput);
+    assert_eq!(send(message("ddnet", "nope", "hi")).await.unwrap_err().code, ErrorCode::ChannelNotFound);
+    let mut reply = message("ddnet", "developer", "hi");
+    reply.reply_to = Some(4242);
+    assert_eq!(send(reply).await.unwrap_err().code, ErrorCode::MessageNotFound);
+    let mut nonce = message("ddnet", "developer", "hi");
+    nonce.nonce = Some("n".repeat(messages::MAX_NONCE_LEN + 1));
+    assert_eq!(send(nonce).await.unwrap_err().code, ErrorCode::InvalidInput);
+    assert_eq!(env.state.history.message_count(), 0);
+}
+
+#[tokio::test]
+async fn private_channels_only_accept_members() {
+    let env = env();
+    let (member, stranger) = {
+        let conn = env.state.db.lock();
+        let mut ch = channel::find_by_discord(&conn, "ddnet", "developer").unwrap();
+        ch.is_private = 1;
+        channel::update(&conn, &ch).unwrap();
+        let stranger = user::insert(&conn, "stranger", "password", "127.0.0.1").unwrap();
+        let member = channel_member::for_channel(&conn, ch.id).first().map(|m| m.user_id).unwrap();
+        (user::find(&conn, member).unwrap(), user::find(&conn, stranger).unwrap())
+    };
+    let send = |author| messages::send(&env.state, None, author, message("ddnet", "developer", "secret"));
+
+    assert_eq!(send(None).await.unwrap_err().code, ErrorCode::PrivateChannel);
+    assert_eq!(send(Some(&stranger)).await.unwrap_err().code, ErrorCode::PrivateChannel);
+    send(Some(&member)).await.unwrap();
+}
@@ -0,0 +1,66 @@
+use irc_websockets::{db, state::AppState, testing::TestEnv};
+
+fn tables(conn: &rusqlite::Connection) -> Vec<String> {
+    let mut st = conn.prepare("SELECT name FROM sqlite_master WHERE type IN ('table', 'view') ORDER BY name").unwrap();
+    st.query_map([], |row| row.get(0)).unwrap().map(Result::unwrap).collect()
+}
+
+#[test]
+fn migrate_applies_everything_once() {
+    let test = TestEnv::new().unwrap();
+    let conn = db::open(&test.dir.join("fresh.db")).unwrap();
+    let all = db::migrations(&TestEnv::migrations_dir()).unwrap();
+    assert!(!all.is_empty());
+
+    let applied = db::migrate(&conn, &TestEnv::migrations_dir(), true).unwrap();
+    assert_eq!(applied.len(), all.len());
+    assert_eq!(db::get_db_version(&conn).unwrap(), all.last().unwrap().version);
+    let names = tables(&conn);
+    for table in ["users", "channels", "channel_members", "reactions", "subscriptions", "message_search"] {
+        assert!(names.iter().any(|n| n == table), "{table} is missing");
+    }
+
+    assert!(db::migrate(&conn, &TestEnv::migrations_dir(), true).unwrap().is_empty());
+}
+
+#[test]
+fn unsafe_migrations_need_force() {
+    let test = TestEnv::new().unwrap();
+    let conn = db::open(&test.dir.join("fresh.db")).unwrap();
+    let all = db::migrations(&TestEnv::migrations_dir()).unwrap();
+    let Some(index) = all.iter().position(|m| db::is_unsafe(&std::fs::read_to_string(&m.path).unwrap())) else { return; };
+
+    for migration in &all[..index] {
+        db::apply_migration(&conn, migration, false).unwrap();
+    }
+    let version = db::get_db_version(&conn).unwrap();
+    assert!(db::apply_migration(&conn, &all[index], false).is_err());
+    assert_eq!(db::get_db_version(&conn).unwrap(), version);
+    db::apply_migration(&conn, &all[index], true).unwrap();
+}
+
+#[test]
+fn every_migration_rolls_back() {
+    let test = TestEnv::new().unwrap();
+    let conn = &test.state.db.lock();
+    let all = db::migrations(&TestEnv::migrations_dir()).unwrap();
+    let before = tables(conn);
+
+    for (i, migration) in all.iter().enumerate().rev() {
+        let previous = if i == 0 { 0 } else { all[i - 1].version };
+        db::rollback_migration(conn, migration, previous, true).unwrap();
+        assert_eq!(db::get_db_version(conn).unwrap(), previous);
+    }
+    db::migrate(conn, &TestEnv::migrations_dir(), true).unwrap();
+    assert_eq!(tables(conn), before);
+}
+
+#[test]
+fn the_state_refuses_an_unmigrated_database() {
+    let test = TestEnv::new().unwrap();
+    let mut config = test.state.config.lock().clone();
+    config.db_path = test.dir.join("empty.db");
+
+    let err = AppState::new(&config).err().expect("the database has no tables");
+    assert!(err.to_string().contains("migrate"));
+}
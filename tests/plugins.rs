@@ -0,0 +1,53 @@
+use std::sync::Arc;
+
+use futures::future::BoxFuture;
+use irc_websockets::{
+    config::PluginConfig,
+    messages,
+    plugins::{MessageHandler, PluginContext, Verdict},
+    testing::TestEnv,
+    types::IrcMessage,
+};
+use parking_lot::Mutex;
+
+/// Rewrites every message to whatever the test put in
+struct Rewriter(Arc<Mutex<String>>);
+
+impl MessageHandler for Rewriter {
+    fn name(&self) -> &'static str {
+        "rewriter"
+    }
+
+    fn filter<'a>(&'a self, _ctx: &'a PluginContext, _msg: &'a IrcMessage) -> BoxFuture<'a, anyhow::Result<Verdict>> {
+        let text = self.0.lock().clone();
+        Box::pin(async move { Ok(Verdict::Rewrite(text)) })
+    }
+}
+
+fn message(text: &str) -> IrcMessage {
+    IrcMessage { from: "ChillerDragon".into(), message: text.into(), server: "ddnet".into(), channel: "developer".into(), ..Default::default() }
+}
+
+#[tokio::test]
+async fn invalid_rewrites_are_ignored() {
+    let env = TestEnv::with_config(|c| {
+        // the rate limiter is shared by all tests of this binary
+        c.rate_limit.min_interval_ms = 0;
+        c.plugins = vec![PluginConfig { name: "rewriter".into(), channels: vec![], account: None, options: Default::default() }];
+    }).unwrap().seeded().unwrap();
+    let rewrite = Arc::new(Mutex::new(String::new()));
+    env.state.plugins.register(Rewriter(rewrite.clone()));
+
+    let send = async |rewrite_to: String| {
+        *rewrite.lock() = rewrite_to;
+        let (stored, _) = messages::send(&env.state, None, None, message("original")).await.unwrap();
+        env.state.history.find_message("ddnet", "developer", stored.id).unwrap().message
+    };
+
+    assert_eq!(send("rewritten".into()).await, "rewritten");
+    assert_eq!(send("a\nb".into()).await, "original");
+    assert_eq!(send("a\rb".into()).await, "original");
+    assert_eq!(send(String::new()).await, "original");
+    assert_eq!(send("x".repeat(messages::MAX_MESSAGE_LEN + 1)).await, "original");
+    assert_eq!(send("x".repeat(messages::MAX_MESSAGE_LEN)).await, "x".repeat(messages::MAX_MESSAGE_LEN));
+}
@@ -0,0 +1,37 @@
+use axum::{http::{header, StatusCode}, response::IntoResponse};
+use irc_websockets::{
+    ack::{ErrorCode, EventError},
+    api::ApiError,
+    config::RateLimitConfig,
+    messages,
+    testing::TestEnv,
+    types::IrcMessage,
+};
+
+#[tokio::test]
+async fn rate_limited_messages_say_when_to_retry() {
+    let env = TestEnv::with_config(|c| c.rate_limit = RateLimitConfig { burst: 1, window_ms: 60_000, min_interval_ms: 60_000 }).unwrap().seeded().unwrap();
+    let msg = IrcMessage { from: "bot".into(), message: "spam".into(), server: "ddnet".into(), channel: "developer".into(), ..Default::default() };
+
+    let mut err = None;
+    for _ in 0..5 {
+        if let Err(e) = messages::send(&env.state, None, None, msg.clone()).await {
+            err = Some(e);
+            break;
+        }
+    }
+    let err = err.expect("the burst should be exceeded");
+    assert_eq!(err.code, ErrorCode::RateLimited);
+    let secs = err.retry_after.expect("the wait is known");
+    assert!((1..=60).contains(&secs));
+
+    let res = ApiError::from(err).into_response();
+    assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(res.headers()[header::RETRY_AFTER], secs.to_string().as_str());
+}
+
+#[test]
+fn irc_failures_are_a_bad_gateway() {
+    let res = ApiError::from(EventError::new(ErrorCode::IrcSendFailed, "irc is down")).into_response();
+    assert_eq!(res.status(), StatusCode::BAD_GATEWAY);
+}
@@ -0,0 +1,22 @@
+use irc_websockets::reactions::is_valid_emoji;
+
+#[test]
+fn unicode_emoji_are_accepted() {
+    for emoji in [
+        "👍", "😂", "❤", "❤️", "™️", "👍🏽", "1️⃣", "#⃣", "🇩🇪", "🏴󠁧󠁢󠁳󠁣󠁴󠁿",
+        "👨‍👩‍👧", "🏳️‍🌈", "🧑🏿‍💻",
+    ] {
+        assert!(is_valid_emoji(emoji), "{emoji} should be valid");
+    }
+}
+
+#[test]
+fn everything_else_is_rejected() {
+    for emoji in [
+        "", "a", ":kek:", "ü", "中文", "١٢٣", "½", "1", "👍 ", " 👍", "👍a", "\u{200D}👍", "👍\u{200D}",
+        "\u{FE0F}", "🏽", "🇩", "🇩🇪🇩", "1\u{FE0F}", "👍\n",
+    ] {
+        assert!(!is_valid_emoji(emoji), "{emoji:?} should be invalid");
+    }
+    assert!(!is_valid_emoji(&"👍".repeat(9)), "too long");
+}
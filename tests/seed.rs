@@ -0,0 +1,82 @@
+use std::process::Command;
+
+use irc_websockets::{
+    models::{channel, server, user},
+    seed,
+    testing::TestEnv,
+};
+
+fn count(env: &TestEnv, table: &str) -> i64 {
+    env.state.db.lock().query_row(&format!("SELECT COUNT(*) FROM {table}"), [], |row| row.get(0)).unwrap()
+}
+
+fn counts(env: &TestEnv) -> Vec<i64> {
+    ["users", "servers", "channels", "channel_members", "webhooks", "friends"].iter().map(|t| count(env, t)).collect()
+}
+
+/// Real rows that happen to use the names of the seed
+fn add_production_rows(env: &TestEnv) {
+    let conn = env.state.db.lock();
+    let owner = user::insert(&conn, "ChillerDragon", "real password", "10.0.0.1").unwrap();
+    let ddnet = server::insert(&conn, "ddnet", "ddnet", "quakenet", "irc.quakenet.org", "", "10.0.0.1", owner).unwrap();
+    channel::insert(&conn, "general", "", "ddnet", "general", "ddnet", "irc.quakenet.org", "quakenet", ddnet, owner).unwrap();
+}
+
+fn db_cli(env: &TestEnv, args: &[&str]) -> bool {
+    Command::new(env!("CARGO_BIN_EXE_db-cli"))
+        .arg("--db").arg(&env.state.config.lock().db_path)
+        .args(args)
+        .output()
+        .unwrap()
+        .status
+        .success()
+}
+
+#[test]
+fn seeding_twice_gives_the_same_data() {
+    let env = TestEnv::new().unwrap().seeded().unwrap();
+    let first = counts(&env);
+    assert_eq!(first, [4, 3, 7, 1, 4, 1]);
+    assert!(seed::foreign_data(&env.state.db.lock()).unwrap().is_empty());
+
+    seed::seed(&env.state.db.lock(), "xxx", false).unwrap();
+    assert_eq!(counts(&env), first);
+}
+
+#[test]
+fn clean_only_removes_seed_rows() {
+    let env = TestEnv::new().unwrap().seeded().unwrap();
+    let stranger = user::insert(&env.state.db.lock(), "stranger", "password", "10.0.0.1").unwrap();
+    assert_eq!(seed::foreign_data(&env.state.db.lock()).unwrap(), ["1 users"]);
+
+    assert!(seed::clean(&env.state.db.lock(), false).is_err());
+    assert_eq!(counts(&env), [5, 3, 7, 1, 4, 1]);
+
+    seed::clean(&env.state.db.lock(), true).unwrap();
+    assert_eq!(counts(&env), [1, 0, 0, 0, 0, 0]);
+    assert!(user::find(&env.state.db.lock(), stranger).is_some());
+}
+
+#[test]
+fn clean_leaves_a_non_seed_database_alone() {
+    let env = TestEnv::new().unwrap();
+    add_production_rows(&env);
+    let before = counts(&env);
+
+    assert!(!db_cli(&env, &["seed", "--clean"]));
+    assert_eq!(counts(&env), before);
+    // even when forced only rows created by the seed are deleted
+    assert!(db_cli(&env, &["seed", "--clean", "--force"]));
+    assert_eq!(counts(&env), before);
+    assert!(seed::seed(&env.state.db.lock(), "xxx", true).is_err());
+    assert_eq!(counts(&env), before);
+    assert_eq!(user::find_by_username(&env.state.db.lock(), "ChillerDragon").unwrap().register_ip, "10.0.0.1");
+}
+
+#[test]
+fn seeding_refuses_a_non_seed_database() {
+    let env = TestEnv::new().unwrap();
+    add_production_rows(&env);
+    let err = seed::seed(&env.state.db.lock(), "xxx", false).unwrap_err();
+    assert!(err.to_string().contains("--force"));
+}
@@ -0,0 +1,121 @@
+use std::time::Duration;
+
+use irc_websockets::{
+    config::SubscriptionConfig,
+    models::{subscription, subscription_delivery::{self as delivery, DeliveryRow}},
+    subscriptions::{self, EventType, ModerationAction, SubscriptionInfo, DELIVERY_HEADER, EVENT_HEADER},
+    testing::{HookReceiver, TestEnv},
+};
+
+const SECRET: &str = "0123456789abcdef";
+const WAIT: Duration = Duration::from_secs(5);
+
+fn subscribe(env: &TestEnv, receiver: &HookReceiver) -> SubscriptionInfo {
+    subscriptions::create(&env.state.db.lock(), &receiver.url, SECRET, &[EventType::Moderation], None).unwrap()
+}
+
+fn emit(env: &TestEnv) {
+    let action = ModerationAction { action: "logout_all", moderator: "admin_token", ..Default::default() };
+    subscriptions::emit(&env.state, &env.state.db.lock(), EventType::Moderation, None, &action);
+}
+
+/// The log is written after the response arrived so it can lag behind the receiver
+async fn wait_for_log(env: &TestEnv, sub: &SubscriptionInfo, count: usize) -> Vec<DeliveryRow> {
+    let start = std::time::Instant::now();
+    loop {
+        let rows = delivery::for_subscription(&env.state.db.lock(), sub.id, None, 100);
+        if rows.len() >= count || start.elapsed() > WAIT { return rows; }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+}
+
+#[tokio::test]
+async fn deliveries_are_signed() {
+    let env = TestEnv::new().unwrap();
+    let receiver = HookReceiver::start().await.unwrap();
+    subscribe(&env, &receiver);
+    emit(&env);
+
+    let hooks = receiver.wait_for(1, WAIT).await;
+    assert_eq!(hooks.len(), 1);
+    let hook = &hooks[0];
+    assert!(hook.is_signed_with(SECRET));
+    assert!(!hook.is_signed_with("some other secret"));
+    assert_eq!(hook.header(EVENT_HEADER), Some("moderation"));
+    let body = hook.json();
+    assert_eq!(body["event"], "moderation");
+    assert_eq!(body["id"].as_str(), hook.header(DELIVERY_HEADER));
+    assert_eq!(body["data"]["action"], "logout_all");
+}
+
+#[tokio::test]
+async fn server_errors_are_retried_with_backoff() {
+    let env = TestEnv::new().unwrap();
+    let receiver = HookReceiver::start().await.unwrap();
+    let sub = subscribe(&env, &receiver);
+    receiver.respond_with(&[500, 503]);
+    emit(&env);
+
+    let hooks = receiver.wait_for(3, WAIT).await;
+    assert_eq!(hooks.len(), 3);
+    // retry_delay_ms is 10 in tests and doubles for every retry
+    assert!(hooks[1].received_at - hooks[0].received_at >= Duration::from_millis(10));
+    assert!(hooks[2].received_at - hooks[1].received_at >= Duration::from_millis(20));
+    assert!(hooks.iter().all(|h| h.body == hooks[0].body && h.header(DELIVERY_HEADER) == hooks[0].header(DELIVERY_HEADER)));
+
+    // newest first
+    let rows = wait_for_log(&env, &sub, 3).await;
+    let attempts: Vec<_> = rows.iter().map(|r| (r.attempt, r.status_code, r.success)).collect();
+    assert_eq!(attempts, [(3, Some(200), 1), (2, Some(503), 0), (1, Some(500), 0)]);
+    assert!(rows.iter().all(|r| Some(r.delivery_id.as_str()) == hooks[0].header(DELIVERY_HEADER)));
+    assert!(rows[1].error.as_deref().is_some_and(|e| e.contains("503")));
+}
+
+#[tokio::test]
+async fn delivery_gives_up_after_max_attempts() {
+    let env = TestEnv::with_config(|c| c.subscriptions = SubscriptionConfig { max_attempts: 3, retry_delay_ms: 10, timeout_ms: 2000 }).unwrap();
+    let receiver = HookReceiver::start().await.unwrap();
+    let sub = subscribe(&env, &receiver);
+    receiver.respond_with(&[500; 10]);
+    emit(&env);
+
+    let rows = wait_for_log(&env, &sub, 3).await;
+    // the next retry would have come after 40ms
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert_eq!(receiver.requests().len(), 3);
+    assert_eq!(rows.len(), 3);
+    assert!(rows.iter().all(|r| r.success == 0 && r.status_code == Some(500)));
+}
+
+#[tokio::test]
+async fn retries_stop_once_the_subscription_is_deleted() {
+    let env = TestEnv::with_config(|c| c.subscriptions.retry_delay_ms = 200).unwrap();
+    let receiver = HookReceiver::start().await.unwrap();
+    let sub = subscribe(&env, &receiver);
+    receiver.respond_with(&[500; 10]);
+    emit(&env);
+
+    assert_eq!(receiver.wait_for(1, WAIT).await.len(), 1);
+    assert!(subscription::delete(&env.state.db.lock(), sub.id).unwrap());
+    tokio::time::sleep(Duration::from_millis(600)).await;
+    assert_eq!(receiver.requests().len(), 1);
+}
+
+#[test]
+fn the_delivery_log_is_pruned_per_subscription() {
+    let env = TestEnv::new().unwrap();
+    let conn = env.state.db.lock();
+    let kept = subscription::insert(&conn, "http://127.0.0.1/a", SECRET, "moderation", None).unwrap();
+    let other = subscription::insert(&conn, "http://127.0.0.1/b", SECRET, "moderation", None).unwrap();
+    for attempt in 1..=5 {
+        delivery::insert(&conn, kept, "d1", "moderation", attempt, Some(500), Some("status 500"), false).unwrap();
+        delivery::insert(&conn, other, "d2", "moderation", attempt, Some(500), Some("status 500"), false).unwrap();
+    }
+
+    assert_eq!(delivery::prune(&conn, kept, 2).unwrap(), 3);
+    let rows = delivery::for_subscription(&conn, kept, None, 100);
+    assert_eq!(rows.iter().map(|r| r.attempt).collect::<Vec<_>>(), [5, 4]);
+    assert_eq!(delivery::for_subscription(&conn, other, None, 100).len(), 5);
+    // paging goes to older attempts
+    assert_eq!(delivery::for_subscription(&conn, other, Some(rows[0].id), 2).iter().map(|r| r.attempt).collect::<Vec<_>>(), [4, 3]);
+}